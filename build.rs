@@ -1,5 +1,12 @@
 use std::{env, fs, path};
 use tar;
+
+#[path = "src/simplify.rs"]
+mod simplify;
+
+//levels of detail below the full one, the same limit is in src/lod.rs
+const LOD_LEVELS: usize = 3;
+
 fn main() {
     println!("cargo:rerun-if-changed=assets/");
     println!("cargo:warning=Building Assets");
@@ -38,4 +45,138 @@ fn main() {
                 .unwrap();
         }
     }
+    generate_lods(&mut archive);
+}
+//adds name_LOD1.obj and so on to the archive for every model in assets/res that doesn't come with
+//authored ones, each level with half the triangles of the one before. stops early once the
+//simplifier can't remove anything, like on a cube where every vertex is on a seam
+fn generate_lods(archive: &mut tar::Builder<fs::File>) {
+    let Ok(entries) = fs::read_dir("assets/res") else {
+        return;
+    };
+    for entry in entries {
+        let path = entry.unwrap().path();
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if path.extension().and_then(|e| e.to_str()) != Some("obj") || stem.contains("_LOD") {
+            continue;
+        }
+        if path.with_file_name(format!("{stem}_LOD1.obj")).exists() {
+            continue;
+        }
+        //the same options the game loads models with, so the vertices come out in the same order
+        let Ok((models, materials)) = tobj::load_obj(
+            &path,
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+        ) else {
+            println!(
+                "cargo:warning=Could not load {} for its LODs",
+                path.display()
+            );
+            continue;
+        };
+        let materials = materials.unwrap_or_default();
+        let source = fs::read_to_string(&path).unwrap();
+        let mtllibs = source
+            .lines()
+            .filter(|line| line.starts_with("mtllib"))
+            .collect::<Vec<_>>();
+        let mut indices = models
+            .iter()
+            .map(|model| model.mesh.indices.clone())
+            .collect::<Vec<_>>();
+        for level in 1..=LOD_LEVELS {
+            let simplified = models
+                .iter()
+                .zip(&indices)
+                .map(|(model, indices)| {
+                    let positions = model
+                        .mesh
+                        .positions
+                        .chunks_exact(3)
+                        .map(|p| [p[0], p[1], p[2]])
+                        .collect::<Vec<_>>();
+                    simplify::simplify(&positions, indices, indices.len() / 6)
+                })
+                .collect::<Vec<_>>();
+            let count = |indices: &[Vec<u32>]| indices.iter().map(Vec::len).sum::<usize>();
+            if count(&simplified) == count(&indices) {
+                break;
+            }
+            indices = simplified;
+            let obj = write_obj(&mtllibs, &models, &materials, &indices);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(obj.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(
+                    &mut header,
+                    format!("res/{stem}_LOD{level}.obj"),
+                    obj.as_bytes(),
+                )
+                .unwrap();
+        }
+    }
+}
+//every model with only the vertices its indices use, OBJ indices count from 1 across the file
+fn write_obj(
+    mtllibs: &[&str],
+    models: &[tobj::Model],
+    materials: &[tobj::Material],
+    indices: &[Vec<u32>],
+) -> String {
+    use std::fmt::Write;
+    let mut obj = mtllibs.join("\n");
+    obj.push('\n');
+    let mut written = 0;
+    for (model, indices) in models.iter().zip(indices) {
+        let mesh = &model.mesh;
+        let mut remap = vec![None; mesh.positions.len() / 3];
+        let mut order = vec![];
+        let faces = indices
+            .iter()
+            .map(|&index| {
+                *remap[index as usize].get_or_insert_with(|| {
+                    order.push(index as usize);
+                    written + order.len()
+                })
+            })
+            .collect::<Vec<_>>();
+        writeln!(obj, "o {}", model.name).unwrap();
+        if let Some(material) = mesh.material_id.and_then(|id| materials.get(id)) {
+            writeln!(obj, "usemtl {}", material.name).unwrap();
+        }
+        for &i in &order {
+            let p = &mesh.positions[i * 3..i * 3 + 3];
+            writeln!(obj, "v {} {} {}", p[0], p[1], p[2]).unwrap();
+            if !mesh.texcoords.is_empty() {
+                let t = &mesh.texcoords[i * 2..i * 2 + 2];
+                writeln!(obj, "vt {} {}", t[0], t[1]).unwrap();
+            }
+            if !mesh.normals.is_empty() {
+                let n = &mesh.normals[i * 3..i * 3 + 3];
+                writeln!(obj, "vn {} {} {}", n[0], n[1], n[2]).unwrap();
+            }
+        }
+        for face in faces.chunks_exact(3) {
+            let corners =
+                face.iter().map(
+                    |&i| match (mesh.texcoords.is_empty(), mesh.normals.is_empty()) {
+                        (false, false) => format!("{i}/{i}/{i}"),
+                        (false, true) => format!("{i}/{i}"),
+                        (true, false) => format!("{i}//{i}"),
+                        (true, true) => format!("{i}"),
+                    },
+                );
+            writeln!(obj, "f {}", corners.collect::<Vec<_>>().join(" ")).unwrap();
+        }
+        written += order.len();
+    }
+    obj
 }
@@ -0,0 +1,90 @@
+// Eye adaptation from a log luminance histogram of the hdr target
+const BIN_COUNT: u32 = 256u;
+struct Params {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // Rates for adapting to a brighter and a darker scene
+    speed_up: f32,
+    speed_down: f32,
+    delta_time: f32,
+    key: f32,
+    pixel_count: u32,
+    _padding: u32,
+}
+struct Exposure {
+    luminance: f32,
+    exposure: f32,
+}
+@group(0)
+@binding(0)
+var hdr_image: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var<uniform> params: Params;
+
+@group(0)
+@binding(2)
+var<storage, read_write> histogram: array<atomic<u32>, BIN_COUNT>;
+
+@group(0)
+@binding(3)
+var<storage, read_write> state: Exposure;
+
+var<workgroup> local_bins: array<atomic<u32>, BIN_COUNT>;
+var<workgroup> weighted: array<u32, BIN_COUNT>;
+
+// Bin 0 holds the near black pixels, which are left out of the average
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    if luminance < 0.0001 {
+        return 0u;
+    }
+    let t = saturate((log2(luminance) - params.min_log_luminance) / params.log_luminance_range);
+    return u32(t * 254.0 + 1.0);
+}
+
+@compute
+@workgroup_size(16, 16)
+fn compute_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+    let size = textureDimensions(hdr_image);
+    if all(id.xy < size) {
+        let color = textureLoad(hdr_image, id.xy, 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+    atomicAdd(&histogram[index], atomicLoad(&local_bins[index]));
+}
+
+@compute
+@workgroup_size(256)
+fn compute_average(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    weighted[index] = count * index;
+    // Clear the histogram for the next frame
+    atomicStore(&histogram[index], 0u);
+    workgroupBarrier();
+    for (var cutoff = BIN_COUNT >> 1u; cutoff > 0u; cutoff >>= 1u) {
+        if index < cutoff {
+            weighted[index] += weighted[index + cutoff];
+        }
+        workgroupBarrier();
+    }
+    if index == 0u {
+        // count is the number of pixels in bin 0 for this invocation
+        let lit_pixels = max(f32(params.pixel_count) - f32(count), 1.0);
+        let average_bin = max(f32(weighted[0]) / lit_pixels - 1.0, 0.0);
+        let average_log = average_bin / 254.0 * params.log_luminance_range + params.min_log_luminance;
+        let target_luminance = exp2(average_log);
+        let previous = state.luminance;
+        let speed = select(params.speed_down, params.speed_up, target_luminance > previous);
+        let luminance = previous + (target_luminance - previous) * (1.0 - exp(-params.delta_time * speed));
+        state.luminance = luminance;
+        state.exposure = params.key / luminance;
+    }
+}
@@ -0,0 +1,114 @@
+// Bloom as a progressive downsample and upsample of the hdr target,
+// with the 13 tap downsample and tent upsample by Jorge Jimenez
+struct Bloom {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    // One over the number of mips, the upsample sums every mip of the chain
+    scale: f32,
+}
+struct VertexOutput{
+    @location(0) uv:vec2<f32>,
+    @builtin(position) clip_position:vec4<f32>
+}
+@vertex
+fn vs_main(@builtin(vertex_index) vi:u32)->VertexOutput{
+    var out:VertexOutput;
+    out.uv=vec2<f32>(f32((vi<<1u)&2u), f32(vi&2u),);
+    out.clip_position=vec4<f32>(out.uv*2.0-1.0,0.0,1.0);
+    out.uv.y=1.0-out.uv.y;
+    return out;
+}
+
+@group(0)
+@binding(0)
+var source:texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var source_sampler:sampler;
+
+@group(0)
+@binding(2)
+var<uniform> bloom: Bloom;
+
+fn luminance(color:vec3<f32>)->f32{
+    return dot(color,vec3(0.2126,0.7152,0.0722));
+}
+// Keeps only what is brighter than the threshold, easing in over the knee
+fn soft_threshold(color:vec3<f32>)->vec3<f32>{
+    let brightness=max(color.r,max(color.g,color.b));
+    var soft=clamp(brightness-bloom.threshold+bloom.knee,0.0,2.0*bloom.knee);
+    soft=soft*soft/(4.0*bloom.knee+0.0001);
+    let contribution=max(soft,brightness-bloom.threshold)/max(brightness,0.0001);
+    return color*contribution;
+}
+// Weights a group of samples down by its brightness so single bright pixels don't flicker
+fn karis_average(a:vec3<f32>,b:vec3<f32>,c:vec3<f32>,d:vec3<f32>)->vec3<f32>{
+    let average=(a+b+c+d)*0.25;
+    return average/(1.0+luminance(average));
+}
+struct Taps{
+    a:vec3<f32>, b:vec3<f32>, c:vec3<f32>,
+    d:vec3<f32>, e:vec3<f32>, f:vec3<f32>,
+    g:vec3<f32>, h:vec3<f32>, i:vec3<f32>,
+    j:vec3<f32>, k:vec3<f32>, l:vec3<f32>, m:vec3<f32>,
+}
+fn sample_taps(uv:vec2<f32>)->Taps{
+    let texel=1.0/vec2<f32>(textureDimensions(source));
+    var taps:Taps;
+    taps.a=textureSample(source,source_sampler,uv+texel*vec2(-2.0,-2.0)).rgb;
+    taps.b=textureSample(source,source_sampler,uv+texel*vec2(0.0,-2.0)).rgb;
+    taps.c=textureSample(source,source_sampler,uv+texel*vec2(2.0,-2.0)).rgb;
+    taps.d=textureSample(source,source_sampler,uv+texel*vec2(-2.0,0.0)).rgb;
+    taps.e=textureSample(source,source_sampler,uv).rgb;
+    taps.f=textureSample(source,source_sampler,uv+texel*vec2(2.0,0.0)).rgb;
+    taps.g=textureSample(source,source_sampler,uv+texel*vec2(-2.0,2.0)).rgb;
+    taps.h=textureSample(source,source_sampler,uv+texel*vec2(0.0,2.0)).rgb;
+    taps.i=textureSample(source,source_sampler,uv+texel*vec2(2.0,2.0)).rgb;
+    taps.j=textureSample(source,source_sampler,uv+texel*vec2(-1.0,-1.0)).rgb;
+    taps.k=textureSample(source,source_sampler,uv+texel*vec2(1.0,-1.0)).rgb;
+    taps.l=textureSample(source,source_sampler,uv+texel*vec2(-1.0,1.0)).rgb;
+    taps.m=textureSample(source,source_sampler,uv+texel*vec2(1.0,1.0)).rgb;
+    return taps;
+}
+
+// The first downsample reads the hdr target
+@fragment
+fn fs_prefilter(vs:VertexOutput)->@location(0) vec4<f32>{
+    let t=sample_taps(vs.uv);
+    var color=karis_average(t.j,t.k,t.l,t.m)*0.5;
+    color+=karis_average(t.a,t.b,t.d,t.e)*0.125;
+    color+=karis_average(t.b,t.c,t.e,t.f)*0.125;
+    color+=karis_average(t.d,t.e,t.g,t.h)*0.125;
+    color+=karis_average(t.e,t.f,t.h,t.i)*0.125;
+    // Undo the karis weighting so the result keeps its brightness
+    color=color/max(1.0-luminance(color),0.0001);
+    return vec4(soft_threshold(max(color,vec3(0.0))),1.0);
+}
+
+@fragment
+fn fs_downsample(vs:VertexOutput)->@location(0) vec4<f32>{
+    let t=sample_taps(vs.uv);
+    var color=t.e*0.125;
+    color+=(t.a+t.c+t.g+t.i)*0.03125;
+    color+=(t.b+t.d+t.f+t.h)*0.0625;
+    color+=(t.j+t.k+t.l+t.m)*0.125;
+    return vec4(color,1.0);
+}
+
+// Blended additively onto the larger mip
+@fragment
+fn fs_upsample(vs:VertexOutput)->@location(0) vec4<f32>{
+    let texel=1.0/vec2<f32>(textureDimensions(source));
+    var color=textureSample(source,source_sampler,vs.uv).rgb*4.0;
+    color+=textureSample(source,source_sampler,vs.uv+texel*vec2(-1.0,0.0)).rgb*2.0;
+    color+=textureSample(source,source_sampler,vs.uv+texel*vec2(1.0,0.0)).rgb*2.0;
+    color+=textureSample(source,source_sampler,vs.uv+texel*vec2(0.0,-1.0)).rgb*2.0;
+    color+=textureSample(source,source_sampler,vs.uv+texel*vec2(0.0,1.0)).rgb*2.0;
+    color+=textureSample(source,source_sampler,vs.uv+texel*vec2(-1.0,-1.0)).rgb;
+    color+=textureSample(source,source_sampler,vs.uv+texel*vec2(1.0,-1.0)).rgb;
+    color+=textureSample(source,source_sampler,vs.uv+texel*vec2(-1.0,1.0)).rgb;
+    color+=textureSample(source,source_sampler,vs.uv+texel*vec2(1.0,1.0)).rgb;
+    return vec4(color/16.0,1.0);
+}
@@ -0,0 +1,102 @@
+// Assigns the point and spot lights to a grid of view space clusters, tiles across the screen
+// and exponentially spaced depth slices
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+
+struct DirectionalLight {
+    direction: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+    shadowed: u32,
+}
+struct Light {
+    position: vec3<f32>,
+    range: f32,
+    direction: vec3<f32>,
+    cos_outer: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    cos_inner: f32,
+    kind: u32,
+    shadow_index: i32,
+}
+struct Lights {
+    directional: DirectionalLight,
+    ambient: vec3<f32>,
+    light_count: u32,
+}
+struct Clusters {
+    inv_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    grid: vec3<u32>,
+    enabled: u32,
+    screen_size: vec2<f32>,
+    near: f32,
+    far: f32,
+}
+@group(0) @binding(0)
+var<uniform> lights: Lights;
+@group(0) @binding(1)
+var<storage, read> light_list: array<Light>;
+@group(0) @binding(2)
+var<uniform> clusters: Clusters;
+// Every cluster's light count followed by room for its light indices
+@group(0) @binding(3)
+var<storage, read_write> cluster_lights: array<u32>;
+
+// The view space point at the given depth on the eye ray through a point in normalized device coordinates
+fn view_point(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let point = clusters.inv_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let ray = point.xyz / point.w;
+    return ray * (depth / -ray.z);
+}
+
+@compute
+@workgroup_size(64)
+fn assign_lights(@builtin(global_invocation_id) id: vec3<u32>) {
+    let cluster = id.x;
+    let grid = clusters.grid;
+    if cluster >= grid.x * grid.y * grid.z {
+        return;
+    }
+    let x = cluster % grid.x;
+    let y = (cluster / grid.x) % grid.y;
+    let z = cluster / (grid.x * grid.y);
+
+    let depth_ratio = clusters.far / clusters.near;
+    let near = clusters.near * pow(depth_ratio, f32(z) / f32(grid.z));
+    let far = clusters.near * pow(depth_ratio, f32(z + 1u) / f32(grid.z));
+    // Tiles are counted from the top left of the screen
+    let tile_min = vec2<f32>(f32(x), f32(y)) / vec2<f32>(grid.xy);
+    let tile_max = vec2<f32>(f32(x + 1u), f32(y + 1u)) / vec2<f32>(grid.xy);
+    let ndc_min = vec2<f32>(tile_min.x * 2.0 - 1.0, 1.0 - tile_max.y * 2.0);
+    let ndc_max = vec2<f32>(tile_max.x * 2.0 - 1.0, 1.0 - tile_min.y * 2.0);
+    var corners = array<vec2<f32>, 4>(
+        ndc_min,
+        ndc_max,
+        vec2<f32>(ndc_min.x, ndc_max.y),
+        vec2<f32>(ndc_max.x, ndc_min.y),
+    );
+    var aabb_min = vec3<f32>(3.4e38);
+    var aabb_max = vec3<f32>(-3.4e38);
+    for (var i = 0; i < 4; i++) {
+        let near_point = view_point(corners[i], near);
+        let far_point = view_point(corners[i], far);
+        aabb_min = min(aabb_min, min(near_point, far_point));
+        aabb_max = max(aabb_max, max(near_point, far_point));
+    }
+
+    let offset = cluster * (MAX_LIGHTS_PER_CLUSTER + 1u);
+    var count = 0u;
+    for (var i = 0u; i < lights.light_count && count < MAX_LIGHTS_PER_CLUSTER; i++) {
+        let light = light_list[i];
+        // Spot lights are tested with the sphere around their range too
+        let center = (clusters.view * vec4<f32>(light.position, 1.0)).xyz;
+        let closest = clamp(center, aabb_min, aabb_max);
+        let to_center = center - closest;
+        if dot(to_center, to_center) <= light.range * light.range {
+            cluster_lights[offset + 1u + count] = i;
+            count += 1u;
+        }
+    }
+    cluster_lights[offset] = count;
+}
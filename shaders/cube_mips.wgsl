@@ -0,0 +1,30 @@
+// Box filters a mip of every cube face from the level above, loading texels as rgba32float
+// isn't filterable
+@group(0)
+@binding(0)
+var src:texture_2d_array<f32>;
+
+@group(0)
+@binding(1)
+var dst:texture_storage_2d_array<rgba32float,write>;
+
+@compute
+@workgroup_size(8,8,1)
+fn compute_downsample(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let size=textureDimensions(dst);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+    let base=vec2<i32>(gid.xy*2u);
+    let max_texel=vec2<i32>(textureDimensions(src))-1;
+    var sum=vec4(0.0);
+    for (var y=0; y < 2; y++) {
+        for (var x=0; x < 2; x++) {
+            sum+=textureLoad(src,min(base+vec2(x,y),max_texel),gid.z,0);
+        }
+    }
+    textureStore(dst,gid.xy,gid.z,sum*0.25);
+}
@@ -0,0 +1,33 @@
+// The debug lines, each vertex can be pushed along the screen so labels keep their size in pixels
+struct DebugUniform {
+    view_proj: mat4x4<f32>,
+    viewport: vec2<f32>,
+}
+@group(0) @binding(0)
+var<uniform> debug: DebugUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    // In pixels, y up
+    @location(1) offset: vec2<f32>,
+    @location(2) color: vec3<f32>,
+}
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    var clip = debug.view_proj * vec4<f32>(in.position, 1.0);
+    clip = vec4<f32>(clip.xy + in.offset * 2.0 / debug.viewport * clip.w, clip.zw);
+    out.clip_position = clip;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
@@ -0,0 +1,123 @@
+// The overlays of the debug views: wireframes, the vertices' tangent frames and overdraw
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+// A mesh's ModelVertex values and indices, pulled by the line views that don't have their own
+// vertex buffers
+const VERTEX_WORDS: u32 = 15u;
+@group(1) @binding(0)
+var<storage, read> vertices: array<f32>;
+@group(1) @binding(1)
+var<storage, read> indices: array<u32>;
+
+const WIREFRAME_COLOR: vec3<f32> = vec3<f32>(0.0, 1.0, 0.3);
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+}
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+}
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+fn vertex_vector(vertex: u32, word: u32) -> vec3<f32> {
+    let base = vertex * VERTEX_WORDS + word;
+    return vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+}
+
+// The mesh drawn through its own vertex and index buffers, for the line polygon mode and overdraw
+@vertex
+fn vs_mesh(@location(0) position: vec3<f32>, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix(instance) * vec4<f32>(position, 1.0);
+    out.color = WIREFRAME_COLOR;
+    return out;
+}
+// A line list of every triangle's three edges, two vertices per edge and six per triangle, for
+// adapters without the line polygon mode
+@vertex
+fn vs_wireframe_edges(@builtin(vertex_index) vertex_index: u32, instance: InstanceInput) -> VertexOutput {
+    let triangle = vertex_index / 6u;
+    let edge = (vertex_index % 6u) / 2u;
+    let corner = (edge + vertex_index % 2u) % 3u;
+    let position = vertex_vector(indices[triangle * 3u + corner], 0u);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix(instance) * vec4<f32>(position, 1.0);
+    out.color = WIREFRAME_COLOR;
+    return out;
+}
+// Every vertex's normal in blue, tangent in red and bitangent in green, six vertices per mesh
+// vertex. The lines keep about the same length on screen
+@vertex
+fn vs_tangent_frames(@builtin(vertex_index) vertex_index: u32, instance: InstanceInput) -> VertexOutput {
+    let vertex = vertex_index / 6u;
+    let axis = (vertex_index % 6u) / 2u;
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var words = array<u32, 3>(6u, 9u, 12u);
+    var colors = array<vec3<f32>, 3>(vec3(0.0, 0.3, 1.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
+    let world_position = (model_matrix(instance) * vec4<f32>(vertex_vector(vertex, 0u), 1.0)).xyz;
+    let direction = normalize(normal_matrix * vertex_vector(vertex, words[axis]));
+    let length = 0.03 * distance(camera.view_pos.xyz, world_position);
+    let position = world_position + direction * length * f32(vertex_index % 2u);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.color = colors[axis];
+    return out;
+}
+@fragment
+fn fs_color(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
+// Counted with additive blending, one per fragment
+@fragment
+fn fs_overdraw() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 0.0, 0.0);
+}
+
+@group(0) @binding(0)
+var t_overdraw: texture_2d<f32>;
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+// The overdraw count as a heat map, from black through blue, green, yellow and red to white at
+// ten or more fragments
+@fragment
+fn fs_heatmap(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    var colors = array<vec3<f32>, 6>(
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(1.0, 0.0, 0.0),
+        vec3(1.0, 1.0, 1.0),
+    );
+    let count = textureLoad(t_overdraw, vec2<i32>(position.xy), 0).r;
+    let scaled = clamp(count / 10.0, 0.0, 1.0) * 5.0;
+    let index = u32(min(floor(scaled), 4.0));
+    return vec4<f32>(mix(colors[index], colors[index + 1u], scaled - f32(index)), 1.0);
+}
@@ -0,0 +1,114 @@
+// Deferred lighting, appended to shader.wgsl for its lights and BRDF. The G-buffer takes the
+// material's place in group 0, numbered after the material's bindings
+@group(0) @binding(16)
+var t_gbuffer_albedo: texture_2d<f32>;
+@group(0) @binding(17)
+var t_gbuffer_normal: texture_2d<f32>;
+@group(0) @binding(18)
+var t_gbuffer_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(19)
+var t_gbuffer_emissive: texture_2d<f32>;
+@group(0) @binding(20)
+var t_gbuffer_depth: texture_depth_2d;
+
+struct GBufferSample {
+    surface: Surface,
+    world_position: vec3<f32>,
+    view_depth: f32,
+    emissive: vec3<f32>,
+    occlusion: f32,
+}
+// Rebuilds the surface and its world position from the depth
+fn load_gbuffer(pixel: vec2<i32>) -> GBufferSample {
+    let size = vec2<f32>(textureDimensions(t_gbuffer_depth));
+    let depth = textureLoad(t_gbuffer_depth, pixel, 0);
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let view_position = camera.inv_proj * ndc;
+    let view = view_position.xyz / view_position.w;
+    let world_position = (camera.inv_view * vec4<f32>(view, 1.0)).xyz;
+    let albedo = textureLoad(t_gbuffer_albedo, pixel, 0);
+    let metallic_roughness = textureLoad(t_gbuffer_metallic_roughness, pixel, 0).rg;
+
+    var out: GBufferSample;
+    out.surface.albedo = albedo.rgb;
+    out.surface.normal = normalize(textureLoad(t_gbuffer_normal, pixel, 0).xyz);
+    out.surface.view_dir = normalize(camera.view_pos.xyz - world_position);
+    out.surface.metallic = metallic_roughness.r;
+    out.surface.roughness = metallic_roughness.g;
+    out.surface.f0 = mix(vec3(0.04), out.surface.albedo, out.surface.metallic);
+    out.world_position = world_position;
+    out.view_depth = -view.z;
+    out.emissive = textureLoad(t_gbuffer_emissive, pixel, 0).rgb;
+    out.occlusion = albedo.a;
+    return out;
+}
+fn is_background(pixel: vec2<i32>) -> bool {
+    return textureLoad(t_gbuffer_depth, pixel, 0) >= 1.0;
+}
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vi: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((vi << 1u) & 2u), f32(vi & 2u));
+    var out: FullscreenOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+// Ambient, sun and emissive light for every covered pixel. The G-buffer only holds the shading
+// normal so it also offsets the shadow lookups
+@fragment
+fn fs_deferred(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    if is_background(pixel) {
+        discard;
+    }
+    let gbuffer = load_gbuffer(pixel);
+    let ambient_occlusion = textureLoad(t_ambient_occlusion, pixel, 0).r;
+    var result = ambient(gbuffer.surface, gbuffer.occlusion * ambient_occlusion);
+    result += sun_light(gbuffer.surface, gbuffer.world_position, gbuffer.surface.normal, gbuffer.view_depth);
+    result += gbuffer.emissive;
+    return vec4<f32>(result, 1.0);
+}
+
+struct VolumeOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) light_index: u32,
+}
+// A cube around each light's range, its back faces are drawn so it still covers the screen
+// when the camera is inside
+@vertex
+fn vs_light_volume(
+    @builtin(vertex_index) vi: u32,
+    @builtin(instance_index) light_index: u32,
+) -> VolumeOutput {
+    var corners = array<u32, 36>(
+        0u, 4u, 6u, 0u, 6u, 2u,
+        5u, 1u, 3u, 5u, 3u, 7u,
+        0u, 1u, 5u, 0u, 5u, 4u,
+        6u, 7u, 3u, 6u, 3u, 2u,
+        1u, 0u, 2u, 1u, 2u, 3u,
+        4u, 5u, 7u, 4u, 7u, 6u,
+    );
+    let corner = corners[vi];
+    let offset = vec3<f32>(vec3<u32>(corner & 1u, (corner >> 1u) & 1u, (corner >> 2u) & 1u)) * 2.0 - 1.0;
+    let light = light_list[light_index];
+    var out: VolumeOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(light.position + offset * light.range, 1.0);
+    out.light_index = light_index;
+    return out;
+}
+// One point or spot light, added onto the lit scene
+@fragment
+fn fs_light_volume(in: VolumeOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    if is_background(pixel) {
+        discard;
+    }
+    let gbuffer = load_gbuffer(pixel);
+    let light = light_list[in.light_index];
+    let result = punctual_light(gbuffer.surface, light, gbuffer.world_position, gbuffer.surface.normal);
+    return vec4<f32>(result, 0.0);
+}
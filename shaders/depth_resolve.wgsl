@@ -0,0 +1,20 @@
+// Copies the nearest sample of a multisampled depth buffer into a single sampled one
+@vertex
+fn vs_main(@builtin(vertex_index) vi:u32)->@builtin(position) vec4<f32>{
+    let uv=vec2<f32>(f32((vi<<1u)&2u), f32(vi&2u));
+    return vec4<f32>(uv*2.0-1.0,0.0,1.0);
+}
+
+@group(0)
+@binding(0)
+var depth_samples:texture_depth_multisampled_2d;
+
+@fragment
+fn fs_main(@builtin(position) position:vec4<f32>)->@builtin(frag_depth) f32{
+    let coords=vec2<i32>(position.xy);
+    var depth=1.0;
+    for (var i=0u; i<textureNumSamples(depth_samples); i++) {
+        depth=min(depth,textureLoad(depth_samples,coords,i32(i)));
+    }
+    return depth;
+}
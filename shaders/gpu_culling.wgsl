@@ -0,0 +1,191 @@
+// Frustum and hierarchical depth culling of the instances, compacting the visible ones into a
+// region per level of detail and writing an indirect draw per level and mesh
+const INSTANCE_WORDS: u32 = 31u;
+const LOD_FADE_WORD: u32 = 30u;
+const MAX_LODS: u32 = 4u;
+
+struct Culling {
+    planes: array<vec4<f32>, 6>,
+    // The view projection the hierarchical depth was drawn with, last frame's
+    prev_view_proj: mat4x4<f32>,
+    aabb_min: vec3<f32>,
+    instance_count: u32,
+    aabb_max: vec3<f32>,
+    occlusion: u32,
+    // Center and radius
+    sphere: vec4<f32>,
+    hiz_size: vec2<f32>,
+    hiz_mips: u32,
+    mesh_count: u32,
+    camera_position: vec3<f32>,
+    lod_count: u32,
+    // The projection's y scale, and the screen size and cross-fade of LodSettings
+    projection_scale: f32,
+    lod_screen_size: f32,
+    lod_cross_fade: f32,
+    lod_enabled: u32,
+}
+struct Counts {
+    visible: atomic<u32>,
+    lods: array<atomic<u32>, MAX_LODS>,
+}
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+@group(0) @binding(0)
+var<uniform> culling: Culling;
+// InstanceRaw as words, the texture layer isn't a float so nothing is converted on the way through
+@group(0) @binding(1)
+var<storage, read> instances: array<u32>;
+@group(0) @binding(2)
+var<storage, read_write> visible_instances: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> counts: Counts;
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+// The furthest depth of every texel, halving down the mips
+@group(0) @binding(5)
+var hiz: texture_2d<f32>;
+
+fn model_matrix(instance: u32) -> mat4x4<f32> {
+    let base = instance * INSTANCE_WORDS;
+    var columns: array<vec4<f32>, 4>;
+    for (var i = 0u; i < 4u; i++) {
+        let word = base + i * 4u;
+        columns[i] = bitcast<vec4<f32>>(vec4<u32>(
+            instances[word],
+            instances[word + 1u],
+            instances[word + 2u],
+            instances[word + 3u],
+        ));
+    }
+    return mat4x4<f32>(columns[0], columns[1], columns[2], columns[3]);
+}
+fn outside_frustum(center: vec3<f32>, radius: f32, aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> bool {
+    for (var i = 0; i < 6; i++) {
+        let plane = culling.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return true;
+        }
+        // The corner furthest along the plane's normal
+        let corner = select(aabb_min, aabb_max, plane.xyz >= vec3(0.0));
+        if dot(plane.xyz, corner) + plane.w < 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
+// Whether the box is behind everything drawn last frame where it covers the screen
+fn occluded(aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> bool {
+    if culling.occlusion == 0u {
+        return false;
+    }
+    var uv_min = vec2<f32>(1.0);
+    var uv_max = vec2<f32>(0.0);
+    var nearest = 1.0;
+    for (var i = 0u; i < 8u; i++) {
+        let corner = select(aabb_min, aabb_max, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+        let clip = culling.prev_view_proj * vec4<f32>(corner, 1.0);
+        // Boxes reaching behind the camera are kept
+        if clip.w <= 0.0 {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        nearest = min(nearest, ndc.z);
+    }
+    if nearest <= 0.0 {
+        return false;
+    }
+    uv_min = clamp(uv_min, vec2(0.0), vec2(1.0));
+    uv_max = clamp(uv_max, vec2(0.0), vec2(1.0));
+    // The mip where the box covers about one texel, so at most 3x3 texels are read
+    let size = (uv_max - uv_min) * culling.hiz_size;
+    let mip = u32(clamp(ceil(log2(max(max(size.x, size.y), 1.0))), 0.0, f32(culling.hiz_mips - 1u)));
+    let mip_size = vec2<i32>(textureDimensions(hiz, mip));
+    let texel_min = clamp(vec2<i32>(uv_min * vec2<f32>(mip_size)), vec2(0), mip_size - 1);
+    let texel_max = clamp(vec2<i32>(uv_max * vec2<f32>(mip_size)), vec2(0), mip_size - 1);
+    var furthest = 0.0;
+    for (var y = texel_min.y; y <= texel_max.y; y++) {
+        for (var x = texel_min.x; x <= texel_max.x; x++) {
+            furthest = max(furthest, textureLoad(hiz, vec2(x, y), i32(mip)).r);
+        }
+    }
+    return nearest > furthest;
+}
+
+// The level and how far it has faded into the next, as LodSettings::select does it
+fn select_lod(center: vec3<f32>, radius: f32) -> vec2<f32> {
+    let distance = length(center - culling.camera_position);
+    if culling.lod_enabled == 0u || culling.lod_count <= 1u || distance <= radius {
+        return vec2(0.0);
+    }
+    let last = f32(culling.lod_count - 1u);
+    let screen_size = radius * culling.projection_scale / distance;
+    let level = max(log2(culling.lod_screen_size / screen_size) + 1.0, 0.0);
+    if level >= last {
+        return vec2(last, 0.0);
+    }
+    let start = 1.0 - culling.lod_cross_fade;
+    let fraction = fract(level);
+    var fade = 0.0;
+    if culling.lod_cross_fade > 0.0 && fraction > start {
+        fade = (fraction - start) / culling.lod_cross_fade;
+    }
+    return vec2(floor(level), fade);
+}
+// Copies the instance into the level's region with the fade the dithering reads
+fn append(instance: u32, level: u32, fade: f32) {
+    let slot = level * culling.instance_count + atomicAdd(&counts.lods[level], 1u);
+    for (var i = 0u; i < LOD_FADE_WORD; i++) {
+        visible_instances[slot * INSTANCE_WORDS + i] = instances[instance * INSTANCE_WORDS + i];
+    }
+    visible_instances[slot * INSTANCE_WORDS + LOD_FADE_WORD] = bitcast<u32>(fade);
+}
+
+@compute
+@workgroup_size(64)
+fn cull_instances(@builtin(global_invocation_id) id: vec3<u32>) {
+    let instance = id.x;
+    if instance >= culling.instance_count {
+        return;
+    }
+    let model = model_matrix(instance);
+    let center = (model * vec4<f32>(culling.sphere.xyz, 1.0)).xyz;
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = culling.sphere.w * scale;
+
+    let box_center = (model * vec4<f32>((culling.aabb_min + culling.aabb_max) * 0.5, 1.0)).xyz;
+    let extents = (culling.aabb_max - culling.aabb_min) * 0.5;
+    let box_extents = abs(model[0].xyz) * extents.x + abs(model[1].xyz) * extents.y + abs(model[2].xyz) * extents.z;
+    let aabb_min = box_center - box_extents;
+    let aabb_max = box_center + box_extents;
+    if outside_frustum(center, radius, aabb_min, aabb_max) || occluded(aabb_min, aabb_max) {
+        return;
+    }
+    atomicAdd(&counts.visible, 1u);
+    let lod = select_lod(center, radius);
+    let level = u32(lod.x);
+    // Fading instances are drawn at both levels with complementary dithering
+    if lod.y > 0.0 {
+        append(instance, level, 1.0 - lod.y);
+        append(instance, level + 1u, lod.y - 1.0);
+    } else {
+        append(instance, level, 0.0);
+    }
+}
+// Runs after culling, every mesh draws all of the instances at each level
+@compute
+@workgroup_size(64)
+fn write_draws(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= culling.mesh_count * culling.lod_count {
+        return;
+    }
+    draws[id.x].instance_count = atomicLoad(&counts.lods[id.x / culling.mesh_count]);
+}
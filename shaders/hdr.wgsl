@@ -1,4 +1,5 @@
-fn tone_map(hdr:vec3<f32>)-> vec3<f32>{
+// ACES fit by Stephen Hill
+fn aces(hdr:vec3<f32>)-> vec3<f32>{
 let m1 = mat3x3(
         0.59719, 0.07600, 0.02840,
         0.35458, 0.90834, 0.13383,
@@ -35,10 +36,111 @@ var hdr_image:texture_2d<f32>;
 @binding(1)
 var hdr_sampler:sampler;
 
+const TONE_MAP_NONE: u32 = 0u;
+const TONE_MAP_REINHARD: u32 = 1u;
+const TONE_MAP_REINHARD_EXTENDED: u32 = 2u;
+const TONE_MAP_ACES: u32 = 3u;
+const TONE_MAP_AGX: u32 = 4u;
+const TONE_MAP_UNCHARTED2: u32 = 5u;
+struct ToneMapping {
+    exposure: f32,
+    gamma_exponent: f32,
+    white_point: f32,
+    mode: u32,
+}
+@group(0)
+@binding(2)
+var<uniform> tone_mapping: ToneMapping;
+
+struct AutoExposure {
+    luminance: f32,
+    exposure: f32,
+}
+@group(0)
+@binding(3)
+var<storage, read> auto_exposure: AutoExposure;
+
+@group(0)
+@binding(4)
+var bloom_image:texture_2d<f32>;
+
+struct Bloom {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    scale: f32,
+}
+@group(0)
+@binding(5)
+var<uniform> bloom: Bloom;
+
+fn reinhard_extended(hdr:vec3<f32>, white:f32)->vec3<f32>{
+    return hdr*(1.0+hdr/(white*white))/(1.0+hdr);
+}
+// AgX base contrast with the polynomial curve fit by Benjamin Wrensch
+fn agx_contrast(x:vec3<f32>)->vec3<f32>{
+    let x2=x*x;
+    let x4=x2*x2;
+    return 15.5*x4*x2-40.14*x4*x+31.96*x4-6.868*x2*x+0.4298*x2+0.1191*x-0.00232;
+}
+fn agx(hdr:vec3<f32>)->vec3<f32>{
+    let inset=mat3x3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset=mat3x3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev=-12.47393;
+    let max_ev=4.026069;
+    var v=clamp(log2(max(inset*hdr,vec3(1e-10))),vec3(min_ev),vec3(max_ev));
+    v=agx_contrast((v-min_ev)/(max_ev-min_ev));
+    // The curve outputs display values, take them back to linear for the gamma step
+    return pow(max(outset*v,vec3(0.0)),vec3(2.2));
+}
+// John Hable's filmic curve from Uncharted 2
+fn hable(x:vec3<f32>)->vec3<f32>{
+    let a=0.15;
+    let b=0.50;
+    let c=0.10;
+    let d=0.20;
+    let e=0.02;
+    let f=0.30;
+    return (x*(a*x+c*b)+d*e)/(x*(a*x+b)+d*f)-e/f;
+}
+fn tone_map(hdr:vec3<f32>)->vec3<f32>{
+    let white=tone_mapping.white_point;
+    switch tone_mapping.mode {
+        case TONE_MAP_REINHARD: {
+            return hdr/(1.0+hdr);
+        }
+        case TONE_MAP_REINHARD_EXTENDED: {
+            return clamp(reinhard_extended(hdr,white),vec3(0.0),vec3(1.0));
+        }
+        case TONE_MAP_ACES: {
+            return aces(hdr);
+        }
+        case TONE_MAP_AGX: {
+            return clamp(agx(hdr),vec3(0.0),vec3(1.0));
+        }
+        case TONE_MAP_UNCHARTED2: {
+            return clamp(hable(hdr*2.0)/hable(vec3(white)),vec3(0.0),vec3(1.0));
+        }
+        default: {
+            return clamp(hdr,vec3(0.0),vec3(1.0));
+        }
+    }
+}
+
 @fragment
 fn fs_main(vs:VertexOutput)->@location(0) vec4<f32>{
     let hdr=textureSample(hdr_image,hdr_sampler,vs.uv);
-    let ldr=tone_map(hdr.rgb);
-    return vec4(ldr,hdr.a);
+    let blurred=textureSample(bloom_image,hdr_sampler,vs.uv).rgb*bloom.scale;
+    let scene=mix(hdr.rgb,blurred,bloom.intensity);
+    let ldr=tone_map(max(scene*tone_mapping.exposure*auto_exposure.exposure,vec3(0.0)));
+    return vec4(pow(ldr,vec3(tone_mapping.gamma_exponent)),hdr.a);
 
 }
@@ -0,0 +1,38 @@
+// Builds the hierarchical depth used for occlusion culling, every texel holding the furthest
+// depth under it
+@group(0) @binding(0)
+var depth: texture_depth_2d;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var destination: texture_storage_2d<r32float, write>;
+
+@compute
+@workgroup_size(8, 8)
+fn copy_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+    let value = textureLoad(depth, vec2<i32>(id.xy), 0);
+    textureStore(destination, vec2<i32>(id.xy), vec4<f32>(value, 0.0, 0.0, 0.0));
+}
+// Reads a 3x3 block so the texels an odd sized source leaves over are still covered
+@compute
+@workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+    let source_max = vec2<i32>(textureDimensions(source)) - 1;
+    let origin = vec2<i32>(id.xy) * 2;
+    var furthest = 0.0;
+    for (var y = 0; y < 3; y++) {
+        for (var x = 0; x < 3; x++) {
+            let texel = min(origin + vec2(x, y), source_max);
+            furthest = max(furthest, textureLoad(source, texel, 0).r);
+        }
+    }
+    textureStore(destination, vec2<i32>(id.xy), vec4<f32>(furthest, 0.0, 0.0, 0.0));
+}
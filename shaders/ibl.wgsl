@@ -0,0 +1,115 @@
+const PI: f32 = 3.1415926535897932384626433832795;
+const PREFILTER_SAMPLES: u32 = 512u;
+
+struct PrefilterParams {
+    roughness: f32,
+}
+
+@group(0)
+@binding(0)
+var src: texture_cube<f32>;
+
+@group(0)
+@binding(1)
+var src_sampler: sampler;
+
+@group(0)
+@binding(2)
+var dst: texture_storage_2d_array<rgba16float, write>;
+
+@group(0)
+@binding(3)
+var<uniform> params: PrefilterParams;
+
+// World space direction through the centre of a cube face texel, following the
+// face layout the hardware uses when sampling cube maps
+fn cube_direction(gid: vec3<u32>) -> vec3<f32> {
+    let uv = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(textureDimensions(dst)) * 2.0 - 1.0;
+    var dir: vec3<f32>;
+    switch gid.z {
+        case 0u: { dir = vec3(1.0, -uv.y, -uv.x); }
+        case 1u: { dir = vec3(-1.0, -uv.y, uv.x); }
+        case 2u: { dir = vec3(uv.x, 1.0, uv.y); }
+        case 3u: { dir = vec3(uv.x, -1.0, -uv.y); }
+        case 4u: { dir = vec3(uv.x, -uv.y, 1.0); }
+        default: { dir = vec3(-uv.x, -uv.y, -1.0); }
+    }
+    return normalize(dir);
+}
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3(0.0, 1.0, 0.0);
+    if abs(n.y) > 0.999 {
+        up = vec3(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return mat3x3<f32>(tangent, bitangent, n);
+}
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return normalize(tangent_frame(n) * h);
+}
+
+// Cosine weighted convolution of the hemisphere around each texel's direction
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_irradiance(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let size = textureDimensions(dst);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+    let normal = cube_direction(gid);
+    let frame = tangent_frame(normal);
+    let delta = 0.05;
+    var irradiance = vec3(0.0);
+    var samples = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            let tangent_sample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let dir = frame * tangent_sample;
+            let radiance = textureSampleLevel(src, src_sampler, dir, 0.0).rgb;
+            irradiance += radiance * cos(theta) * sin(theta);
+            samples += 1.0;
+        }
+    }
+    irradiance = PI * irradiance / samples;
+    textureStore(dst, gid.xy, gid.z, vec4(irradiance, 1.0));
+}
+
+// Split sum approximation: convolve the environment with the GGX lobe for this mip's roughness
+@compute
+@workgroup_size(16, 16, 1)
+fn compute_prefilter(
+    @builtin(global_invocation_id)
+    gid: vec3<u32>,
+) {
+    let size = textureDimensions(dst);
+    if gid.x >= size.x || gid.y >= size.y {
+        return;
+    }
+    let n = cube_direction(gid);
+    let v = n;
+    var color = vec3(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLES; i++) {
+        let xi = hammersley(i, PREFILTER_SAMPLES);
+        let h = importance_sample_ggx(xi, n, params.roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            color += textureSampleLevel(src, src_sampler, l, 0.0).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    textureStore(dst, gid.xy, gid.z, vec4(color / max(weight, 1e-4), 1.0));
+}
@@ -10,10 +10,17 @@ var<uniform> camera: Camera;
 
 struct Light{
     position:vec3<f32>,
-    color:vec3<f32>
+    range:f32,
+    direction:vec3<f32>,
+    cos_outer:f32,
+    color:vec3<f32>,
+    intensity:f32,
+    cos_inner:f32,
+    kind:u32,
+    shadow_index:i32,
 }
-@group(1) @binding(0)
-var<uniform> light:Light;
+@group(1) @binding(1)
+var<storage, read> light_list:array<Light>;
 
 struct VertexInput{
     @location(0) position:vec3<f32>,
@@ -23,8 +30,9 @@ struct VertexOutput{
     @location(0) color:vec3<f32>,
 };
 @vertex
-fn vs_main(model:VertexInput,) -> VertexOutput{
+fn vs_main(model:VertexInput,@builtin(instance_index) light_index:u32) -> VertexOutput{
     let scale=0.25;
+    let light=light_list[light_index];
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4<f32>(model.position*scale+light.position,1.0);
     out.color=light.color;
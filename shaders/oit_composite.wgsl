@@ -0,0 +1,31 @@
+struct VertexOutput{
+    @builtin(position) clip_position:vec4<f32>,
+}
+@vertex
+fn vs_main(@builtin(vertex_index) vi:u32)->VertexOutput{
+    var out:VertexOutput;
+    let uv=vec2<f32>(f32((vi<<1u)&2u), f32(vi&2u));
+    out.clip_position=vec4<f32>(uv*2.0-1.0,0.0,1.0);
+    return out;
+}
+
+@group(0)
+@binding(0)
+var accum_image:texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var revealage_image:texture_2d<f32>;
+
+// Resolves the weighted average of the transparent surfaces, blended with premultiplied alpha
+@fragment
+fn fs_main(vs:VertexOutput)->@location(0) vec4<f32>{
+    let coords=vec2<i32>(vs.clip_position.xy);
+    let revealage=textureLoad(revealage_image,coords,0).r;
+    if revealage>=0.999 {
+        discard;
+    }
+    let accum=textureLoad(accum_image,coords,0);
+    let average=accum.rgb/max(accum.a,1e-5);
+    return vec4(average*(1.0-revealage),1.0-revealage);
+}
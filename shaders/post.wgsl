@@ -0,0 +1,59 @@
+// Shared by every post-process effect, the effect's own source is appended after this
+struct PostGlobals {
+    resolution: vec2<f32>,
+    time: f32,
+    frame: u32,
+    // The colour texture holds linear values when the output is srgb, display values otherwise
+    output_is_srgb: u32,
+}
+struct VertexOutput{
+    @location(0) uv:vec2<f32>,
+    @builtin(position) clip_position:vec4<f32>
+}
+@vertex
+fn vs_main(@builtin(vertex_index) vi:u32)->VertexOutput{
+    var out:VertexOutput;
+    out.uv=vec2<f32>(f32((vi<<1u)&2u), f32(vi&2u),);
+    out.clip_position=vec4<f32>(out.uv*2.0-1.0,0.0,1.0);
+    out.uv.y=1.0-out.uv.y;
+    return out;
+}
+
+@group(0)
+@binding(0)
+var color_texture:texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var color_sampler:sampler;
+
+@group(0)
+@binding(2)
+var depth_texture:texture_depth_2d;
+
+@group(0)
+@binding(3)
+var normal_texture:texture_2d<f32>;
+
+@group(0)
+@binding(4)
+var<uniform> globals: PostGlobals;
+
+@group(1)
+@binding(1)
+var lut_texture:texture_3d<f32>;
+
+@group(1)
+@binding(2)
+var lut_sampler:sampler;
+
+fn linear_to_srgb(color:vec3<f32>)->vec3<f32>{
+    let low=color*12.92;
+    let high=1.055*pow(color,vec3(1.0/2.4))-0.055;
+    return select(high,low,color<=vec3(0.0031308));
+}
+fn srgb_to_linear(color:vec3<f32>)->vec3<f32>{
+    let low=color/12.92;
+    let high=pow((color+0.055)/1.055,vec3(2.4));
+    return select(high,low,color<=vec3(0.04045));
+}
@@ -0,0 +1,17 @@
+struct ChromaticAberration {
+    // How far apart red and blue are at the screen's corners, in uv
+    strength: f32,
+}
+@group(1)
+@binding(0)
+var<uniform> aberration: ChromaticAberration;
+
+@fragment
+fn fs_main(vs:VertexOutput)->@location(0) vec4<f32>{
+    // Lenses split colours more the further they are from the centre
+    let offset=(vs.uv-0.5)*aberration.strength;
+    let r=textureSample(color_texture,color_sampler,vs.uv+offset).r;
+    let center=textureSample(color_texture,color_sampler,vs.uv);
+    let b=textureSample(color_texture,color_sampler,vs.uv-offset).b;
+    return vec4(r,center.g,b,center.a);
+}
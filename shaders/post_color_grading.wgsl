@@ -0,0 +1,25 @@
+struct ColorGrading {
+    // Blend between the ungraded and the graded colour
+    strength: f32,
+}
+@group(1)
+@binding(0)
+var<uniform> grading: ColorGrading;
+
+@fragment
+fn fs_main(vs:VertexOutput)->@location(0) vec4<f32>{
+    let color=textureSample(color_texture,color_sampler,vs.uv);
+    // Luts are authored for display values
+    var display=clamp(color.rgb,vec3(0.0),vec3(1.0));
+    if globals.output_is_srgb==1u {
+        display=linear_to_srgb(display);
+    }
+    // Sample texel centres so the ends of the lut map to zero and one
+    let size=f32(textureDimensions(lut_texture).x);
+    let graded_display=textureSample(lut_texture,lut_sampler,display*(size-1.0)/size+0.5/size).rgb;
+    var graded=graded_display;
+    if globals.output_is_srgb==1u {
+        graded=srgb_to_linear(graded_display);
+    }
+    return vec4(mix(color.rgb,graded,grading.strength),color.a);
+}
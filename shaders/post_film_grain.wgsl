@@ -0,0 +1,25 @@
+struct FilmGrain {
+    intensity: f32,
+    // Grain cell size in pixels
+    size: f32,
+}
+@group(1)
+@binding(0)
+var<uniform> grain: FilmGrain;
+
+fn hash(p:vec3<f32>)->f32{
+    var q=fract(p*0.1031);
+    q+=dot(q,q.zyx+31.32);
+    return fract((q.x+q.y)*q.z);
+}
+
+@fragment
+fn fs_main(vs:VertexOutput)->@location(0) vec4<f32>{
+    let color=textureSample(color_texture,color_sampler,vs.uv);
+    let cell=floor(vs.clip_position.xy/max(grain.size,1.0));
+    let noise=hash(vec3(cell,f32(globals.frame%1024u)))-0.5;
+    // Grain shows most in the mid tones
+    let luminance=dot(color.rgb,vec3(0.2126,0.7152,0.0722));
+    let response=1.0-abs(luminance*2.0-1.0);
+    return vec4(max(color.rgb+noise*grain.intensity*response,vec3(0.0)),color.a);
+}
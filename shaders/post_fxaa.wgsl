@@ -0,0 +1,48 @@
+// Fast approximate anti-aliasing after Timothy Lottes
+struct Fxaa {
+    // The longest an edge is searched along, in pixels
+    span_max: f32,
+    reduce_mul: f32,
+    reduce_min: f32,
+}
+@group(1)
+@binding(0)
+var<uniform> fxaa: Fxaa;
+
+fn fxaa_sample(uv:vec2<f32>)->vec3<f32>{
+    return textureSampleLevel(color_texture,color_sampler,uv,0.0).rgb;
+}
+// Edges are found on perceptual brightness
+fn fxaa_luma(color:vec3<f32>)->f32{
+    var display=clamp(color,vec3(0.0),vec3(1.0));
+    if globals.output_is_srgb==1u {
+        display=linear_to_srgb(display);
+    }
+    return dot(display,vec3(0.299,0.587,0.114));
+}
+
+@fragment
+fn fs_main(vs:VertexOutput)->@location(0) vec4<f32>{
+    let texel=1.0/globals.resolution;
+    let center=textureSampleLevel(color_texture,color_sampler,vs.uv,0.0);
+    let luma_nw=fxaa_luma(fxaa_sample(vs.uv+vec2(-1.0,-1.0)*texel));
+    let luma_ne=fxaa_luma(fxaa_sample(vs.uv+vec2(1.0,-1.0)*texel));
+    let luma_sw=fxaa_luma(fxaa_sample(vs.uv+vec2(-1.0,1.0)*texel));
+    let luma_se=fxaa_luma(fxaa_sample(vs.uv+vec2(1.0,1.0)*texel));
+    let luma_m=fxaa_luma(center.rgb);
+    let luma_min=min(luma_m,min(min(luma_nw,luma_ne),min(luma_sw,luma_se)));
+    let luma_max=max(luma_m,max(max(luma_nw,luma_ne),max(luma_sw,luma_se)));
+    // The edge runs perpendicular to the luma gradient
+    var direction=vec2(-((luma_nw+luma_ne)-(luma_sw+luma_se)),(luma_nw+luma_sw)-(luma_ne+luma_se));
+    let reduce=max((luma_nw+luma_ne+luma_sw+luma_se)*0.25*fxaa.reduce_mul,fxaa.reduce_min);
+    let scale=1.0/(min(abs(direction.x),abs(direction.y))+reduce);
+    direction=clamp(direction*scale,vec2(-fxaa.span_max),vec2(fxaa.span_max))*texel;
+    let near=0.5*(fxaa_sample(vs.uv+direction*(1.0/3.0-0.5))+fxaa_sample(vs.uv+direction*(2.0/3.0-0.5)));
+    let far=near*0.5+0.25*(fxaa_sample(vs.uv-direction*0.5)+fxaa_sample(vs.uv+direction*0.5));
+    // The wider blur overshoots when it crosses another edge
+    let luma_far=fxaa_luma(far);
+    if luma_far<luma_min || luma_far>luma_max {
+        return vec4(near,center.a);
+    }
+    return vec4(far,center.a);
+}
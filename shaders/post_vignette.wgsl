@@ -0,0 +1,18 @@
+struct Vignette {
+    intensity: f32,
+    // Distance from the centre, in half screen heights, where the darkening starts
+    radius: f32,
+    smoothness: f32,
+}
+@group(1)
+@binding(0)
+var<uniform> vignette: Vignette;
+
+@fragment
+fn fs_main(vs:VertexOutput)->@location(0) vec4<f32>{
+    let color=textureSample(color_texture,color_sampler,vs.uv);
+    let aspect=globals.resolution.x/globals.resolution.y;
+    let offset=(vs.uv-0.5)*vec2(aspect,1.0)*2.0;
+    let falloff=smoothstep(vignette.radius,vignette.radius+vignette.smoothness,length(offset));
+    return vec4(color.rgb*(1.0-falloff*vignette.intensity),color.a);
+}
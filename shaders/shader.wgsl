@@ -17,10 +17,15 @@ struct VertexInput {
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec3<f32>,
-    @location(1) tan_pos: vec3<f32>,
-    @location(2) tan_light_pos: vec3<f32>,
-    @location(3) tan_view_pos: vec3<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+    @location(5) view_depth: f32,
+    // The material entry, the mesh's entry in a batched material plus the instance's layer
+    @location(6) @interpolate(flat) entry: u32,
+    @location(7) @interpolate(flat) lod_fade: f32,
 };
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
@@ -30,6 +35,11 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3<f32>,
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
+    // Offset and scale of the uvs, and the entry of a batched material the instance shifts to
+    @location(12) uv_transform: vec4<f32>,
+    @location(13) texture_layer: u32,
+    // Cross-fades between levels of detail, 0 when the instance isn't fading
+    @location(14) lod_fade: f32,
 };
 
 @vertex
@@ -48,57 +58,544 @@ fn vs_main(
         instance.normal_matrix_1,
         instance.normal_matrix_2,
     );
-    let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
-    let tangent_matrix = transpose(mat3x3<f32>(
-        world_tangent,
-        world_bitangent,
-        world_normal,
-    ));
     let world_position=model_matrix*vec4<f32>(model.position,1.0);
 
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
+    out.tex_coords = model.tex_coords.xy*instance.uv_transform.zw+instance.uv_transform.xy;
+    out.entry = u32(model.tex_coords.z)+instance.texture_layer;
+    out.lod_fade = instance.lod_fade;
     out.clip_position =camera.view_proj * world_position;
-    out.tan_pos=tangent_matrix*model.position;
-    out.tan_view_pos=tangent_matrix*camera.view_pos.xyz;
-    out.tan_light_pos=tangent_matrix*light.position;
+    out.world_position=world_position.xyz;
+    out.world_normal = normalize(normal_matrix * model.normal);
+    out.world_tangent = normalize(normal_matrix * model.tangent);
+    out.world_bitangent = normalize(normal_matrix * model.bitangent);
+    out.view_depth=-(camera.view*world_position).z;
     return out;
 }
 
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    alpha_cutoff: f32,
+    // Offset and scale of the entry's tile in an atlas, and the layer its textures are on
+    uv_rect: vec4<f32>,
+    layer: u32,
+}
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_base_color: texture_2d_array<f32>;
 @group(0) @binding(1)
-var s_diffuse: sampler;
+var s_base_color: sampler;
 @group(0) @binding(2)
-var t_normal: texture_2d<f32>;
+var t_normal: texture_2d_array<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
- struct Light{
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d_array<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+@group(0) @binding(6)
+var t_occlusion: texture_2d_array<f32>;
+@group(0) @binding(7)
+var s_occlusion: sampler;
+@group(0) @binding(8)
+var t_emissive: texture_2d_array<f32>;
+@group(0) @binding(9)
+var s_emissive: sampler;
+// One material per batched source
+@group(0) @binding(10)
+var<storage, read> materials: array<Material>;
+// Where a fragment's uv falls in the entry's textures
+fn material_uv(material: Material, uv: vec2<f32>) -> vec2<f32> {
+    return uv*material.uv_rect.zw+material.uv_rect.xy;
+}
+
+const PI: f32 = 3.14159265359;
+const MAX_CASCADES: u32 = 4u;
+const MAX_SHADOWED_SPOT_LIGHTS: u32 = 4u;
+const LIGHT_KIND_POINT: u32 = 0u;
+const LIGHT_KIND_SPOT: u32 = 1u;
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+
+struct DirectionalLight {
+    direction: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+    shadowed: u32,
+}
+struct Light {
     position: vec3<f32>,
+    range: f32,
+    direction: vec3<f32>,
+    cos_outer: f32,
     color: vec3<f32>,
- }
- @group(2) @binding(0)
- var<uniform> light:Light;
+    intensity: f32,
+    cos_inner: f32,
+    kind: u32,
+    shadow_index: i32,
+}
+struct Lights {
+    directional: DirectionalLight,
+    ambient: vec3<f32>,
+    light_count: u32,
+}
+struct Shadows {
+    cascade_view_proj: array<mat4x4<f32>, MAX_CASCADES>,
+    spot_view_proj: array<mat4x4<f32>, MAX_SHADOWED_SPOT_LIGHTS>,
+    cascade_splits: vec4<f32>,
+    cascade_count: u32,
+    pcf_radius: u32,
+    normal_bias: f32,
+    texel_size: f32,
+    point_filter_radius: f32,
+    point_depth_bias: f32,
+}
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+@group(2) @binding(1)
+var<storage, read> light_list: array<Light>;
+@group(2) @binding(2)
+var<uniform> shadows: Shadows;
+@group(2) @binding(3)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(4)
+var s_shadow: sampler_comparison;
+@group(2) @binding(5)
+var t_point_shadow: texture_depth_cube_array;
+struct Clusters {
+    inv_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    grid: vec3<u32>,
+    enabled: u32,
+    screen_size: vec2<f32>,
+    near: f32,
+    far: f32,
+}
+@group(2) @binding(6)
+var<uniform> clusters: Clusters;
+// Every cluster's light count followed by its light indices
+@group(2) @binding(7)
+var<storage, read> cluster_lights: array<u32>;
+
+struct Environment {
+    intensity: f32,
+}
+@group(3) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(2)
+var t_brdf_lut: texture_2d<f32>;
+@group(3) @binding(3)
+var s_environment: sampler;
+@group(3) @binding(4)
+var<uniform> environment: Environment;
+// Screen space ambient occlusion, white when it is off
+@group(3) @binding(5)
+var t_ambient_occlusion: texture_2d<f32>;
+
+// Percentage closer filtering over a (2r+1)^2 texel box
+fn sample_shadow(layer: u32, view_proj: mat4x4<f32>, world_pos: vec3<f32>) -> f32 {
+    let clip = view_proj * vec4<f32>(world_pos, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    if any(uv < vec2(0.0)) || any(uv > vec2(1.0)) || ndc.z < 0.0 || ndc.z > 1.0 {
+        return 1.0;
+    }
+    let radius = i32(shadows.pcf_radius);
+    var lit = 0.0;
+    var taps = 0.0;
+    for (var x = -radius; x <= radius; x++) {
+        for (var y = -radius; y <= radius; y++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadows.texel_size;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, layer, ndc.z);
+            taps += 1.0;
+        }
+    }
+    return lit / taps;
+}
+fn directional_shadow(world_pos: vec3<f32>, normal: vec3<f32>, view_depth: f32) -> f32 {
+    if lights.directional.shadowed == 0u {
+        return 1.0;
+    }
+    var cascade = 0u;
+    for (var i = 0u; i < shadows.cascade_count; i++) {
+        if view_depth > shadows.cascade_splits[i] {
+            cascade = i + 1u;
+        }
+    }
+    if cascade >= shadows.cascade_count {
+        return 1.0;
+    }
+    // Further cascades cover more world space per texel so need more offset
+    let biased = world_pos + normal * shadows.normal_bias * f32(cascade + 1u);
+    return sample_shadow(cascade, shadows.cascade_view_proj[cascade], biased);
+}
+fn spot_shadow(shadow_index: i32, world_pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if shadow_index < 0 {
+        return 1.0;
+    }
+    let index = u32(shadow_index);
+    let biased = world_pos + normal * shadows.normal_bias;
+    return sample_shadow(MAX_CASCADES + index, shadows.spot_view_proj[index], biased);
+}
+// Point shadows hold the distance to the light divided by its range, filtered with a disk of
+// offsets that widens as the camera moves away
+fn point_shadow(light: Light, world_pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if light.shadow_index < 0 {
+        return 1.0;
+    }
+    var offsets = array<vec3<f32>, 20>(
+        vec3(1.0, 1.0, 1.0), vec3(1.0, -1.0, 1.0), vec3(-1.0, -1.0, 1.0), vec3(-1.0, 1.0, 1.0),
+        vec3(1.0, 1.0, -1.0), vec3(1.0, -1.0, -1.0), vec3(-1.0, -1.0, -1.0), vec3(-1.0, 1.0, -1.0),
+        vec3(1.0, 1.0, 0.0), vec3(1.0, -1.0, 0.0), vec3(-1.0, -1.0, 0.0), vec3(-1.0, 1.0, 0.0),
+        vec3(1.0, 0.0, 1.0), vec3(-1.0, 0.0, 1.0), vec3(1.0, 0.0, -1.0), vec3(-1.0, 0.0, -1.0),
+        vec3(0.0, 1.0, 1.0), vec3(0.0, -1.0, 1.0), vec3(0.0, -1.0, -1.0), vec3(0.0, 1.0, -1.0),
+    );
+    let to_fragment = world_pos + normal * shadows.normal_bias - light.position;
+    let depth = length(to_fragment) / light.range - shadows.point_depth_bias;
+    let view_distance = length(camera.view_pos.xyz - world_pos);
+    let disk = shadows.point_filter_radius * (1.0 + view_distance / light.range);
+    var lit = 0.0;
+    for (var i = 0; i < 20; i++) {
+        lit += textureSampleCompareLevel(
+            t_point_shadow,
+            s_shadow,
+            to_fragment + offsets[i] * disk,
+            light.shadow_index,
+            depth,
+        );
+    }
+    return lit / 20.0;
+}
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+fn geometry_schlick_ggx(n_dot_x: f32, k: f32) -> f32 {
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    return geometry_schlick_ggx(n_dot_v, k) * geometry_schlick_ggx(n_dot_l, k);
+}
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+// Fresnel for the environment, rough surfaces reflect less at grazing angles
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+struct Surface {
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    f0: vec3<f32>,
+}
+// Cook-Torrance specular plus Lambert diffuse, already multiplied by n dot l
+fn brdf(surface: Surface, light_dir: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = max(dot(surface.normal, light_dir), 0.0);
+    if n_dot_l <= 0.0 {
+        return vec3(0.0);
+    }
+    let half_dir = normalize(light_dir + surface.view_dir);
+    let n_dot_v = max(dot(surface.normal, surface.view_dir), 1e-4);
+    let n_dot_h = max(dot(surface.normal, half_dir), 0.0);
+    let d = distribution_ggx(n_dot_h, surface.roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, surface.roughness);
+    let f = fresnel_schlick(max(dot(half_dir, surface.view_dir), 0.0), surface.f0);
+    let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 1e-4);
+    let k_d = (vec3(1.0) - f) * (1.0 - surface.metallic);
+    return (k_d * surface.albedo / PI + specular) * n_dot_l;
+}
+// Inverse square falloff windowed to reach zero at the light's range
+fn attenuation(distance: f32, range: f32) -> f32 {
+    let window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
+// Diffuse irradiance plus split-sum specular from the prefiltered environment
+fn ambient_light(surface: Surface) -> vec3<f32> {
+    let n_dot_v = max(dot(surface.normal, surface.view_dir), 1e-4);
+    let f = fresnel_schlick_roughness(n_dot_v, surface.f0, surface.roughness);
+    let k_d = (vec3(1.0) - f) * (1.0 - surface.metallic);
+    let diffuse = textureSample(t_irradiance, s_environment, surface.normal).rgb * surface.albedo;
+    let reflected = reflect(-surface.view_dir, surface.normal);
+    let max_lod = f32(textureNumLevels(t_prefiltered) - 1u);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflected, surface.roughness * max_lod).rgb;
+    let env_brdf = textureSampleLevel(t_brdf_lut, s_environment, vec2(n_dot_v, surface.roughness), 0.0).rg;
+    let specular = prefiltered * (f * env_brdf.x + env_brdf.y);
+    return (k_d * diffuse + specular) * environment.intensity;
+}
+
+
+// Material inputs of a fragment, before lighting
+struct MaterialSample {
+    surface: Surface,
+    emissive: vec3<f32>,
+    occlusion: f32,
+    alpha: f32,
+}
+fn sample_material(in: VertexOutput) -> MaterialSample {
+    let material=materials[in.entry];
+    let uv=material_uv(material,in.tex_coords);
+    let layer=material.layer;
+    let base_color=textureSample(t_base_color, s_base_color, uv, layer)*material.base_color;
+    let obj_norm:vec4<f32> =textureSample(t_normal, s_normal, uv, layer);
+    let metallic_roughness=textureSample(t_metallic_roughness, s_metallic_roughness, uv, layer);
+    let occlusion_sample=textureSample(t_occlusion, s_occlusion, uv, layer).r;
+    let emissive=textureSample(t_emissive, s_emissive, uv, layer).rgb*material.emissive;
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    var tan_normal=obj_norm.xyz* 2.0- vec3(1.0,1.0,1.0);
+    tan_normal=vec3(tan_normal.xy*material.normal_scale,tan_normal.z);
+
+    var out: MaterialSample;
+    out.surface.albedo=base_color.rgb;
+    out.surface.normal=normalize(tangent_matrix*tan_normal);
+    out.surface.view_dir=normalize(camera.view_pos.xyz-in.world_position);
+    out.surface.metallic=clamp(metallic_roughness.b*material.metallic,0.0,1.0);
+    // Very low roughness makes the highlights alias into single pixels
+    out.surface.roughness=clamp(metallic_roughness.g*material.roughness,0.045,1.0);
+    out.surface.f0=mix(vec3(0.04),out.surface.albedo,out.surface.metallic);
+    out.emissive=emissive;
+    out.occlusion=mix(1.0,occlusion_sample,material.occlusion_strength);
+    out.alpha=base_color.a;
+    return out;
+}
+// Constant and image based light, scaled by the occlusion
+fn ambient(surface: Surface, occlusion: f32) -> vec3<f32> {
+    return (lights.ambient*surface.albedo+ambient_light(surface))*occlusion;
+}
+fn sun_light(surface: Surface, world_position: vec3<f32>, geometric_normal: vec3<f32>, view_depth: f32) -> vec3<f32> {
+    let sun=lights.directional;
+    if sun.intensity <= 0.0 {
+        return vec3(0.0);
+    }
+    let light_dir=normalize(-sun.direction);
+    let shadow=directional_shadow(world_position,geometric_normal,view_depth);
+    return brdf(surface,light_dir)*sun.color*sun.intensity*shadow;
+}
+// A point or spot light, zero outside its range
+fn punctual_light(surface: Surface, light: Light, world_position: vec3<f32>, geometric_normal: vec3<f32>) -> vec3<f32> {
+    let to_light=light.position-world_position;
+    let distance=length(to_light);
+    if distance > light.range {
+        return vec3(0.0);
+    }
+    let light_dir=to_light/distance;
+    var strength=light.intensity*attenuation(distance,light.range);
+    if light.kind == LIGHT_KIND_SPOT {
+        let cone=dot(-light_dir,light.direction);
+        strength*=smoothstep(light.cos_outer,light.cos_inner,cone);
+        strength*=spot_shadow(light.shadow_index,world_position,geometric_normal);
+    } else if light.kind == LIGHT_KIND_POINT {
+        strength*=point_shadow(light,world_position,geometric_normal);
+    }
+    return brdf(surface,light_dir)*light.color*strength;
+}
+// The cluster a fragment falls in, from its screen position and logarithmic view depth
+fn cluster_index(frag_coord: vec2<f32>, view_depth: f32) -> u32 {
+    let grid=clusters.grid;
+    let tile=vec2<u32>(clamp(frag_coord/clusters.screen_size*vec2<f32>(grid.xy),vec2(0.0),vec2<f32>(grid.xy-1u)));
+    let slice_position=log(max(view_depth,clusters.near)/clusters.near)/log(clusters.far/clusters.near);
+    let slice=min(u32(slice_position*f32(grid.z)),grid.z-1u);
+    return tile.x+grid.x*(tile.y+grid.y*slice);
+}
+// The ambient, sun and punctual light reflected by a surface, without its emission
+fn light_surface(surface: Surface, in: VertexOutput, occlusion: f32) -> vec3<f32> {
+    let geometric_normal=normalize(in.world_normal);
+    var result=ambient(surface,occlusion);
+    result+=sun_light(surface,in.world_position,geometric_normal,in.view_depth);
+    if clusters.enabled != 0u {
+        let offset=cluster_index(in.clip_position.xy,in.view_depth)*(MAX_LIGHTS_PER_CLUSTER+1u);
+        let count=cluster_lights[offset];
+        for (var i = 0u; i < count; i++) {
+            let light=light_list[cluster_lights[offset+1u+i]];
+            result+=punctual_light(surface,light,in.world_position,geometric_normal);
+        }
+    } else {
+        for (var i = 0u; i < lights.light_count; i++) {
+            result+=punctual_light(surface,light_list[i],in.world_position,geometric_normal);
+        }
+    }
+    return result;
+}
+// Lit color with the base color alpha, shared by the opaque and transparent entry points
+fn shade(in: VertexOutput, ambient_occlusion: f32) -> vec4<f32> {
+    let sample=sample_material(in);
+    let result=light_surface(sample.surface,in,sample.occlusion*ambient_occlusion)+sample.emissive;
+    return vec4<f32>(result,sample.alpha);
+}
 
+// A 4x4 Bayer matrix threshold, positive fades keep the pixels below them and negative fades the
+// rest, so the two levels of a cross-fade fill each pixel once
+fn lod_dithered(in: VertexOutput) -> bool {
+    if in.lod_fade == 0.0 {
+        return false;
+    }
+    var bayer = array<u32, 16>(0u, 8u, 2u, 10u, 12u, 4u, 14u, 6u, 3u, 11u, 1u, 9u, 15u, 7u, 13u, 5u);
+    let pixel = vec2<u32>(in.clip_position.xy) % 4u;
+    let threshold = (f32(bayer[pixel.y * 4u + pixel.x]) + 0.5) / 16.0;
+    if in.lod_fade > 0.0 {
+        return threshold >= in.lod_fade;
+    }
+    return threshold < -in.lod_fade;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
-    let obj_color: vec4<f32>= textureSample(t_diffuse, s_diffuse, in.tex_coords.xy);
-    let obj_norm:vec4<f32> =textureSample(t_normal, s_normal, in.tex_coords.xy);
-    let amb_str=0.1;
-    let amb_color=light.color*amb_str;
-    let tan_normal=obj_norm.xyz* 2.0- vec3(1.0,1.0,1.0);
-    let light_dir=normalize(in.tan_light_pos-in.tan_pos);
-    let view_dir=normalize(in.tan_view_pos.xyz-in.tan_pos);
-    let diff_str=max(dot(tan_normal,light_dir),0.0);
-    let diff_color=light.color*diff_str;
+    if lod_dithered(in) {
+        discard;
+    }
+    let ambient_occlusion=textureLoad(t_ambient_occlusion,vec2<i32>(in.clip_position.xy),0).r;
+    let color=shade(in,ambient_occlusion);
+    if color.a < materials[in.entry].alpha_cutoff {
+        discard;
+    }
+    return vec4<f32>(color.rgb,1.0);
+}
+// Sorted transparency, blended with premultiplied alpha
+@fragment
+fn fs_transparent(in: VertexOutput) -> @location(0) vec4<f32>{
+    let color=shade(in,1.0);
+    return vec4<f32>(color.rgb*color.a,color.a);
+}
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: f32,
+}
+// Weighted blended order-independent transparency (McGuire and Bavoil 2013)
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    let color=shade(in,1.0);
+    let z=abs(in.view_depth);
+    let weight=color.a*clamp(10.0/(1e-5+pow(z/5.0,2.0)+pow(z/200.0,6.0)),1e-2,3e3);
+    var out: OitOutput;
+    out.accum=vec4<f32>(color.rgb*color.a,color.a)*weight;
+    out.revealage=color.a;
+    return out;
+}
+// View space normals for the ambient occlusion prepass, encoded into 0 to 1
+@fragment
+fn fs_normals(in: VertexOutput) -> @location(0) vec4<f32>{
+    if lod_dithered(in) {
+        discard;
+    }
+    let material=materials[in.entry];
+    let alpha=textureSample(t_base_color, s_base_color, material_uv(material,in.tex_coords), material.layer).a*material.base_color.a;
+    if alpha < material.alpha_cutoff {
+        discard;
+    }
+    let normal=normalize((camera.view*vec4(normalize(in.world_normal),0.0)).xyz);
+    return vec4<f32>(normal*0.5+0.5,1.0);
+}
 
-    let half_dir=normalize(light_dir+view_dir);
-    let spec_str=pow(max(dot(tan_normal,half_dir),0.0),64.0);
-    let spec_color=spec_str*light.color;
-    let result=(amb_color+diff_color+spec_color)*obj_color.xyz;
-    return vec4<f32>(result,obj_color.a);
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) metallic_roughness: vec4<f32>,
+    @location(3) emissive: vec4<f32>,
+}
+// Material inputs for the deferred lighting pass, the material's occlusion goes in the albedo's alpha
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBufferOutput {
+    if lod_dithered(in) {
+        discard;
+    }
+    let sample=sample_material(in);
+    if sample.alpha < materials[in.entry].alpha_cutoff {
+        discard;
+    }
+    var out: GBufferOutput;
+    out.albedo=vec4<f32>(sample.surface.albedo,sample.occlusion);
+    out.normal=vec4<f32>(sample.surface.normal,0.0);
+    out.metallic_roughness=vec4<f32>(sample.surface.metallic,sample.surface.roughness,0.0,0.0);
+    out.emissive=vec4<f32>(sample.emissive,0.0);
+    return out;
+}
+
+// The debug views, which replace the lit color of the opaque standard materials
+fn debug_discarded(in: VertexOutput) -> bool {
+    if lod_dithered(in) {
+        return true;
+    }
+    let material=materials[in.entry];
+    let alpha=textureSample(t_base_color, s_base_color, material_uv(material,in.tex_coords), material.layer).a*material.base_color.a;
+    return alpha < material.alpha_cutoff;
+}
+// An 8x8 checker per uv tile, tinted by the uv so flipped and stretched mappings show
+@fragment
+fn fs_debug_uv_checker(in: VertexOutput) -> @location(0) vec4<f32> {
+    if debug_discarded(in) {
+        discard;
+    }
+    let uv=in.tex_coords;
+    let cell=vec2<i32>(floor(uv*8.0));
+    let checker=f32((cell.x+cell.y)&1);
+    let tint=vec3<f32>(fract(uv),1.0);
+    return vec4<f32>(mix(vec3(0.1),tint,checker),1.0);
+}
+@fragment
+fn fs_debug_albedo(in: VertexOutput) -> @location(0) vec4<f32> {
+    if debug_discarded(in) {
+        discard;
+    }
+    return vec4<f32>(sample_material(in).surface.albedo,1.0);
+}
+// The light on a white surface with the material's normal, metallic and roughness
+@fragment
+fn fs_debug_lighting(in: VertexOutput) -> @location(0) vec4<f32> {
+    if debug_discarded(in) {
+        discard;
+    }
+    let sample=sample_material(in);
+    var surface=sample.surface;
+    surface.albedo=vec3(1.0);
+    surface.f0=mix(vec3(0.04),surface.albedo,surface.metallic);
+    let ambient_occlusion=textureLoad(t_ambient_occlusion,vec2<i32>(in.clip_position.xy),0).r;
+    return vec4<f32>(light_surface(surface,in,sample.occlusion*ambient_occlusion),1.0);
+}
+// The view depth between the near and far planes, logarithmic like the cluster slices
+@fragment
+fn fs_debug_depth(in: VertexOutput) -> @location(0) vec4<f32> {
+    if debug_discarded(in) {
+        discard;
+    }
+    let depth=log(max(in.view_depth,clusters.near)/clusters.near)/log(clusters.far/clusters.near);
+    return vec4<f32>(vec3(clamp(depth,0.0,1.0)),1.0);
+}
+// The base color mip the sampler picks, from red at the full size through to purple at mip 6
+// and smaller, shaded by the albedo's brightness
+@fragment
+fn fs_debug_mip_level(in: VertexOutput) -> @location(0) vec4<f32> {
+    if debug_discarded(in) {
+        discard;
+    }
+    var colors = array<vec3<f32>, 7>(
+        vec3(1.0, 0.0, 0.0),
+        vec3(1.0, 0.5, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(0.0, 1.0, 1.0),
+        vec3(0.0, 0.0, 1.0),
+        vec3(0.6, 0.0, 1.0),
+    );
+    let texels=material_uv(materials[in.entry],in.tex_coords)*vec2<f32>(textureDimensions(t_base_color));
+    let footprint=max(length(dpdx(texels)),length(dpdy(texels)));
+    let level=u32(clamp(floor(log2(max(footprint,1.0))),0.0,6.0));
+    let albedo=sample_material(in).surface.albedo;
+    let brightness=dot(albedo,vec3(0.2126,0.7152,0.0722));
+    return vec4<f32>(colors[level]*(0.5+0.5*brightness),1.0);
 }
- 
@@ -0,0 +1,61 @@
+struct ShadowView {
+    view_proj: mat4x4<f32>,
+    // xyz is the light position and w its range, only set for point lights
+    light_position: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> shadow_view: ShadowView;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return shadow_view.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+struct PointOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+};
+
+@vertex
+fn vs_point(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> PointOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    var out: PointOutput;
+    out.clip_position = shadow_view.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+// Point light shadows store the linear distance to the light so every face compares the same way
+@fragment
+fn fs_point(in: PointOutput) -> @builtin(frag_depth) f32 {
+    let distance = length(in.world_position - shadow_view.light_position.xyz);
+    return distance / shadow_view.light_position.w;
+}
@@ -0,0 +1,112 @@
+// Screen space ambient occlusion from the depth and the view space normals of the prepass,
+// sampling a hemisphere around each pixel, then blurred without bleeding across edges
+struct Ssao {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    // Offsets in the hemisphere around +z, scaled to cluster near the centre
+    kernel: array<vec4<f32>, 32>,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    sample_count: u32,
+}
+struct VertexOutput{
+    @location(0) uv:vec2<f32>,
+    @builtin(position) clip_position:vec4<f32>
+}
+@vertex
+fn vs_main(@builtin(vertex_index) vi:u32)->VertexOutput{
+    var out:VertexOutput;
+    out.uv=vec2<f32>(f32((vi<<1u)&2u), f32(vi&2u),);
+    out.clip_position=vec4<f32>(out.uv*2.0-1.0,0.0,1.0);
+    out.uv.y=1.0-out.uv.y;
+    return out;
+}
+
+@group(0)
+@binding(0)
+var<uniform> ssao: Ssao;
+
+@group(0)
+@binding(1)
+var depth_texture:texture_depth_2d;
+
+@group(0)
+@binding(2)
+var normal_texture:texture_2d<f32>;
+
+// The occlusion being blurred
+@group(0)
+@binding(3)
+var source_texture:texture_2d<f32>;
+
+fn view_position(uv:vec2<f32>,depth:f32)->vec3<f32>{
+    let position=ssao.inv_proj*vec4(uv.x*2.0-1.0,1.0-uv.y*2.0,depth,1.0);
+    return position.xyz/position.w;
+}
+fn load_depth(coords:vec2<i32>)->f32{
+    let size=vec2<i32>(textureDimensions(depth_texture));
+    return textureLoad(depth_texture,clamp(coords,vec2(0),size-1),0);
+}
+
+@fragment
+fn fs_ssao(vs:VertexOutput)->@location(0) vec4<f32>{
+    let coords=vec2<i32>(vs.clip_position.xy);
+    let depth=load_depth(coords);
+    if depth>=1.0 {
+        return vec4(1.0);
+    }
+    let size=vec2<f32>(textureDimensions(depth_texture));
+    let position=view_position(vs.uv,depth);
+    let normal=normalize(textureLoad(normal_texture,coords,0).xyz*2.0-1.0);
+    // Interleaved gradient noise rotates the kernel per pixel, the blur hides the pattern
+    let noise=fract(52.9829189*fract(dot(vs.clip_position.xy,vec2(0.06711056,0.00583715))));
+    let angle=noise*6.2831853;
+    let random=vec3(cos(angle),sin(angle),0.0);
+    let tangent=normalize(random-normal*dot(random,normal));
+    let tbn=mat3x3<f32>(tangent,cross(normal,tangent),normal);
+    var occlusion=0.0;
+    let count=min(ssao.sample_count,32u);
+    for (var i=0u; i<count; i++) {
+        let sample_position=position+tbn*ssao.kernel[i].xyz*ssao.radius;
+        let clip=ssao.proj*vec4(sample_position,1.0);
+        let ndc=clip.xy/clip.w;
+        let uv=vec2(ndc.x*0.5+0.5,0.5-ndc.y*0.5);
+        let scene_depth=load_depth(vec2<i32>(uv*size));
+        let scene_z=view_position(uv,scene_depth).z;
+        // Surfaces far outside the radius don't occlude, so silhouettes don't darken the background
+        let range=smoothstep(0.0,1.0,ssao.radius/max(abs(position.z-scene_z),1e-4));
+        occlusion+=select(0.0,1.0,scene_z>=sample_position.z+ssao.bias)*range;
+    }
+    let ambient=1.0-occlusion/f32(max(count,1u));
+    return vec4(pow(ambient,ssao.intensity));
+}
+
+fn linear_depth(coords:vec2<i32>)->f32{
+    let position=ssao.inv_proj*vec4(0.0,0.0,load_depth(coords),1.0);
+    return -position.z/position.w;
+}
+// A 9 tap gaussian weighted down by the depth difference to the centre
+fn bilateral_blur(coords:vec2<i32>,direction:vec2<i32>)->f32{
+    var weights=array<f32,5>(0.227027,0.1945946,0.1216216,0.054054,0.016216);
+    let size=vec2<i32>(textureDimensions(source_texture));
+    let center_depth=linear_depth(coords);
+    var total=0.0;
+    var weight_sum=0.0;
+    for (var i=-4; i<=4; i++) {
+        let sample_coords=clamp(coords+direction*i,vec2(0),size-1);
+        let difference=abs(linear_depth(sample_coords)-center_depth)/max(center_depth,1e-4);
+        let weight=weights[abs(i)]*exp(-difference*32.0);
+        total+=textureLoad(source_texture,sample_coords,0).r*weight;
+        weight_sum+=weight;
+    }
+    return total/weight_sum;
+}
+@fragment
+fn fs_blur_horizontal(vs:VertexOutput)->@location(0) vec4<f32>{
+    return vec4(bilateral_blur(vec2<i32>(vs.clip_position.xy),vec2(1,0)));
+}
+@fragment
+fn fs_blur_vertical(vs:VertexOutput)->@location(0) vec4<f32>{
+    return vec4(bilateral_blur(vec2<i32>(vs.clip_position.xy),vec2(0,1)));
+}
@@ -0,0 +1,93 @@
+// Temporal anti-aliasing, blending the jittered frame into a reprojected history
+struct Taa {
+    // The jittered frame's inverse, to rebuild positions from depth
+    inv_view_proj: mat4x4<f32>,
+    // Unjittered, so the motion vectors don't move with the jitter
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    // How much of the current frame goes into the history
+    blend: f32,
+    // Set when the history is invalid, e.g. on the first frame
+    reset: u32,
+}
+struct VertexOutput{
+    @location(0) uv:vec2<f32>,
+    @builtin(position) clip_position:vec4<f32>
+}
+@vertex
+fn vs_main(@builtin(vertex_index) vi:u32)->VertexOutput{
+    var out:VertexOutput;
+    out.uv=vec2<f32>(f32((vi<<1u)&2u), f32(vi&2u),);
+    out.clip_position=vec4<f32>(out.uv*2.0-1.0,0.0,1.0);
+    out.uv.y=1.0-out.uv.y;
+    return out;
+}
+
+@group(0)
+@binding(0)
+var<uniform> taa: Taa;
+
+@group(0)
+@binding(1)
+var depth_texture:texture_depth_2d;
+
+@group(1)
+@binding(0)
+var current_texture:texture_2d<f32>;
+
+@group(1)
+@binding(1)
+var history_texture:texture_2d<f32>;
+
+@group(1)
+@binding(2)
+var motion_texture:texture_2d<f32>;
+
+@group(1)
+@binding(3)
+var linear_sampler:sampler;
+
+// The screen space movement of each pixel since the last frame, in uv
+@fragment
+fn fs_motion_vectors(vs:VertexOutput)->@location(0) vec4<f32>{
+    let depth=textureLoad(depth_texture,vec2<i32>(vs.clip_position.xy),0);
+    let ndc=vec4(vs.uv.x*2.0-1.0,1.0-vs.uv.y*2.0,depth,1.0);
+    let world=taa.inv_view_proj*ndc;
+    let position=vec4(world.xyz/world.w,1.0);
+    let current=taa.view_proj*position;
+    let previous=taa.prev_view_proj*position;
+    let velocity=(current.xy/current.w-previous.xy/previous.w)*vec2(0.5,-0.5);
+    return vec4(velocity,0.0,0.0);
+}
+
+fn luminance(color:vec3<f32>)->f32{
+    return dot(color,vec3(0.2126,0.7152,0.0722));
+}
+
+@fragment
+fn fs_resolve(vs:VertexOutput)->@location(0) vec4<f32>{
+    let coords=vec2<i32>(vs.clip_position.xy);
+    let size=vec2<i32>(textureDimensions(current_texture));
+    let current=textureLoad(current_texture,coords,0);
+    // The history is clamped to the colours around the pixel to reject stale samples
+    var low=current.rgb;
+    var high=current.rgb;
+    for (var y=-1; y<=1; y++) {
+        for (var x=-1; x<=1; x++) {
+            let neighbour=textureLoad(current_texture,clamp(coords+vec2(x,y),vec2(0),size-1),0).rgb;
+            low=min(low,neighbour);
+            high=max(high,neighbour);
+        }
+    }
+    let velocity=textureLoad(motion_texture,coords,0).xy;
+    let history_uv=vs.uv-velocity;
+    if taa.reset==1u || any(history_uv<vec2(0.0)) || any(history_uv>vec2(1.0)) {
+        return current;
+    }
+    let history=clamp(textureSampleLevel(history_texture,linear_sampler,history_uv,0.0).rgb,low,high);
+    // Weighting by inverse luminance keeps bright pixels from flickering
+    let current_weight=taa.blend/(1.0+luminance(current.rgb));
+    let history_weight=(1.0-taa.blend)/(1.0+luminance(history));
+    let color=(current.rgb*current_weight+history*history_weight)/(current_weight+history_weight);
+    return vec4(color,current.a);
+}
@@ -0,0 +1,553 @@
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::{hdr::HdrPipeline, shaders, textures::Texture};
+
+const MOTION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+//the jitter repeats after this many frames
+const JITTER_SAMPLES: u32 = 8;
+//how much of the current frame is blended into the history
+const TAA_BLEND: f32 = 0.1;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AntiAliasing {
+    #[default]
+    None,
+    //renders the scene with this many samples per pixel and resolves it into the hdr target
+    Msaa(u32),
+    //a post effect on the tone mapped image
+    Fxaa,
+    //accumulates jittered frames over time, reprojected with motion vectors
+    Taa,
+}
+impl AntiAliasing {
+    pub const ALL: [AntiAliasing; 6] = [
+        AntiAliasing::None,
+        AntiAliasing::Msaa(2),
+        AntiAliasing::Msaa(4),
+        AntiAliasing::Msaa(8),
+        AntiAliasing::Fxaa,
+        AntiAliasing::Taa,
+    ];
+    pub fn sample_count(self) -> u32 {
+        match self {
+            AntiAliasing::Msaa(count) => count,
+            _ => 1,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            AntiAliasing::None => "none",
+            AntiAliasing::Msaa(2) => "msaa2",
+            AntiAliasing::Msaa(4) => "msaa4",
+            AntiAliasing::Msaa(8) => "msaa8",
+            AntiAliasing::Msaa(_) => "msaa",
+            AntiAliasing::Fxaa => "fxaa",
+            AntiAliasing::Taa => "taa",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+//the multisampled colour and depth the scene is drawn into before being resolved
+pub struct Multisampling {
+    sample_count: u32,
+    color: Texture,
+    depth: Texture,
+    depth_layout: wgpu::BindGroupLayout,
+    depth_bind_group: wgpu::BindGroup,
+    depth_resolve_pipeline: wgpu::RenderPipeline,
+}
+impl Multisampling {
+    pub fn new(device: &wgpu::Device, sample_count: u32, width: u32, height: u32) -> Self {
+        let depth_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth Resolve Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: true,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Resolve Pipeline Layout"),
+            bind_group_layouts: &[&depth_layout],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(shaders::descriptor("depth_resolve.wgsl"));
+        let depth_resolve_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Depth Resolve Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: "fs_main",
+                    targets: &[],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            });
+        let (color, depth, depth_bind_group) =
+            Self::create_targets(device, &depth_layout, sample_count, width, height);
+        Self {
+            sample_count,
+            color,
+            depth,
+            depth_layout,
+            depth_bind_group,
+            depth_resolve_pipeline,
+        }
+    }
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+        width: u32,
+        height: u32,
+    ) -> (Texture, Texture, wgpu::BindGroup) {
+        let color = Texture::create_multisampled(
+            device,
+            "Multisampled HDR Texture",
+            width,
+            height,
+            HdrPipeline::FORMAT,
+            sample_count,
+        );
+        let depth = Texture::create_multisampled(
+            device,
+            "Multisampled Depth Texture",
+            width,
+            height,
+            Texture::DEPTH_FORMAT,
+            sample_count,
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Depth Resolve Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth.view),
+            }],
+        });
+        (color, depth, bind_group)
+    }
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.color, self.depth, self.depth_bind_group) =
+            Self::create_targets(device, &self.depth_layout, self.sample_count, width, height);
+    }
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color.view
+    }
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth.view
+    }
+    //the nearest sample of each pixel is written to the single sampled depth the post effects read
+    pub fn resolve_depth(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: target,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.depth_resolve_pipeline);
+        pass.set_bind_group(0, &self.depth_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    blend: f32,
+    reset: u32,
+    _padding: [u32; 2],
+}
+//blends each jittered frame of the hdr target into a history reprojected with per pixel motion vectors
+pub struct TemporalAa {
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    frame_layout: wgpu::BindGroupLayout,
+    resolve_layout: wgpu::BindGroupLayout,
+    motion_pipeline: wgpu::RenderPipeline,
+    resolve_pipeline: wgpu::RenderPipeline,
+    motion: Texture,
+    history: [Texture; 2],
+    frame_bind_group: wgpu::BindGroup,
+    //the first reads history 0 and writes history 1, the second the other way around
+    resolve_bind_groups: [wgpu::BindGroup; 2],
+    current: usize,
+    frame: u32,
+    prev_view_proj: Matrix4<f32>,
+    reset: bool,
+}
+impl TemporalAa {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let frame_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TAA Frame Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let resolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TAA Resolve Layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                texture_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TAA Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TAA Buffer"),
+            contents: bytemuck::cast_slice(&[TaaUniform {
+                inv_view_proj: Matrix4::identity().into(),
+                view_proj: Matrix4::identity().into(),
+                prev_view_proj: Matrix4::identity().into(),
+                blend: TAA_BLEND,
+                reset: 1,
+                _padding: [0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let module = device.create_shader_module(shaders::descriptor("taa.wgsl"));
+        let create_pipeline = |layouts: &[&wgpu::BindGroupLayout],
+                               entry_point: &str,
+                               format: wgpu::TextureFormat,
+                               label: &str| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let motion_pipeline = create_pipeline(
+            &[&frame_layout],
+            "fs_motion_vectors",
+            MOTION_FORMAT,
+            "Motion Vector Pipeline",
+        );
+        let resolve_pipeline = create_pipeline(
+            &[&frame_layout, &resolve_layout],
+            "fs_resolve",
+            HdrPipeline::FORMAT,
+            "TAA Resolve Pipeline",
+        );
+        let (motion, history, frame_bind_group, resolve_bind_groups) = Self::create_targets(
+            device,
+            &frame_layout,
+            &resolve_layout,
+            &uniform_buffer,
+            &sampler,
+            hdr_view,
+            depth_view,
+            width,
+            height,
+        );
+        Self {
+            uniform_buffer,
+            sampler,
+            frame_layout,
+            resolve_layout,
+            motion_pipeline,
+            resolve_pipeline,
+            motion,
+            history,
+            frame_bind_group,
+            resolve_bind_groups,
+            current: 0,
+            frame: 0,
+            prev_view_proj: Matrix4::identity(),
+            reset: true,
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn create_targets(
+        device: &wgpu::Device,
+        frame_layout: &wgpu::BindGroupLayout,
+        resolve_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> (Texture, [Texture; 2], wgpu::BindGroup, [wgpu::BindGroup; 2]) {
+        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        let motion = Texture::create_2d_texture(
+            device,
+            Some("Motion Vector Texture"),
+            width,
+            height,
+            MOTION_FORMAT,
+            usage,
+            wgpu::FilterMode::Nearest,
+        );
+        let history = [0, 1].map(|_| {
+            Texture::create_2d_texture(
+                device,
+                Some("TAA History Texture"),
+                width,
+                height,
+                HdrPipeline::FORMAT,
+                usage | wgpu::TextureUsages::COPY_SRC,
+                wgpu::FilterMode::Linear,
+            )
+        });
+        let frame_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TAA Frame Bind Group"),
+            layout: frame_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+            ],
+        });
+        let resolve_bind_groups = [0, 1].map(|read| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("TAA Resolve Bind Group"),
+                layout: resolve_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(hdr_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&history[read].view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&motion.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+            })
+        });
+        (motion, history, frame_bind_group, resolve_bind_groups)
+    }
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        (
+            self.motion,
+            self.history,
+            self.frame_bind_group,
+            self.resolve_bind_groups,
+        ) = Self::create_targets(
+            device,
+            &self.frame_layout,
+            &self.resolve_layout,
+            &self.uniform_buffer,
+            &self.sampler,
+            hdr_view,
+            depth_view,
+            width,
+            height,
+        );
+        self.reset = true;
+    }
+    //drops the history, for when the image jumps and reprojecting it would smear
+    pub fn reset(&mut self) {
+        self.reset = true;
+    }
+    //the sub pixel offset of the next frame, from the halton 2,3 sequence
+    pub fn next_jitter(&mut self) -> [f32; 2] {
+        self.frame = (self.frame + 1) % JITTER_SAMPLES;
+        let index = self.frame + 1;
+        [halton(index, 2) - 0.5, halton(index, 3) - 0.5]
+    }
+    //takes the jittered matrix the frame is drawn with and the unjittered one the motion is measured with
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        view_proj: Matrix4<f32>,
+        unjittered: Matrix4<f32>,
+    ) {
+        let inv_view_proj = view_proj.invert().unwrap_or(Matrix4::identity());
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TaaUniform {
+                inv_view_proj: inv_view_proj.into(),
+                view_proj: unjittered.into(),
+                prev_view_proj: self.prev_view_proj.into(),
+                blend: TAA_BLEND,
+                reset: self.reset as u32,
+                _padding: [0; 2],
+            }]),
+        );
+        self.prev_view_proj = unjittered;
+        self.reset = false;
+    }
+    //resolves the hdr target against the history and copies the result back into it
+    pub fn process(&mut self, encoder: &mut wgpu::CommandEncoder, hdr_texture: &wgpu::Texture) {
+        let read = self.current;
+        let write = 1 - read;
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Motion Vector Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.motion.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(&self.motion_pipeline);
+            pass.set_bind_group(0, &self.frame_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("TAA Resolve Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.history[write].view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(&self.resolve_pipeline);
+            pass.set_bind_group(0, &self.frame_bind_group, &[]);
+            pass.set_bind_group(1, &self.resolve_bind_groups[read], &[]);
+            pass.draw(0..3, 0..1);
+        }
+        encoder.copy_texture_to_texture(
+            self.history[write].texture.as_image_copy(),
+            hdr_texture.as_image_copy(),
+            self.history[write].texture.size(),
+        );
+        self.current = write;
+    }
+}
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
@@ -0,0 +1,350 @@
+use wgpu::util::DeviceExt;
+
+use crate::{hdr::HdrPipeline, shaders};
+
+const MAX_MIPS: u32 = 7;
+//the smallest mip of the chain is at least this many pixels on its short side
+const MIN_MIP_SIZE: u32 = 4;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    //how much of the blurred image is blended into the scene, zero turns bloom off
+    pub intensity: f32,
+    //the brightness bloom starts at, zero lets everything bloom
+    pub threshold: f32,
+    //the range below the threshold that fades in instead of cutting off
+    pub knee: f32,
+}
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 0.04,
+            threshold: 0.0,
+            knee: 0.5,
+        }
+    }
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    scale: f32,
+}
+//blurs the hdr target down a mip chain and back up, the tone mapping pass blends the result in
+pub struct Bloom {
+    settings: BloomSettings,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    mip_views: Vec<wgpu::TextureView>,
+    //the hdr target followed by each mip, as the source of the pass after it
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+impl Bloom {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let settings = BloomSettings::default();
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[uniform(settings, chain_size(width, height).2)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let module = device.create_shader_module(shaders::descriptor("bloom.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point: &str, blend: wgpu::BlendState, label: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HdrPipeline::FORMAT,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let prefilter_pipeline = create_pipeline(
+            "fs_prefilter",
+            wgpu::BlendState::REPLACE,
+            "Bloom Prefilter Pipeline",
+        );
+        let downsample_pipeline = create_pipeline(
+            "fs_downsample",
+            wgpu::BlendState::REPLACE,
+            "Bloom Downsample Pipeline",
+        );
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let upsample_pipeline = create_pipeline(
+            "fs_upsample",
+            wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            },
+            "Bloom Upsample Pipeline",
+        );
+        let (mip_views, bind_groups) = Self::create_chain(
+            device,
+            &layout,
+            &sampler,
+            &uniform_buffer,
+            hdr_view,
+            width,
+            height,
+        );
+        Self {
+            settings,
+            layout,
+            sampler,
+            uniform_buffer,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            mip_views,
+            bind_groups,
+        }
+    }
+    fn create_chain(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> (Vec<wgpu::TextureView>, Vec<wgpu::BindGroup>) {
+        let (width, height, mip_level_count) = chain_size(width, height);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Bloom Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HdrPipeline::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let mip_views = (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Bloom Mip View"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let bind_groups = std::iter::once(hdr_view)
+            .chain(mip_views.iter())
+            .map(|source| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Bloom Bind Group"),
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: uniform_buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect();
+        (mip_views, bind_groups)
+    }
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        (self.mip_views, self.bind_groups) = Self::create_chain(
+            device,
+            &self.layout,
+            &self.sampler,
+            &self.uniform_buffer,
+            hdr_view,
+            width,
+            height,
+        );
+        self.write_uniform(queue);
+    }
+    //the blurred scene, read by the tone mapping pass
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.mip_views[0]
+    }
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+    pub fn settings(&self) -> BloomSettings {
+        self.settings
+    }
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: BloomSettings) {
+        self.settings = settings;
+        self.write_uniform(queue);
+    }
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform(self.settings, self.mip_views.len() as u32)]),
+        );
+    }
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.settings.intensity <= 0.0 {
+            return;
+        }
+        let mips = self.mip_views.len();
+        for mip in 0..mips {
+            let pipeline = if mip == 0 {
+                &self.prefilter_pipeline
+            } else {
+                &self.downsample_pipeline
+            };
+            self.draw(
+                encoder,
+                pipeline,
+                &self.bind_groups[mip],
+                &self.mip_views[mip],
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                "Bloom Downsample Pass",
+            );
+        }
+        //each mip adds the blurred mip below it on top of its own downsample
+        for mip in (0..mips - 1).rev() {
+            self.draw(
+                encoder,
+                &self.upsample_pipeline,
+                &self.bind_groups[mip + 2],
+                &self.mip_views[mip],
+                wgpu::LoadOp::Load,
+                "Bloom Upsample Pass",
+            );
+        }
+    }
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        label: &str,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+//the chain starts at half the target's size and stops before the short side gets too small
+fn chain_size(width: u32, height: u32) -> (u32, u32, u32) {
+    let width = (width / 2).max(1);
+    let height = (height / 2).max(1);
+    let mut mip_level_count = 1;
+    while mip_level_count < MAX_MIPS && width.min(height) >> mip_level_count >= MIN_MIP_SIZE {
+        mip_level_count += 1;
+    }
+    (width, height, mip_level_count)
+}
+fn uniform(settings: BloomSettings, mips: u32) -> BloomUniform {
+    BloomUniform {
+        threshold: settings.threshold.max(0.0),
+        knee: settings.knee.max(0.0),
+        intensity: settings.intensity.clamp(0.0, 1.0),
+        scale: 1.0 / mips.max(1) as f32,
+    }
+}
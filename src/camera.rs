@@ -1,13 +1,13 @@
 use std::f32::consts::FRAC_PI_2;
 use std::time::Duration;
 
-use cgmath::{self, Matrix, SquareMatrix, Vector3};
+use cgmath::{self, SquareMatrix, Vector3};
 use cgmath::{InnerSpace, Rad};
 use winit::dpi::PhysicalPosition;
 use winit::event::MouseScrollDelta;
 use winit::keyboard::{self, Key, ModifiersState};
 #[rustfmt::skip]
-const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
     0.0, 0.0, 0.5, 0.5,
@@ -50,6 +50,10 @@ pub struct Projection {
     fovy: Rad<f32>,
     znear: f32,
     zfar: f32,
+    width: u32,
+    height: u32,
+    //a sub pixel offset of the whole image, in pixels, used by temporal anti-aliasing
+    jitter: [f32; 2],
 }
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
@@ -58,15 +62,45 @@ impl Projection {
             fovy: fovy.into(),
             znear,
             zfar,
+            width,
+            height,
+            jitter: [0.0; 2],
         }
     }
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
+        self.width = width;
+        self.height = height;
     }
     pub fn calc_matrix(&self) -> cgmath::Matrix4<f32> {
+        let mut proj = self.calc_unjittered_matrix();
+        //shifts clip space x and y by a constant after the divide by w, which is -z
+        proj.z.x -= 2.0 * self.jitter[0] / self.width.max(1) as f32;
+        proj.z.y -= 2.0 * self.jitter[1] / self.height.max(1) as f32;
+        proj
+    }
+    pub fn calc_unjittered_matrix(&self) -> cgmath::Matrix4<f32> {
         //OPENGL_TO_WGPU_MATRIX *
         cgmath::perspective(self.fovy, self.aspect, self.znear, self.zfar)
     }
+    pub fn set_jitter(&mut self, jitter: [f32; 2]) {
+        self.jitter = jitter;
+    }
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
 }
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -97,7 +131,14 @@ impl CameraUniform {
         self.view = view.into();
         self.view_proj = view_proj.into();
         self.inv_proj = proj.invert().unwrap().into();
-        self.inv_view = view.transpose().into();
+        //the full inverse, so positions can be rebuilt from the depth as well as directions
+        self.inv_view = view.invert().unwrap().into();
+    }
+    pub fn view(&self) -> [[f32; 4]; 4] {
+        self.view
+    }
+    pub fn inv_proj(&self) -> [[f32; 4]; 4] {
+        self.inv_proj
     }
 }
 #[derive(PartialEq, Debug, Copy, Clone)]
@@ -0,0 +1,175 @@
+use cgmath::{Matrix4, SquareMatrix};
+
+use crate::shaders;
+use wgpu::util::DeviceExt;
+
+use crate::camera::{CameraUniform, Projection};
+
+//tiles across and down the screen, and depth slices
+pub const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+//lights past this in one cluster are left out of it, the same constant is in cluster.wgsl
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+const CLUSTER_COUNT: u32 = CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2];
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterUniform {
+    inv_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    grid: [u32; 3],
+    enabled: u32,
+    screen_size: [f32; 2],
+    near: f32,
+    far: f32,
+}
+//assigns the point and spot lights to a 3d grid of froxels on the gpu, so the main shader only
+//loops over the lights that can reach the fragment's cluster
+pub struct LightClusters {
+    enabled: bool,
+    uniform_buffer: wgpu::Buffer,
+    cluster_buffer: wgpu::Buffer,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+}
+impl LightClusters {
+    pub fn new(
+        device: &wgpu::Device,
+        light_uniform_buffer: &wgpu::Buffer,
+        light_buffer: &wgpu::Buffer,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cluster Buffer"),
+            contents: bytemuck::cast_slice(&[ClusterUniform {
+                inv_proj: Matrix4::identity().into(),
+                view: Matrix4::identity().into(),
+                grid: CLUSTER_GRID,
+                enabled: 0,
+                screen_size: [1.0; 2],
+                near: 0.1,
+                far: 100.0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        //a count and the light indices for every cluster
+        let cluster_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Light Buffer"),
+            size: (CLUSTER_COUNT * (MAX_LIGHTS_PER_CLUSTER + 1) * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Light Cluster Layout"),
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(2, wgpu::BufferBindingType::Uniform),
+                buffer_entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Cluster Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: cluster_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Cluster Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout);
+        Self {
+            enabled: false,
+            uniform_buffer,
+            cluster_buffer,
+            pipeline_layout,
+            pipeline,
+            bind_group,
+        }
+    }
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+    ) -> wgpu::ComputePipeline {
+        let module = device.create_shader_module(shaders::descriptor("cluster.wgsl"));
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Cluster Pipeline"),
+            layout: Some(pipeline_layout),
+            module: &module,
+            entry_point: "assign_lights",
+        })
+    }
+    //the buffers are bound with the lights, so only the pipeline is rebuilt
+    pub fn reload(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        self.pipeline = shaders::build(device, || {
+            Self::create_pipeline(device, &self.pipeline_layout)
+        })?;
+        Ok(())
+    }
+    //bound with the lights for the main shader
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+    pub fn cluster_buffer(&self) -> &wgpu::Buffer {
+        &self.cluster_buffer
+    }
+    //while disabled the main shader loops over every light
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+    pub fn update(&self, queue: &wgpu::Queue, camera: &CameraUniform, projection: &Projection) {
+        let (width, height) = projection.size();
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[ClusterUniform {
+                inv_proj: camera.inv_proj(),
+                view: camera.view(),
+                grid: CLUSTER_GRID,
+                enabled: self.enabled as u32,
+                screen_size: [width.max(1) as f32, height.max(1) as f32],
+                near: projection.znear(),
+                far: projection.zfar(),
+            }]),
+        );
+    }
+    //has to run after the lights are written and before anything is shaded with them
+    pub fn assign(&self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.enabled {
+            return;
+        }
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Cluster Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(CLUSTER_COUNT.div_ceil(64), 1, 1);
+    }
+}
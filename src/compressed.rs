@@ -0,0 +1,1489 @@
+//KTX2 and DDS containers with pre-built mips, plus CPU decoders for block formats the adapter lacks
+use anyhow::{bail, Context};
+
+//the container contents, uncompressed formats are allowed too
+pub struct CompressedImage {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    //array layers times faces, so a cube map has six
+    pub layers: u32,
+    pub is_cube: bool,
+    //largest level first, every level holds all of its layers back to back
+    pub levels: Vec<Vec<u8>>,
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+impl CompressedImage {
+    //picks the container from the magic bytes, linear only matters for DDS files without a DX10 header
+    pub fn parse(bytes: &[u8], linear: bool) -> anyhow::Result<Self> {
+        let image = if bytes.starts_with(&KTX2_IDENTIFIER) {
+            parse_ktx2(bytes)?
+        } else if bytes.starts_with(DDS_MAGIC) {
+            parse_dds(bytes, linear)?
+        } else {
+            bail!("Not a KTX2 or DDS file");
+        };
+        for level in 0..image.levels.len() as u32 {
+            if image.levels[level as usize].len() < image.level_bytes(level) {
+                bail!("Mip level {} is truncated", level);
+            }
+        }
+        Ok(image)
+    }
+    pub fn level_size(&self, level: u32) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: self.layers,
+        }
+        .mip_level_size(level, wgpu::TextureDimension::D2)
+    }
+    //blocks per row and rows of blocks for a level
+    pub fn level_blocks(&self, level: u32) -> (u32, u32) {
+        let (block_width, block_height) = self.format.block_dimensions();
+        let size = self.level_size(level);
+        (
+            size.width.div_ceil(block_width),
+            size.height.div_ceil(block_height),
+        )
+    }
+    pub fn block_size(&self) -> u32 {
+        self.format.block_copy_size(None).unwrap_or(0)
+    }
+    fn level_bytes(&self, level: u32) -> usize {
+        let (blocks_x, blocks_y) = self.level_blocks(level);
+        (blocks_x * blocks_y * self.block_size() * self.layers) as usize
+    }
+    //the gpu can only take block formats whose first level is a whole number of blocks
+    pub fn supported_by(&self, device: &wgpu::Device) -> bool {
+        let (block_width, block_height) = self.format.block_dimensions();
+        device.features().contains(self.format.required_features())
+            && self.width.is_multiple_of(block_width)
+            && self.height.is_multiple_of(block_height)
+    }
+    //writes every level into a texture created with the image's format, size and level count
+    pub fn upload(&self, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        for (level, data) in self.levels.iter().enumerate() {
+            let level = level as u32;
+            let (blocks_x, blocks_y) = self.level_blocks(level);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture,
+                    mip_level: level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(blocks_x * self.block_size()),
+                    rows_per_image: Some(blocks_y),
+                },
+                self.level_size(level).physical_size(self.format),
+            );
+        }
+    }
+    //serializes an uncompressed rgba image as KTX2, smallest level first as the format asks
+    pub fn to_ktx2(&self) -> anyhow::Result<Vec<u8>> {
+        use wgpu::TextureFormat as F;
+        let (vk_format, type_size) = match self.format {
+            F::Rgba8Unorm => (37, 1),
+            F::Rgba8UnormSrgb => (43, 1),
+            F::Rgba16Float => (97, 2),
+            F::Rgba32Float => (109, 4),
+            format => bail!("Writing {:?} to KTX2 is not supported", format),
+        };
+        let face_count = if self.is_cube { 6 } else { 1 };
+        let layer_count = self.layers / face_count;
+        let dfd = basic_data_format_descriptor(self.format, type_size);
+        let level_count = self.levels.len();
+        let dfd_offset = 80 + 24 * level_count;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&KTX2_IDENTIFIER);
+        for value in [
+            vk_format,
+            type_size,
+            self.width,
+            self.height,
+            0,
+            //a single layer is written as a plain texture rather than an array
+            if layer_count > 1 { layer_count } else { 0 },
+            face_count,
+            level_count as u32,
+            0,
+            dfd_offset as u32,
+            dfd.len() as u32,
+            0,
+            0,
+        ] {
+            bytes.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        //no supercompression global data
+        bytes.extend_from_slice(&[0; 16]);
+        let mut offset = dfd_offset + dfd.len();
+        let mut index = vec![(0, 0); level_count];
+        for level in (0..level_count).rev() {
+            offset = offset.next_multiple_of(16);
+            index[level] = (offset, self.levels[level].len());
+            offset += self.levels[level].len();
+        }
+        for (offset, length) in &index {
+            for value in [*offset as u64, *length as u64, *length as u64] {
+                bytes.extend_from_slice(&u64::to_le_bytes(value));
+            }
+        }
+        bytes.extend_from_slice(&dfd);
+        for level in (0..level_count).rev() {
+            bytes.resize(index[level].0, 0);
+            bytes.extend_from_slice(&self.levels[level]);
+        }
+        Ok(bytes)
+    }
+    //decodes every level to rgba8 keeping the srgb-ness of the source format, signed formats to
+    //rgba8 snorm and bc6h to rgba16 float
+    pub fn decompress(&self) -> anyhow::Result<Self> {
+        if !self.format.is_compressed() {
+            bail!("{:?} is not block compressed", self.format);
+        }
+        let (decoder, format) = block_decoder(self.format)
+            .with_context(|| format!("No CPU decoder for {:?}", self.format))?;
+        let block_size = self.block_size() as usize;
+        let texel_size = format.block_copy_size(None).unwrap_or(4) as usize;
+        let mut levels = Vec::with_capacity(self.levels.len());
+        for (level, data) in self.levels.iter().enumerate() {
+            let size = self.level_size(level as u32);
+            let (blocks_x, blocks_y) = self.level_blocks(level as u32);
+            let (width, height) = (size.width as usize, size.height as usize);
+            let mut decoded = vec![0u8; width * height * texel_size * self.layers as usize];
+            let mut texels = [0u8; 16 * 8];
+            let mut blocks = data.chunks_exact(block_size);
+            for layer in 0..self.layers as usize {
+                let layer_offset = layer * width * height * texel_size;
+                for block_y in 0..blocks_y as usize {
+                    for block_x in 0..blocks_x as usize {
+                        decoder.decode(blocks.next().unwrap(), &mut texels);
+                        //edge blocks hang over small levels
+                        for y in 0..4.min(height - block_y * 4) {
+                            for x in 0..4.min(width - block_x * 4) {
+                                let pixel = (block_y * 4 + y) * width + block_x * 4 + x;
+                                let offset = layer_offset + pixel * texel_size;
+                                let texel = (y * 4 + x) * texel_size;
+                                decoded[offset..offset + texel_size]
+                                    .copy_from_slice(&texels[texel..texel + texel_size]);
+                            }
+                        }
+                    }
+                }
+            }
+            levels.push(decoded);
+        }
+        Ok(Self {
+            format,
+            width: self.width,
+            height: self.height,
+            layers: self.layers,
+            is_cube: self.is_cube,
+            levels,
+        })
+    }
+}
+
+//the Khronos basic descriptor block for four rgba channels of type_size bytes
+fn basic_data_format_descriptor(format: wgpu::TextureFormat, type_size: u32) -> Vec<u8> {
+    const FLOAT: u32 = 0x80;
+    const SIGNED: u32 = 0x40;
+    const LINEAR: u32 = 0x10;
+    let is_float = type_size > 1;
+    let block_size = 24 + 16 * 4;
+    let transfer = if format.is_srgb() { 2 } else { 1 };
+    let mut words = vec![
+        (block_size + 4) as u32,
+        0,
+        2 | (block_size << 16),
+        //rgbsda colour model with bt709 primaries
+        1 | (1 << 8) | (transfer << 16),
+        0,
+        type_size * 4,
+        0,
+    ];
+    for (channel, id) in [0, 1, 2, 15].into_iter().enumerate() {
+        let bits = type_size * 8;
+        let mut channel_type = id;
+        if is_float {
+            channel_type |= FLOAT | SIGNED;
+        } else if id == 15 && format.is_srgb() {
+            channel_type |= LINEAR;
+        }
+        words.push((channel as u32 * bits) | ((bits - 1) << 16) | (channel_type << 24));
+        words.push(0);
+        if is_float {
+            words.extend([(-1.0f32).to_bits(), 1.0f32.to_bits()]);
+        } else {
+            words.extend([0, (1 << bits) - 1]);
+        }
+    }
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> anyhow::Result<u32> {
+    let word = bytes
+        .get(offset..offset + 4)
+        .context("Unexpected end of file")?;
+    Ok(u32::from_le_bytes(word.try_into().unwrap()))
+}
+fn read_u64(bytes: &[u8], offset: usize) -> anyhow::Result<u64> {
+    let word = bytes
+        .get(offset..offset + 8)
+        .context("Unexpected end of file")?;
+    Ok(u64::from_le_bytes(word.try_into().unwrap()))
+}
+
+fn parse_ktx2(bytes: &[u8]) -> anyhow::Result<CompressedImage> {
+    let vk_format = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 20)?;
+    let height = read_u32(bytes, 24)?.max(1);
+    let depth = read_u32(bytes, 28)?;
+    let layer_count = read_u32(bytes, 32)?.max(1);
+    let face_count = read_u32(bytes, 36)?.max(1);
+    //zero asks the loader to generate the mips
+    let level_count = read_u32(bytes, 40)?.max(1);
+    let supercompression = read_u32(bytes, 44)?;
+    if supercompression != 0 {
+        bail!("Supercompressed KTX2 files are not supported");
+    }
+    if depth > 1 {
+        bail!("3D KTX2 textures are not supported");
+    }
+    let format = vk_format_to_wgpu(vk_format)
+        .with_context(|| format!("Unsupported KTX2 vkFormat {}", vk_format))?;
+    //the level index follows the 80 byte header, level 0 first
+    let mut levels = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count as usize {
+        let offset = read_u64(bytes, 80 + level * 24)? as usize;
+        let length = read_u64(bytes, 88 + level * 24)? as usize;
+        let data = bytes
+            .get(offset..offset + length)
+            .with_context(|| format!("Mip level {} is out of bounds", level))?;
+        levels.push(data.to_vec());
+    }
+    Ok(CompressedImage {
+        format,
+        width,
+        height,
+        layers: layer_count * face_count,
+        is_cube: face_count == 6,
+        levels,
+    })
+}
+
+fn vk_format_to_wgpu(vk_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat as F};
+    let format = match vk_format {
+        37 => F::Rgba8Unorm,
+        43 => F::Rgba8UnormSrgb,
+        97 => F::Rgba16Float,
+        109 => F::Rgba32Float,
+        131 | 133 => F::Bc1RgbaUnorm,
+        132 | 134 => F::Bc1RgbaUnormSrgb,
+        135 => F::Bc2RgbaUnorm,
+        136 => F::Bc2RgbaUnormSrgb,
+        137 => F::Bc3RgbaUnorm,
+        138 => F::Bc3RgbaUnormSrgb,
+        139 => F::Bc4RUnorm,
+        140 => F::Bc4RSnorm,
+        141 => F::Bc5RgUnorm,
+        142 => F::Bc5RgSnorm,
+        143 => F::Bc6hRgbUfloat,
+        144 => F::Bc6hRgbFloat,
+        145 => F::Bc7RgbaUnorm,
+        146 => F::Bc7RgbaUnormSrgb,
+        147 => F::Etc2Rgb8Unorm,
+        148 => F::Etc2Rgb8UnormSrgb,
+        149 => F::Etc2Rgb8A1Unorm,
+        150 => F::Etc2Rgb8A1UnormSrgb,
+        151 => F::Etc2Rgba8Unorm,
+        152 => F::Etc2Rgba8UnormSrgb,
+        153 => F::EacR11Unorm,
+        154 => F::EacR11Snorm,
+        155 => F::EacRg11Unorm,
+        156 => F::EacRg11Snorm,
+        157..=184 => {
+            let block = [
+                AstcBlock::B4x4,
+                AstcBlock::B5x4,
+                AstcBlock::B5x5,
+                AstcBlock::B6x5,
+                AstcBlock::B6x6,
+                AstcBlock::B8x5,
+                AstcBlock::B8x6,
+                AstcBlock::B8x8,
+                AstcBlock::B10x5,
+                AstcBlock::B10x6,
+                AstcBlock::B10x8,
+                AstcBlock::B10x10,
+                AstcBlock::B12x10,
+                AstcBlock::B12x12,
+            ][(vk_format - 157) as usize / 2];
+            let channel = if vk_format % 2 == 1 {
+                AstcChannel::Unorm
+            } else {
+                AstcChannel::UnormSrgb
+            };
+            F::Astc { block, channel }
+        }
+        _ => return None,
+    };
+    Some(format)
+}
+
+fn parse_dds(bytes: &[u8], linear: bool) -> anyhow::Result<CompressedImage> {
+    let height = read_u32(bytes, 12)?.max(1);
+    let width = read_u32(bytes, 16)?.max(1);
+    let level_count = read_u32(bytes, 28)?.max(1);
+    let pixel_flags = read_u32(bytes, 80)?;
+    let four_cc = bytes.get(84..88).context("Unexpected end of file")?;
+    let caps2 = read_u32(bytes, 112)?;
+    let (format, layers, is_cube, data_offset) = if four_cc == b"DX10" {
+        let dxgi_format = read_u32(bytes, 128)?;
+        let misc_flag = read_u32(bytes, 136)?;
+        let array_size = read_u32(bytes, 140)?.max(1);
+        let format = dxgi_format_to_wgpu(dxgi_format)
+            .with_context(|| format!("Unsupported DXGI format {}", dxgi_format))?;
+        let is_cube = misc_flag & 0x4 != 0;
+        let layers = if is_cube { array_size * 6 } else { array_size };
+        (format, layers, is_cube, 148)
+    } else {
+        //legacy headers carry no colour space, so follow the same rule as png textures
+        let format = if pixel_flags & 0x4 != 0 {
+            legacy_four_cc_to_wgpu(four_cc).with_context(|| {
+                format!(
+                    "Unsupported DDS FourCC {:?}",
+                    String::from_utf8_lossy(four_cc)
+                )
+            })?
+        } else if read_u32(bytes, 88)? == 32
+            && read_u32(bytes, 92)? == 0xff
+            && read_u32(bytes, 96)? == 0xff00
+            && read_u32(bytes, 100)? == 0xff0000
+        {
+            wgpu::TextureFormat::Rgba8Unorm
+        } else {
+            bail!("Unsupported uncompressed DDS pixel format");
+        };
+        let format = if linear {
+            format
+        } else {
+            format.add_srgb_suffix()
+        };
+        let is_cube = caps2 & 0x200 != 0;
+        (format, if is_cube { 6 } else { 1 }, is_cube, 128)
+    };
+    let mut image = CompressedImage {
+        format,
+        width,
+        height,
+        layers,
+        is_cube,
+        levels: Vec::new(),
+    };
+    //dds stores every mip chain of a layer together, regroup it level by level
+    let level_sizes = (0..level_count)
+        .map(|level| image.level_bytes(level) / layers as usize)
+        .collect::<Vec<_>>();
+    let chain_size: usize = level_sizes.iter().sum();
+    let data = bytes
+        .get(data_offset..data_offset + chain_size * layers as usize)
+        .context("DDS data is truncated")?;
+    image.levels = vec![Vec::new(); level_count as usize];
+    for chain in data.chunks_exact(chain_size) {
+        let mut offset = 0;
+        for (level, size) in level_sizes.iter().enumerate() {
+            image.levels[level].extend_from_slice(&chain[offset..offset + size]);
+            offset += size;
+        }
+    }
+    Ok(image)
+}
+
+fn legacy_four_cc_to_wgpu(four_cc: &[u8]) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as F;
+    let format = match four_cc {
+        b"DXT1" => F::Bc1RgbaUnorm,
+        b"DXT2" | b"DXT3" => F::Bc2RgbaUnorm,
+        b"DXT4" | b"DXT5" => F::Bc3RgbaUnorm,
+        b"ATI1" | b"BC4U" => F::Bc4RUnorm,
+        b"BC4S" => F::Bc4RSnorm,
+        b"ATI2" | b"BC5U" => F::Bc5RgUnorm,
+        b"BC5S" => F::Bc5RgSnorm,
+        //d3d9 format numbers stored in place of a FourCC
+        [113, 0, 0, 0] => F::Rgba16Float,
+        [116, 0, 0, 0] => F::Rgba32Float,
+        _ => return None,
+    };
+    Some(format)
+}
+
+fn dxgi_format_to_wgpu(dxgi_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as F;
+    let format = match dxgi_format {
+        2 => F::Rgba32Float,
+        10 => F::Rgba16Float,
+        28 => F::Rgba8Unorm,
+        29 => F::Rgba8UnormSrgb,
+        71 => F::Bc1RgbaUnorm,
+        72 => F::Bc1RgbaUnormSrgb,
+        74 => F::Bc2RgbaUnorm,
+        75 => F::Bc2RgbaUnormSrgb,
+        77 => F::Bc3RgbaUnorm,
+        78 => F::Bc3RgbaUnormSrgb,
+        80 => F::Bc4RUnorm,
+        81 => F::Bc4RSnorm,
+        83 => F::Bc5RgUnorm,
+        84 => F::Bc5RgSnorm,
+        95 => F::Bc6hRgbUfloat,
+        96 => F::Bc6hRgbFloat,
+        98 => F::Bc7RgbaUnorm,
+        99 => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    };
+    Some(format)
+}
+
+type Rgba8Decoder = fn(&[u8], &mut [[u8; 4]; 16]);
+//decodes one block into 4x4 texels in row order
+enum BlockDecoder {
+    Rgba8(Rgba8Decoder),
+    //the texels are half float bits
+    Rgba16Float(fn(&[u8], &mut [[u16; 4]; 16])),
+}
+impl BlockDecoder {
+    fn decode(&self, block: &[u8], bytes: &mut [u8]) {
+        match self {
+            BlockDecoder::Rgba8(decode) => {
+                let mut texels = [[0; 4]; 16];
+                decode(block, &mut texels);
+                bytes[..64].copy_from_slice(bytemuck::cast_slice(&texels));
+            }
+            BlockDecoder::Rgba16Float(decode) => {
+                let mut texels = [[0; 4]; 16];
+                decode(block, &mut texels);
+                bytes[..128].copy_from_slice(bytemuck::cast_slice(&texels));
+            }
+        }
+    }
+}
+
+//the decoder of a block format and the format it decodes to
+fn block_decoder(format: wgpu::TextureFormat) -> Option<(BlockDecoder, wgpu::TextureFormat)> {
+    use wgpu::TextureFormat as F;
+    match format {
+        F::Bc6hRgbUfloat => {
+            let decoder =
+                BlockDecoder::Rgba16Float(|block, texels| decode_bc6h(block, texels, false));
+            return Some((decoder, F::Rgba16Float));
+        }
+        F::Bc6hRgbFloat => {
+            let decoder =
+                BlockDecoder::Rgba16Float(|block, texels| decode_bc6h(block, texels, true));
+            return Some((decoder, F::Rgba16Float));
+        }
+        _ => {}
+    }
+    //the signed formats keep their values as snorm bytes
+    let snorm: Option<Rgba8Decoder> = match format {
+        F::Bc4RSnorm => Some(|block, texels| {
+            let red = decode_bc4_snorm(block);
+            for (texel, red) in texels.iter_mut().zip(red) {
+                *texel = [red as u8, 0, 0, 127];
+            }
+        }),
+        F::Bc5RgSnorm => Some(|block, texels| {
+            let red = decode_bc4_snorm(&block[..8]);
+            let green = decode_bc4_snorm(&block[8..]);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                *texel = [red[i] as u8, green[i] as u8, 0, 127];
+            }
+        }),
+        F::EacR11Snorm => Some(|block, texels| {
+            let red = decode_eac(block, EacValues::Snorm11);
+            for (texel, red) in texels.iter_mut().zip(red) {
+                *texel = [(red / 8) as i8 as u8, 0, 0, 127];
+            }
+        }),
+        F::EacRg11Snorm => Some(|block, texels| {
+            let red = decode_eac(&block[..8], EacValues::Snorm11);
+            let green = decode_eac(&block[8..], EacValues::Snorm11);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                *texel = [(red[i] / 8) as i8 as u8, (green[i] / 8) as i8 as u8, 0, 127];
+            }
+        }),
+        _ => None,
+    };
+    if let Some(decoder) = snorm {
+        return Some((BlockDecoder::Rgba8(decoder), F::Rgba8Snorm));
+    }
+    let decoder: Rgba8Decoder = match format {
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => |block, texels| decode_bc1(block, texels, false),
+        F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => decode_bc2,
+        F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => decode_bc3,
+        F::Bc4RUnorm => |block, texels| {
+            let red = decode_bc4(block);
+            for (texel, red) in texels.iter_mut().zip(red) {
+                *texel = [red, 0, 0, 255];
+            }
+        },
+        F::Bc5RgUnorm => |block, texels| {
+            let red = decode_bc4(&block[..8]);
+            let green = decode_bc4(&block[8..]);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                *texel = [red[i], green[i], 0, 255];
+            }
+        },
+        F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => decode_bc7,
+        F::Etc2Rgb8Unorm | F::Etc2Rgb8UnormSrgb => {
+            |block, texels| decode_etc2(block, texels, false)
+        }
+        F::Etc2Rgb8A1Unorm | F::Etc2Rgb8A1UnormSrgb => {
+            |block, texels| decode_etc2(block, texels, true)
+        }
+        F::Etc2Rgba8Unorm | F::Etc2Rgba8UnormSrgb => |block, texels| {
+            decode_etc2(&block[8..], texels, false);
+            for (texel, alpha) in texels
+                .iter_mut()
+                .zip(decode_eac(&block[..8], EacValues::Alpha8))
+            {
+                texel[3] = alpha as u8;
+            }
+        },
+        F::EacR11Unorm => |block, texels| {
+            let red = decode_eac(block, EacValues::Unorm11);
+            for (texel, red) in texels.iter_mut().zip(red) {
+                *texel = [(red >> 3) as u8, 0, 0, 255];
+            }
+        },
+        F::EacRg11Unorm => |block, texels| {
+            let red = decode_eac(&block[..8], EacValues::Unorm11);
+            let green = decode_eac(&block[8..], EacValues::Unorm11);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                *texel = [(red[i] >> 3) as u8, (green[i] >> 3) as u8, 0, 255];
+            }
+        },
+        _ => return None,
+    };
+    let format = if format.is_srgb() {
+        F::Rgba8UnormSrgb
+    } else {
+        F::Rgba8Unorm
+    };
+    Some((BlockDecoder::Rgba8(decoder), format))
+}
+
+fn unpack_565(color: u16) -> [u32; 3] {
+    let r = (color >> 11) as u32 & 31;
+    let g = (color >> 5) as u32 & 63;
+    let b = color as u32 & 31;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+//bc2 and bc3 always use the four colour palette
+fn decode_bc1(block: &[u8], texels: &mut [[u8; 4]; 16], four_colors: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (unpack_565(c0), unpack_565(c1));
+    let mix = |wa: u32, wb: u32| {
+        let total = wa + wb;
+        [
+            ((a[0] * wa + b[0] * wb) / total) as u8,
+            ((a[1] * wa + b[1] * wb) / total) as u8,
+            ((a[2] * wa + b[2] * wb) / total) as u8,
+            255,
+        ]
+    };
+    let palette = if four_colors || c0 > c1 {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0; 4]]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(indices >> (2 * i)) as usize & 3];
+    }
+}
+fn decode_bc2(block: &[u8], texels: &mut [[u8; 4]; 16]) {
+    decode_bc1(&block[8..], texels, true);
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = ((alpha >> (4 * i)) & 15) as u8 * 17;
+    }
+}
+fn decode_bc3(block: &[u8], texels: &mut [[u8; 4]; 16]) {
+    decode_bc1(&block[8..], texels, true);
+    for (texel, alpha) in texels.iter_mut().zip(decode_bc4(&block[..8])) {
+        texel[3] = alpha;
+    }
+}
+//the single channel block, also the alpha half of bc3
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    bc4_values(block, block[0] as i32, block[1] as i32, [0, 255]).map(|value| value as u8)
+}
+//signed endpoints, where -128 reads as -127 like -1.0 does
+fn decode_bc4_snorm(block: &[u8]) -> [i8; 16] {
+    let endpoint = |byte: u8| (byte as i8).max(-127) as i32;
+    bc4_values(block, endpoint(block[0]), endpoint(block[1]), [-127, 127]).map(|value| value as i8)
+}
+//eight values between the endpoints, or six plus the extremes when the first isn't larger
+fn bc4_values(block: &[u8], a: i32, b: i32, extremes: [i32; 2]) -> [i32; 16] {
+    let mut palette = [a, b, 0, 0, 0, 0, extremes[0], extremes[1]];
+    if a > b {
+        for i in 1..7 {
+            palette[i as usize + 1] = ((7 - i) * a + i * b) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i as usize + 1] = ((5 - i) * a + i * b) / 5;
+        }
+    }
+    let mut bytes = [0; 8];
+    bytes[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bytes);
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(indices >> (3 * i)) as usize & 7];
+    }
+    values
+}
+
+fn bits(value: u64, low: u32, count: u32) -> i32 {
+    ((value >> low) & ((1 << count) - 1)) as i32
+}
+fn extend(value: i32, count: u32) -> i32 {
+    (value << (8 - count)) | (value >> (2 * count - 8))
+}
+const ETC_MODIFIERS: [[i32; 4]; 8] = [
+    [2, 8, -2, -8],
+    [5, 17, -5, -17],
+    [9, 29, -9, -29],
+    [13, 42, -13, -42],
+    [18, 60, -18, -60],
+    [24, 80, -24, -80],
+    [33, 106, -33, -106],
+    [47, 183, -47, -183],
+];
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+fn offset_color(color: [i32; 3], offset: i32) -> [u8; 4] {
+    [
+        (color[0] + offset).clamp(0, 255) as u8,
+        (color[1] + offset).clamp(0, 255) as u8,
+        (color[2] + offset).clamp(0, 255) as u8,
+        255,
+    ]
+}
+//etc1 plus the T, H and planar modes hidden in overflowing differential colours,
+//punch-through reuses the differential bit as an opaque flag
+fn decode_etc2(block: &[u8], texels: &mut [[u8; 4]; 16], punch_through: bool) {
+    let v = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let diff = bits(v, 33, 1) == 1;
+    let opaque = !punch_through || diff;
+    //pixel indices run down the columns
+    let index = |x: usize, y: usize| {
+        let i = (x * 4 + y) as u32;
+        ((bits(v, 16 + i, 1) << 1) | bits(v, i, 1)) as usize
+    };
+    let (r, g, b) = (bits(v, 59, 5), bits(v, 51, 5), bits(v, 43, 5));
+    let delta = |low| (bits(v, low, 3) << 29) >> 29;
+    let (dr, dg, db) = (delta(56), delta(48), delta(40));
+    let paint = if !punch_through && !diff {
+        None
+    } else if !(0..32).contains(&(r + dr)) {
+        //T mode
+        let c1 = [
+            (bits(v, 59, 2) << 2) | bits(v, 56, 2),
+            bits(v, 52, 4),
+            bits(v, 48, 4),
+        ];
+        let c2 = [bits(v, 44, 4), bits(v, 40, 4), bits(v, 36, 4)];
+        let c1 = c1.map(|c| extend(c, 4));
+        let c2 = c2.map(|c| extend(c, 4));
+        let d = ETC_DISTANCES[((bits(v, 34, 2) << 1) | bits(v, 32, 1)) as usize];
+        Some([
+            offset_color(c1, 0),
+            offset_color(c2, d),
+            offset_color(c2, 0),
+            offset_color(c2, -d),
+        ])
+    } else if !(0..32).contains(&(g + dg)) {
+        //H mode
+        let c1 = [
+            bits(v, 59, 4),
+            (bits(v, 56, 3) << 1) | bits(v, 52, 1),
+            (bits(v, 51, 1) << 3) | bits(v, 47, 3),
+        ];
+        let c2 = [bits(v, 43, 4), bits(v, 39, 4), bits(v, 35, 4)];
+        let order = |c: [i32; 3]| (c[0] << 8) | (c[1] << 4) | c[2];
+        let d = ETC_DISTANCES[((bits(v, 34, 1) << 2)
+            | (bits(v, 32, 1) << 1)
+            | (order(c1) >= order(c2)) as i32) as usize];
+        let c1 = c1.map(|c| extend(c, 4));
+        let c2 = c2.map(|c| extend(c, 4));
+        Some([
+            offset_color(c1, d),
+            offset_color(c1, -d),
+            offset_color(c2, d),
+            offset_color(c2, -d),
+        ])
+    } else if !(0..32).contains(&(b + db)) {
+        //planar mode, always opaque
+        let o = [
+            extend(bits(v, 57, 6), 6),
+            extend((bits(v, 56, 1) << 6) | bits(v, 49, 6), 7),
+            extend(
+                (bits(v, 48, 1) << 5) | (bits(v, 43, 2) << 3) | bits(v, 39, 3),
+                6,
+            ),
+        ];
+        let h = [
+            extend((bits(v, 34, 5) << 1) | bits(v, 32, 1), 6),
+            extend(bits(v, 25, 7), 7),
+            extend(bits(v, 19, 6), 6),
+        ];
+        let vertical = [
+            extend(bits(v, 13, 6), 6),
+            extend(bits(v, 6, 7), 7),
+            extend(bits(v, 0, 6), 6),
+        ];
+        for y in 0..4 {
+            for x in 0..4 {
+                let channel = |c: usize| {
+                    ((x * (h[c] - o[c]) + y * (vertical[c] - o[c]) + 4 * o[c] + 2) >> 2)
+                        .clamp(0, 255) as u8
+                };
+                texels[(y * 4 + x) as usize] = [channel(0), channel(1), channel(2), 255];
+            }
+        }
+        return;
+    } else {
+        None
+    };
+    if let Some(paint) = paint {
+        for y in 0..4 {
+            for x in 0..4 {
+                let index = index(x, y);
+                texels[y * 4 + x] = if !opaque && index == 2 {
+                    [0; 4]
+                } else {
+                    paint[index]
+                };
+            }
+        }
+        return;
+    }
+    //individual or differential sub-blocks
+    let (base1, base2) = if diff {
+        (
+            [r, g, b].map(|c| extend(c, 5)),
+            [r + dr, g + dg, b + db].map(|c| extend(c, 5)),
+        )
+    } else {
+        (
+            [bits(v, 60, 4), bits(v, 52, 4), bits(v, 44, 4)].map(|c| extend(c, 4)),
+            [bits(v, 56, 4), bits(v, 48, 4), bits(v, 40, 4)].map(|c| extend(c, 4)),
+        )
+    };
+    let tables = [bits(v, 37, 3) as usize, bits(v, 34, 3) as usize];
+    let flip = bits(v, 32, 1) == 1;
+    for y in 0..4 {
+        for x in 0..4 {
+            let second = if flip { y >= 2 } else { x >= 2 };
+            let (base, table) = if second {
+                (base2, tables[1])
+            } else {
+                (base1, tables[0])
+            };
+            let index = index(x, y);
+            texels[y * 4 + x] = match index {
+                2 if !opaque => [0; 4],
+                0 if !opaque => offset_color(base, 0),
+                _ => offset_color(base, ETC_MODIFIERS[table][index]),
+            };
+        }
+    }
+}
+//what an eac block holds
+#[derive(Clone, Copy, PartialEq)]
+enum EacValues {
+    //the 8 bit alpha half of etc2 rgba8
+    Alpha8,
+    //0 to 2047
+    Unorm11,
+    //-1023 to 1023
+    Snorm11,
+}
+fn decode_eac(block: &[u8], kind: EacValues) -> [i32; 16] {
+    let v = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let base = bits(v, 56, 8);
+    let multiplier = bits(v, 52, 4);
+    let table = EAC_MODIFIERS[bits(v, 48, 4) as usize];
+    let mut values = [0; 16];
+    for x in 0..4 {
+        for y in 0..4 {
+            let modifier = table[bits(v, 45 - 3 * (x * 4 + y) as u32, 3) as usize];
+            values[y * 4 + x] = match kind {
+                EacValues::Alpha8 => (base + modifier * multiplier).clamp(0, 255),
+                EacValues::Unorm11 if multiplier == 0 => (base * 8 + 4 + modifier).clamp(0, 2047),
+                EacValues::Unorm11 => (base * 8 + 4 + modifier * multiplier * 8).clamp(0, 2047),
+                EacValues::Snorm11 => {
+                    //the base is a signed byte, -128 reads as -127
+                    let base = (base as u8 as i8).max(-127) as i32;
+                    let modifier = if multiplier == 0 {
+                        modifier
+                    } else {
+                        modifier * multiplier * 8
+                    };
+                    (base * 8 + modifier).clamp(-1023, 1023)
+                }
+            };
+        }
+    }
+    values
+}
+//reads a 128 bit block from its lowest bit up
+struct BlockBits {
+    bits: u128,
+    position: u32,
+}
+impl BlockBits {
+    fn new(block: &[u8]) -> Self {
+        Self {
+            bits: u128::from_le_bytes(block[..16].try_into().unwrap()),
+            position: 0,
+        }
+    }
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+//the interpolation weights of 2, 3 and 4 bit indices, out of 64
+const WEIGHTS_2: [i32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [i32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+fn weight(index_bits: u32, index: u32) -> i32 {
+    match index_bits {
+        2 => WEIGHTS_2[index as usize],
+        3 => WEIGHTS_3[index as usize],
+        _ => WEIGHTS_4[index as usize],
+    }
+}
+fn interpolate(a: i32, b: i32, weight: i32) -> i32 {
+    ((64 - weight) * a + weight * b + 32) >> 6
+}
+//the subset of every texel in the two subset partitions, one bit per texel, shared with bc6h
+const PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+//the three subset partitions, two bits per texel
+const PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+//the texel whose index drops its top bit, for the second subset of two and the second and
+//third of three. the first subset's is always texel 0
+const ANCHORS_2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+const ANCHORS_3_SECOND: [usize; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+const ANCHORS_3_THIRD: [usize; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+fn subset(subsets: usize, partition: usize, texel: usize) -> usize {
+    match subsets {
+        1 => 0,
+        2 => (PARTITIONS_2[partition] >> texel) as usize & 1,
+        _ => (PARTITIONS_3[partition] >> (2 * texel)) as usize & 3,
+    }
+}
+fn is_anchor(subsets: usize, partition: usize, texel: usize) -> bool {
+    texel == 0
+        || match subsets {
+            2 => texel == ANCHORS_2[partition],
+            3 => texel == ANCHORS_3_SECOND[partition] || texel == ANCHORS_3_THIRD[partition],
+            _ => false,
+        }
+}
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    //a p-bit per endpoint, or per subset when shared
+    endpoint_p_bits: bool,
+    shared_p_bits: bool,
+    index_bits: u32,
+    //the separate alpha indices of modes 4 and 5
+    secondary_index_bits: u32,
+}
+const fn bc7_mode(
+    subsets: usize,
+    [partition_bits, rotation_bits, selection_bits]: [u32; 3],
+    [color_bits, alpha_bits]: [u32; 2],
+    [endpoint_p_bits, shared_p_bits]: [bool; 2],
+    [index_bits, secondary_index_bits]: [u32; 2],
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        selection_bits,
+        color_bits,
+        alpha_bits,
+        endpoint_p_bits,
+        shared_p_bits,
+        index_bits,
+        secondary_index_bits,
+    }
+}
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, [4, 0, 0], [4, 0], [true, false], [3, 0]),
+    bc7_mode(2, [6, 0, 0], [6, 0], [false, true], [3, 0]),
+    bc7_mode(3, [6, 0, 0], [5, 0], [false, false], [2, 0]),
+    bc7_mode(2, [6, 0, 0], [7, 0], [true, false], [2, 0]),
+    bc7_mode(1, [0, 2, 1], [5, 6], [false, false], [2, 3]),
+    bc7_mode(1, [0, 2, 0], [7, 8], [false, false], [2, 2]),
+    bc7_mode(1, [0, 0, 0], [7, 7], [true, false], [4, 0]),
+    bc7_mode(2, [6, 0, 0], [5, 5], [true, false], [2, 0]),
+];
+//the mode is the number of zero bits before the first one, blocks without a one are transparent black
+fn decode_bc7(block: &[u8], texels: &mut [[u8; 4]; 16]) {
+    let mut bits = BlockBits::new(block);
+    let Some(mode) = (0..8).find(|_| bits.read(1) == 1) else {
+        *texels = [[0; 4]; 16];
+        return;
+    };
+    let mode = &BC7_MODES[mode];
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let selection = bits.read(mode.selection_bits);
+    //every channel of every endpoint, then the p-bits, then the indices
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..4 {
+        let channel_bits = if channel < 3 {
+            mode.color_bits
+        } else {
+            mode.alpha_bits
+        };
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[channel] = bits.read(channel_bits);
+        }
+    }
+    let mut p_bits = [0; 6];
+    if mode.endpoint_p_bits {
+        for p_bit in &mut p_bits[..endpoint_count] {
+            *p_bit = bits.read(1);
+        }
+    } else if mode.shared_p_bits {
+        for subset in 0..mode.subsets {
+            let p_bit = bits.read(1);
+            p_bits[subset * 2] = p_bit;
+            p_bits[subset * 2 + 1] = p_bit;
+        }
+    }
+    let has_p_bits = (mode.endpoint_p_bits || mode.shared_p_bits) as u32;
+    for (endpoint, p_bit) in endpoints[..endpoint_count].iter_mut().zip(p_bits) {
+        for (channel, value) in endpoint.iter_mut().enumerate() {
+            let channel_bits = if channel < 3 {
+                mode.color_bits
+            } else {
+                mode.alpha_bits
+            };
+            if channel_bits == 0 {
+                *value = 255;
+                continue;
+            }
+            //shift the p-bit in and repeat the top bits into the bottom ones
+            let count = channel_bits + has_p_bits;
+            let with_p_bit = (*value << has_p_bits) | p_bit;
+            *value = (with_p_bit << (8 - count)) | (with_p_bit >> (2 * count - 8));
+        }
+    }
+    let read_indices = |bits: &mut BlockBits, index_bits: u32, subsets: usize| {
+        let mut indices = [0; 16];
+        if index_bits == 0 {
+            return indices;
+        }
+        for (texel, index) in indices.iter_mut().enumerate() {
+            *index = bits.read(index_bits - is_anchor(subsets, partition, texel) as u32);
+        }
+        indices
+    };
+    let primary = read_indices(&mut bits, mode.index_bits, mode.subsets);
+    let secondary = read_indices(&mut bits, mode.secondary_index_bits, 1);
+    //the index selection bit swaps which indices the color and alpha use
+    let ((color_bits, color), (alpha_bits, alpha)) = match (mode.secondary_index_bits, selection) {
+        (0, _) => ((mode.index_bits, primary), (mode.index_bits, primary)),
+        (secondary_bits, 0) => ((mode.index_bits, primary), (secondary_bits, secondary)),
+        (secondary_bits, _) => ((secondary_bits, secondary), (mode.index_bits, primary)),
+    };
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let subset = subset(mode.subsets, partition, i);
+        let [a, b] = [endpoints[subset * 2], endpoints[subset * 2 + 1]];
+        for channel in 0..4 {
+            let (index_bits, index) = if channel < 3 {
+                (color_bits, color[i])
+            } else {
+                (alpha_bits, alpha[i])
+            };
+            let weight = weight(index_bits, index);
+            texel[channel] = interpolate(a[channel] as i32, b[channel] as i32, weight) as u8;
+        }
+        if rotation > 0 {
+            texel.swap(rotation as usize - 1, 3);
+        }
+    }
+}
+
+//an endpoint's channel: w and x are the first subset's, y and z the second's
+type Bc6hField = (usize, usize);
+const RW: Bc6hField = (0, 0);
+const GW: Bc6hField = (0, 1);
+const BW: Bc6hField = (0, 2);
+const RX: Bc6hField = (1, 0);
+const GX: Bc6hField = (1, 1);
+const BX: Bc6hField = (1, 2);
+const RY: Bc6hField = (2, 0);
+const GY: Bc6hField = (2, 1);
+const BY: Bc6hField = (2, 2);
+const RZ: Bc6hField = (3, 0);
+const GZ: Bc6hField = (3, 1);
+const BZ: Bc6hField = (3, 2);
+struct Bc6hMode {
+    mode_bits: u32,
+    //the other endpoints are stored as deltas from the first
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    //runs of bits after the mode, as the field, its lowest bit and the count
+    layout: &'static [(Bc6hField, u32, u32)],
+}
+//the ten two subset modes, then the four with one, from the BC6H format's mode table
+#[rustfmt::skip]
+const BC6H_MODES: [Bc6hMode; 14] = [
+    Bc6hMode {
+        mode_bits: 0b00,
+        transformed: true,
+        endpoint_bits: 10,
+        delta_bits: [5, 5, 5],
+        layout: &[
+            (GY, 4, 1), (BY, 4, 1), (BZ, 4, 1), (RW, 0, 10), (GW, 0, 10), (BW, 0, 10),
+            (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4),
+            (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b01,
+        transformed: true,
+        endpoint_bits: 7,
+        delta_bits: [6, 6, 6],
+        layout: &[
+            (GY, 5, 1), (GZ, 4, 1), (GZ, 5, 1), (RW, 0, 7), (BZ, 0, 1), (BZ, 1, 1),
+            (BY, 4, 1), (GW, 0, 7), (BY, 5, 1), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 7),
+            (BZ, 3, 1), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 6),
+            (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b00010,
+        transformed: true,
+        endpoint_bits: 11,
+        delta_bits: [5, 4, 4],
+        layout: &[
+            (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 5), (RW, 10, 1), (GY, 0, 4),
+            (GX, 0, 4), (GW, 10, 1), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 4), (BW, 10, 1),
+            (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b00110,
+        transformed: true,
+        endpoint_bits: 11,
+        delta_bits: [4, 5, 4],
+        layout: &[
+            (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 10, 1), (GZ, 4, 1),
+            (GY, 0, 4), (GX, 0, 5), (GW, 10, 1), (GZ, 0, 4), (BX, 0, 4), (BW, 10, 1),
+            (BZ, 1, 1), (BY, 0, 4), (RY, 0, 4), (BZ, 0, 1), (BZ, 2, 1), (RZ, 0, 4),
+            (GY, 4, 1), (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b01010,
+        transformed: true,
+        endpoint_bits: 11,
+        delta_bits: [4, 4, 5],
+        layout: &[
+            (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 10, 1), (BY, 4, 1),
+            (GY, 0, 4), (GX, 0, 4), (GW, 10, 1), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5),
+            (BW, 10, 1), (BY, 0, 4), (RY, 0, 4), (BZ, 1, 1), (BZ, 2, 1), (RZ, 0, 4),
+            (BZ, 4, 1), (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b01110,
+        transformed: true,
+        endpoint_bits: 9,
+        delta_bits: [5, 5, 5],
+        layout: &[
+            (RW, 0, 9), (BY, 4, 1), (GW, 0, 9), (GY, 4, 1), (BW, 0, 9), (BZ, 4, 1),
+            (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4),
+            (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b10010,
+        transformed: true,
+        endpoint_bits: 8,
+        delta_bits: [6, 5, 5],
+        layout: &[
+            (RW, 0, 8), (GZ, 4, 1), (BY, 4, 1), (GW, 0, 8), (BZ, 2, 1), (GY, 4, 1),
+            (BW, 0, 8), (BZ, 3, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 5),
+            (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b10110,
+        transformed: true,
+        endpoint_bits: 8,
+        delta_bits: [5, 6, 5],
+        layout: &[
+            (RW, 0, 8), (BZ, 0, 1), (BY, 4, 1), (GW, 0, 8), (GY, 5, 1), (GY, 4, 1),
+            (BW, 0, 8), (GZ, 5, 1), (BZ, 4, 1), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4),
+            (GX, 0, 6), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5),
+            (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b11010,
+        transformed: true,
+        endpoint_bits: 8,
+        delta_bits: [5, 5, 6],
+        layout: &[
+            (RW, 0, 8), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 8), (BY, 5, 1), (GY, 4, 1),
+            (BW, 0, 8), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4),
+            (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4), (RY, 0, 5),
+            (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b11110,
+        transformed: false,
+        endpoint_bits: 6,
+        delta_bits: [6, 6, 6],
+        layout: &[
+            (RW, 0, 6), (GZ, 4, 1), (BZ, 0, 1), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 6),
+            (GY, 5, 1), (BY, 5, 1), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 6), (GZ, 5, 1),
+            (BZ, 3, 1), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 6),
+            (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b00011,
+        transformed: false,
+        endpoint_bits: 10,
+        delta_bits: [10, 10, 10],
+        layout: &[
+            (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 10), (GX, 0, 10), (BX, 0, 10),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b00111,
+        transformed: true,
+        endpoint_bits: 11,
+        delta_bits: [9, 9, 9],
+        layout: &[
+            (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 9), (RW, 10, 1), (GX, 0, 9),
+            (GW, 10, 1), (BX, 0, 9), (BW, 10, 1),
+        ],
+    },
+    //the high bits of the first endpoint come in reverse order in the last two modes
+    Bc6hMode {
+        mode_bits: 0b01011,
+        transformed: true,
+        endpoint_bits: 12,
+        delta_bits: [8, 8, 8],
+        layout: &[
+            (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 8), (RW, 11, 1), (RW, 10, 1),
+            (GX, 0, 8), (GW, 11, 1), (GW, 10, 1), (BX, 0, 8), (BW, 11, 1), (BW, 10, 1),
+        ],
+    },
+    Bc6hMode {
+        mode_bits: 0b01111,
+        transformed: true,
+        endpoint_bits: 16,
+        delta_bits: [4, 4, 4],
+        layout: &[
+            (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 15, 1), (RW, 14, 1),
+            (RW, 13, 1), (RW, 12, 1), (RW, 11, 1), (RW, 10, 1), (GX, 0, 4), (GW, 15, 1),
+            (GW, 14, 1), (GW, 13, 1), (GW, 12, 1), (GW, 11, 1), (GW, 10, 1), (BX, 0, 4),
+            (BW, 15, 1), (BW, 14, 1), (BW, 13, 1), (BW, 12, 1), (BW, 11, 1), (BW, 10, 1),
+        ],
+    },
+];
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    (value << (32 - bits)) >> (32 - bits)
+}
+//scales an endpoint to the full 16 bits, or 15 and a sign
+fn bc6h_unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if !signed {
+        return match value {
+            _ if bits >= 15 => value,
+            0 => 0,
+            _ if value == (1 << bits) - 1 => 0xFFFF,
+            _ => ((value << 16) + 0x8000) >> bits,
+        };
+    }
+    if bits >= 16 {
+        return value;
+    }
+    let magnitude = match value.abs() {
+        0 => 0,
+        magnitude if magnitude >= (1 << (bits - 1)) - 1 => 0x7FFF,
+        magnitude => ((magnitude << 15) + 0x4000) >> (bits - 1),
+    };
+    magnitude * value.signum()
+}
+//the interpolated value scaled into a half float's bits
+fn bc6h_finish(value: i32, signed: bool) -> u16 {
+    if !signed {
+        ((value * 31) >> 6) as u16
+    } else if value < 0 {
+        0x8000 | ((-value * 31) >> 5) as u16
+    } else {
+        ((value * 31) >> 5) as u16
+    }
+}
+//reserved modes decode to black
+fn decode_bc6h(block: &[u8], texels: &mut [[u16; 4]; 16], signed: bool) {
+    const ONE: u16 = 0x3C00;
+    let mut bits = BlockBits::new(block);
+    let low_bits = bits.read(2);
+    let mode_bits = if low_bits < 2 {
+        low_bits
+    } else {
+        low_bits | (bits.read(3) << 2)
+    };
+    let Some(index) = BC6H_MODES
+        .iter()
+        .position(|mode| mode.mode_bits == mode_bits)
+    else {
+        *texels = [[0, 0, 0, ONE]; 16];
+        return;
+    };
+    let mode = &BC6H_MODES[index];
+    let subsets = if index < 10 { 2 } else { 1 };
+    let mut endpoints = [[0i32; 3]; 4];
+    for &((endpoint, channel), low, count) in mode.layout {
+        endpoints[endpoint][channel] |= (bits.read(count) as i32) << low;
+    }
+    let partition = if subsets == 2 {
+        bits.read(5) as usize
+    } else {
+        0
+    };
+    let endpoint_bits = mode.endpoint_bits;
+    for channel in 0..3 {
+        if signed {
+            endpoints[0][channel] = sign_extend(endpoints[0][channel], endpoint_bits);
+        }
+        let base = endpoints[0][channel];
+        for endpoint in &mut endpoints[1..subsets * 2] {
+            if mode.transformed {
+                let delta = sign_extend(endpoint[channel], mode.delta_bits[channel]);
+                endpoint[channel] = (base + delta) & ((1 << endpoint_bits) - 1);
+            }
+            if signed {
+                endpoint[channel] = sign_extend(endpoint[channel], endpoint_bits);
+            }
+        }
+    }
+    for endpoint in &mut endpoints {
+        for value in endpoint {
+            *value = bc6h_unquantize(*value, endpoint_bits, signed);
+        }
+    }
+    let index_bits = if subsets == 2 { 3 } else { 4 };
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let index = bits.read(index_bits - is_anchor(subsets, partition, i) as u32);
+        let subset = subset(subsets, partition, i);
+        let weight = weight(index_bits, index);
+        for channel in 0..3 {
+            let (a, b) = (
+                endpoints[subset * 2][channel],
+                endpoints[subset * 2 + 1][channel],
+            );
+            texel[channel] = bc6h_finish(interpolate(a, b, weight), signed);
+        }
+        texel[3] = ONE;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baked_cube_loads_back() {
+        //a 2x2 cube with its 1x1 mip, every texel a different value
+        let levels = [2usize, 1]
+            .iter()
+            .map(|size| {
+                (0..6 * size * size * 8)
+                    .map(|byte| (byte * 7 + size) as u8)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let cube = CompressedImage {
+            format: wgpu::TextureFormat::Rgba16Float,
+            width: 2,
+            height: 2,
+            layers: 6,
+            is_cube: true,
+            levels: levels.clone(),
+        };
+        let image = CompressedImage::parse(&cube.to_ktx2().unwrap(), true).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Rgba16Float);
+        assert_eq!((image.width, image.height, image.layers), (2, 2, 6));
+        assert!(image.is_cube);
+        assert_eq!(image.levels, levels);
+    }
+    //packs runs of (value, bit count) from the block's lowest bit up
+    fn pack_block(runs: &[(u32, u32)]) -> [u8; 16] {
+        let mut bits = 0u128;
+        let mut position = 0;
+        for &(value, count) in runs {
+            bits |= (value as u128) << position;
+            position += count;
+        }
+        assert!(position <= 128);
+        bits.to_le_bytes()
+    }
+    #[test]
+    fn bc7_mode_6_interpolates_its_endpoints() {
+        //black and white endpoints, texel i takes index i and the anchor texel 0 has three bits
+        let mut runs = vec![(1 << 6, 7), (0, 7), (127, 7), (0, 7), (127, 7)];
+        runs.extend([(0, 7), (127, 7), (0, 7), (127, 7), (0, 1), (1, 1), (0, 3)]);
+        runs.extend((1..16).map(|index| (index, 4)));
+        let mut texels = [[0; 4]; 16];
+        decode_bc7(&pack_block(&runs), &mut texels);
+        for (i, texel) in texels.iter().enumerate() {
+            let value = ((WEIGHTS_4[i] * 255 + 32) >> 6) as u8;
+            assert_eq!(*texel, [value; 4]);
+        }
+    }
+    #[test]
+    fn bc7_mode_1_splits_its_partition() {
+        //partition 13 puts the bottom two rows in the second subset, red on top and blue below,
+        //the shared p-bits set the low bit of every channel
+        let mut runs = vec![(0b10, 2), (13, 6)];
+        runs.extend([(63, 6), (63, 6), (0, 6), (0, 6)]);
+        runs.extend([(0, 6), (0, 6), (0, 6), (0, 6)]);
+        runs.extend([(0, 6), (0, 6), (63, 6), (63, 6)]);
+        runs.extend([(1, 1), (1, 1), (0, 46)]);
+        let mut texels = [[0; 4]; 16];
+        decode_bc7(&pack_block(&runs), &mut texels);
+        assert_eq!(texels[..8], [[255, 2, 2, 255]; 8]);
+        assert_eq!(texels[8..], [[2, 2, 255, 255]; 8]);
+    }
+    #[test]
+    fn bc6h_decodes_to_half_floats() {
+        //mode 11 with both endpoints at 495, which unquantizes to 1.0
+        let runs = [(0b00011, 5), (495, 10), (495, 10), (495, 10)];
+        let runs = [&runs[..], &[(495, 10), (495, 10), (495, 10)]].concat();
+        let image = CompressedImage {
+            format: wgpu::TextureFormat::Bc6hRgbUfloat,
+            width: 4,
+            height: 4,
+            layers: 1,
+            is_cube: false,
+            levels: vec![pack_block(&runs).to_vec()],
+        };
+        let decoded = image.decompress().unwrap();
+        assert_eq!(decoded.format, wgpu::TextureFormat::Rgba16Float);
+        let texels: &[u16] = bytemuck::cast_slice(&decoded.levels[0]);
+        assert_eq!(texels, [0x3C00; 64]);
+    }
+    #[test]
+    fn bc6h_mode_1_adds_its_deltas() {
+        //the first endpoint at 495 and the second subset's first 16 below it, with its sign and
+        //green and blue top bits in the mode's header and partition 13's bottom rows using it
+        let runs = [
+            (0b00, 2),
+            (1, 1),
+            (1, 1),
+            (0, 1),
+            (495, 10),
+            (495, 10),
+            (495, 10),
+            (0, 30),
+            (0b10000, 5),
+            (0, 7),
+            (13, 5),
+        ];
+        let mut texels = [[0; 4]; 16];
+        decode_bc6h(&pack_block(&runs), &mut texels, false);
+        assert_eq!(texels[..8], [[0x3C00; 4]; 8]);
+        assert_eq!(texels[8..], [[0x3A10, 0x3A10, 0x3A10, 0x3C00]; 8]);
+    }
+    #[test]
+    fn bc4_snorm_keeps_its_sign() {
+        //texel 0 takes the first endpoint, texel 1 the second and texel 2 a sixth of the way
+        let block = pack_block(&[(0x7F, 8), (0x81, 8), (0, 3), (1, 3), (2, 3)]);
+        let values = decode_bc4_snorm(&block);
+        assert_eq!(values[..3], [127, -127, 90]);
+    }
+}
@@ -0,0 +1,159 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+//an axis aligned box, in the space of whatever it bounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+impl Aabb {
+    //an empty set of points gives an empty box at the origin
+    pub fn from_points(points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        let mut points = points.into_iter().map(Vector3::from);
+        let Some(first) = points.next() else {
+            return Self {
+                min: Vector3::new(0.0, 0.0, 0.0),
+                max: Vector3::new(0.0, 0.0, 0.0),
+            };
+        };
+        points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Self {
+                min: Vector3::new(
+                    aabb.min.x.min(point.x),
+                    aabb.min.y.min(point.y),
+                    aabb.min.z.min(point.z),
+                ),
+                max: Vector3::new(
+                    aabb.max.x.max(point.x),
+                    aabb.max.y.max(point.y),
+                    aabb.max.z.max(point.z),
+                ),
+            },
+        )
+    }
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+    //half the size along each axis
+    pub fn extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: Vector3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Vector3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+    //the box around this one once it is transformed, looser than the transformed box itself
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let center = (matrix * self.center().extend(1.0)).truncate();
+        let extents = self.extents();
+        let extents = Vector3::new(
+            matrix.x.x.abs() * extents.x
+                + matrix.y.x.abs() * extents.y
+                + matrix.z.x.abs() * extents.z,
+            matrix.x.y.abs() * extents.x
+                + matrix.y.y.abs() * extents.y
+                + matrix.z.y.abs() * extents.z,
+            matrix.x.z.abs() * extents.x
+                + matrix.y.z.abs() * extents.y
+                + matrix.z.z.abs() * extents.z,
+        );
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+impl BoundingSphere {
+    //the smallest sphere around the given center that holds every point
+    pub fn from_points(center: Vector3<f32>, points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        let radius = points
+            .into_iter()
+            .map(|point| (Vector3::from(point) - center).magnitude())
+            .fold(0.0, f32::max);
+        Self { center, radius }
+    }
+    //the radius grows with the largest scale of the transform
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let scale = [matrix.x, matrix.y, matrix.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+        Self {
+            center: (matrix * self.center.extend(1.0)).truncate(),
+            radius: self.radius * scale,
+        }
+    }
+}
+//the six planes of a view projection, pointing inwards, with wgpu's 0 to 1 clip depth
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+impl Frustum {
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 {
+                plane / length
+            } else {
+                plane
+            }
+        });
+        Self { planes }
+    }
+    //left, right, bottom, top, near and far, as normal and distance
+    pub fn planes(&self) -> [Vector4<f32>; 6] {
+        self.planes
+    }
+    fn distance(plane: &Vector4<f32>, point: Vector3<f32>) -> f32 {
+        plane.truncate().dot(point) + plane.w
+    }
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Self::distance(plane, sphere.center) >= -sphere.radius)
+    }
+    //tests the corner furthest along each plane's normal
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let corner = Vector3::new(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            Self::distance(plane, corner) >= 0.0
+        })
+    }
+}
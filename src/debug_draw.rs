@@ -0,0 +1,483 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::{
+    culling::Aabb, model::Vertex, renderer::create_render_pipeline, shaders, textures::Texture,
+};
+
+//the height of the labels' capitals in pixels
+const LABEL_HEIGHT: f32 = 14.0;
+const CIRCLE_SEGMENTS: usize = 32;
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    position: [f32; 3],
+    //pushes the vertex along the screen in pixels, for the labels
+    offset: [f32; 2],
+    color: [f32; 3],
+}
+impl Vertex for DebugVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugUniform {
+    view_proj: [[f32; 4]; 4],
+    viewport: [f32; 2],
+    _padding: [f32; 2],
+}
+
+//immediate mode lines and shapes, everything added is drawn in the next frame and then cleared.
+//colors are linear hdr values, drawn before tone mapping like the rest of the scene
+#[derive(Debug, Default)]
+pub struct DebugDraw {
+    depth_tested: Vec<DebugVertex>,
+    on_top: Vec<DebugVertex>,
+    //whether what is added from now on is drawn over the scene instead of hidden behind it
+    draw_on_top: bool,
+}
+impl DebugDraw {
+    pub fn set_on_top(&mut self, on_top: bool) {
+        self.draw_on_top = on_top;
+    }
+    pub fn is_empty(&self) -> bool {
+        self.depth_tested.is_empty() && self.on_top.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.depth_tested.clear();
+        self.on_top.clear();
+    }
+    fn push(&mut self, position: Vector3<f32>, offset: [f32; 2], color: [f32; 3]) {
+        let vertex = DebugVertex {
+            position: position.into(),
+            offset,
+            color,
+        };
+        if self.draw_on_top {
+            self.on_top.push(vertex);
+        } else {
+            self.depth_tested.push(vertex);
+        }
+    }
+    pub fn line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: [f32; 3]) {
+        self.push(from, [0.0; 2], color);
+        self.push(to, [0.0; 2], color);
+    }
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 3]) {
+        self.oriented_box(aabb, &Matrix4::identity(), color);
+    }
+    //the box's edges once transformed, e.g. a mesh's bounds by an instance's model matrix
+    pub fn oriented_box(&mut self, aabb: &Aabb, transform: &Matrix4<f32>, color: [f32; 3]) {
+        let corners = box_corners(|i| {
+            let corner = Vector3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            );
+            (transform * corner.extend(1.0)).truncate()
+        });
+        self.box_edges(&corners, color);
+    }
+    //a circle around each axis
+    pub fn sphere(&mut self, center: Vector3<f32>, radius: f32, color: [f32; 3]) {
+        self.circle(center, Vector3::unit_x(), Vector3::unit_y(), radius, color);
+        self.circle(center, Vector3::unit_y(), Vector3::unit_z(), radius, color);
+        self.circle(center, Vector3::unit_z(), Vector3::unit_x(), radius, color);
+    }
+    fn circle(
+        &mut self,
+        center: Vector3<f32>,
+        u: Vector3<f32>,
+        v: Vector3<f32>,
+        radius: f32,
+        color: [f32; 3],
+    ) {
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + (u * angle.cos() + v * angle.sin()) * radius
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+    //a line with a four sided head at the end, a quarter of its length
+    pub fn arrow(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: [f32; 3]) {
+        self.line(from, to, color);
+        let direction = to - from;
+        let length = direction.magnitude();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let direction = direction / length;
+        let side = if direction.y.abs() < 0.99 {
+            Vector3::unit_y()
+        } else {
+            Vector3::unit_x()
+        };
+        let u = direction.cross(side).normalize();
+        let v = direction.cross(u);
+        let head = length * 0.25;
+        let base = to - direction * head;
+        for side in [u, -u, v, -v] {
+            self.line(to, base + side * head * 0.4, color);
+        }
+    }
+    //the volume a view projection sees, with the same gl clip space as camera::Projection
+    pub fn frustum(&mut self, view_proj: &Matrix4<f32>, color: [f32; 3]) {
+        let Some(inverse) = view_proj.invert() else {
+            return;
+        };
+        let corners = box_corners(|i| {
+            let corner = inverse
+                * Vector4::new(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                    1.0,
+                );
+            corner.truncate() / corner.w
+        });
+        self.box_edges(&corners, color);
+    }
+    //lines on the xz plane, size across with divisions cells along each side
+    pub fn grid(&mut self, center: Vector3<f32>, size: f32, divisions: u32, color: [f32; 3]) {
+        let divisions = divisions.max(1);
+        let half = size * 0.5;
+        for i in 0..=divisions {
+            let along = i as f32 / divisions as f32 * size - half;
+            self.line(
+                center + Vector3::new(along, 0.0, -half),
+                center + Vector3::new(along, 0.0, half),
+                color,
+            );
+            self.line(
+                center + Vector3::new(-half, 0.0, along),
+                center + Vector3::new(half, 0.0, along),
+                color,
+            );
+        }
+    }
+    //a label centered above the position that keeps its size on screen, drawn with a line font.
+    //letters are drawn as capitals and characters the font doesn't have as question marks
+    pub fn text(&mut self, position: Vector3<f32>, text: &str, color: [f32; 3]) {
+        //the glyphs are 4 units wide and 6 tall with 2 between them
+        let unit = LABEL_HEIGHT / 6.0;
+        let width = (text.chars().count() as f32 * 6.0 - 2.0).max(0.0);
+        for (i, c) in text.chars().enumerate() {
+            let left = i as f32 * 6.0 - width * 0.5;
+            for stroke in glyph(c).split(' ') {
+                let points = stroke
+                    .as_bytes()
+                    .chunks_exact(2)
+                    .map(|point| {
+                        [
+                            (left + (point[0] - b'0') as f32) * unit,
+                            ((point[1] - b'0') as f32 + 2.0) * unit,
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+                for pair in points.windows(2) {
+                    self.push(position, pair[0], color);
+                    self.push(position, pair[1], color);
+                }
+            }
+        }
+    }
+    //corners indexed by their x, y and z bits, as box_corners gives them
+    fn box_edges(&mut self, corners: &[Vector3<f32>; 8], color: [f32; 3]) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+}
+fn box_corners(corner: impl Fn(usize) -> Vector3<f32>) -> [Vector3<f32>; 8] {
+    std::array::from_fn(corner)
+}
+//the strokes of a character on a 5x7 grid of points, as pairs of x and y digits with y up.
+//strokes are separated by spaces
+fn glyph(c: char) -> &'static str {
+    match c.to_ascii_uppercase() {
+        'A' => "0004264440 0343",
+        'B' => "00063645443303 3342413000",
+        'C' => "4536160501103041",
+        'D' => "00062644422000",
+        'E' => "46060040 0333",
+        'F' => "460600 0333",
+        'G' => "45361605011030414323",
+        'H' => "0006 4046 0343",
+        'I' => "0646 2620 0040",
+        'J' => "4641301001",
+        'K' => "0006 4602 1340",
+        'L' => "060040",
+        'M' => "0006234640",
+        'N' => "00064046",
+        'O' => "100105163645413010",
+        'P' => "00063645443303",
+        'Q' => "100105163645413010 2240",
+        'R' => "00063645443303 3340",
+        'S' => "453616050413334241301001",
+        'T' => "0646 2620",
+        'U' => "060110304146",
+        'V' => "062046",
+        'W' => "0610233046",
+        'X' => "0046 0640",
+        'Y' => "062346 2320",
+        'Z' => "06460040",
+        '0' => "100105163645413010 0145",
+        '1' => "152620 0040",
+        '2' => "05163645440040",
+        '3' => "05163645443313 334241301001",
+        '4' => "30360242",
+        '5' => "4606033342413000",
+        '6' => "4536160501103041423303",
+        '7' => "064620",
+        '8' => "13040516364544331302011030414233",
+        '9' => "10304145361605041343",
+        ' ' => "",
+        '.' => "2021",
+        ',' => "2110",
+        ':' => "2122 2425",
+        '-' => "0343",
+        '+' => "0343 2125",
+        '=' => "0242 0444",
+        '/' => "0046",
+        '(' => "36242230",
+        ')' => "16242210",
+        '[' => "36060030",
+        ']' => "16464010",
+        '<' => "450341",
+        '>' => "054301",
+        '_' => "0040",
+        '|' => "2026",
+        '*' => "2125 0244 0442",
+        '!' => "2623 2021",
+        '\'' => "2625",
+        '"' => "1615 3635",
+        _ => "05163645443323 2021",
+    }
+}
+
+//draws what DebugDraw collected as a line list, the depth tested lines and then the ones on top.
+//it runs after anti-aliasing on the single sampled hdr target, so the lines aren't jittered
+pub struct DebugDrawPass {
+    depth_tested_pipeline: wgpu::RenderPipeline,
+    on_top_pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+}
+impl DebugDrawPass {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Draw Uniform Buffer"),
+            size: std::mem::size_of::<DebugUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Debug Draw Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Debug Draw Bind Group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        //the lines on top are drawn in a pass without the depth attachment
+        let [depth_tested_pipeline, on_top_pipeline] =
+            [Some(Texture::DEPTH_FORMAT), None].map(|depth_format| {
+                create_render_pipeline(
+                    device,
+                    &pipeline_layout,
+                    color_format,
+                    depth_format,
+                    &[DebugVertex::desc()],
+                    shaders::descriptor("debug.wgsl"),
+                    wgpu::PrimitiveTopology::LineList,
+                    1,
+                    "Debug Draw Pipeline",
+                )
+            });
+        let vertex_capacity = 1024;
+        Self {
+            depth_tested_pipeline,
+            on_top_pipeline,
+            uniform_buffer,
+            bind_group,
+            vertex_buffer: Self::create_vertex_buffer(device, vertex_capacity),
+            vertex_capacity,
+        }
+    }
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Draw Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    //draws everything added since the last frame and clears it, view_proj should be unjittered
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        view_proj: Matrix4<f32>,
+        viewport: [f32; 2],
+        debug_draw: &mut DebugDraw,
+    ) {
+        if debug_draw.is_empty() {
+            return;
+        }
+        let vertex_count = debug_draw.depth_tested.len() + debug_draw.on_top.len();
+        if vertex_count > self.vertex_capacity {
+            self.vertex_capacity = vertex_count.next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.vertex_capacity);
+        }
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[DebugUniform {
+                view_proj: view_proj.into(),
+                viewport,
+                _padding: [0.0; 2],
+            }]),
+        );
+        let depth_tested_count = debug_draw.depth_tested.len() as u32;
+        queue.write_buffer(
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&[&debug_draw.depth_tested[..], &debug_draw.on_top[..]].concat()),
+        );
+        let passes = [
+            (
+                &self.depth_tested_pipeline,
+                Some(depth),
+                0..depth_tested_count,
+            ),
+            (
+                &self.on_top_pipeline,
+                None,
+                depth_tested_count..vertex_count as u32,
+            ),
+        ];
+        for (pipeline, depth, vertices) in passes {
+            if vertices.is_empty() {
+                continue;
+            }
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Debug Draw Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: depth.map(|view| {
+                    wgpu::RenderPassDepthStencilAttachment {
+                        view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    }
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.draw(vertices, 0..1);
+        }
+        debug_draw.clear();
+    }
+}
+//a shape for scripts to draw, which hand their arguments over as plain arrays
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugShape {
+    Line([f32; 3], [f32; 3]),
+    //min and max corners
+    Box([f32; 3], [f32; 3]),
+    Sphere([f32; 3], f32),
+    Arrow([f32; 3], [f32; 3]),
+    //a column major view projection
+    Frustum([f32; 16]),
+    Grid([f32; 3], f32, u32),
+    Text([f32; 3], String),
+}
+impl DebugShape {
+    pub fn draw(&self, debug_draw: &mut DebugDraw, color: [f32; 3]) {
+        match self {
+            DebugShape::Line(from, to) => debug_draw.line((*from).into(), (*to).into(), color),
+            DebugShape::Box(min, max) => debug_draw.aabb(
+                &Aabb {
+                    min: (*min).into(),
+                    max: (*max).into(),
+                },
+                color,
+            ),
+            DebugShape::Sphere(center, radius) => {
+                debug_draw.sphere((*center).into(), *radius, color)
+            }
+            DebugShape::Arrow(from, to) => debug_draw.arrow((*from).into(), (*to).into(), color),
+            DebugShape::Frustum(columns) => {
+                let matrix: &Matrix4<f32> = columns.into();
+                debug_draw.frustum(matrix, color)
+            }
+            DebugShape::Grid(center, size, divisions) => {
+                debug_draw.grid((*center).into(), *size, *divisions, color)
+            }
+            DebugShape::Text(position, text) => debug_draw.text((*position).into(), text, color),
+        }
+    }
+}
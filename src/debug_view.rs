@@ -0,0 +1,547 @@
+use crate::{
+    hdr,
+    model::{self, DrawInstances, LodInstances, Model, Vertex},
+    renderer::InstanceRaw,
+    shaders,
+    textures::Texture,
+};
+
+const OVERDRAW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugView {
+    #[default]
+    Off,
+    //the triangles' edges over the shaded scene
+    Wireframe,
+    //each vertex's normal, tangent and bitangent as lines over the shaded scene
+    TangentFrames,
+    UvChecker,
+    Albedo,
+    //the light on a white surface
+    Lighting,
+    Depth,
+    //how many opaque fragments each pixel is drawn over, as a heat map
+    Overdraw,
+    //the base color mip each pixel samples
+    MipLevel,
+}
+impl DebugView {
+    pub const ALL: [DebugView; 9] = [
+        DebugView::Off,
+        DebugView::Wireframe,
+        DebugView::TangentFrames,
+        DebugView::UvChecker,
+        DebugView::Albedo,
+        DebugView::Lighting,
+        DebugView::Depth,
+        DebugView::Overdraw,
+        DebugView::MipLevel,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::Off => "off",
+            DebugView::Wireframe => "wireframe",
+            DebugView::TangentFrames => "tangent frames",
+            DebugView::UvChecker => "uv checker",
+            DebugView::Albedo => "albedo",
+            DebugView::Lighting => "lighting",
+            DebugView::Depth => "depth",
+            DebugView::Overdraw => "overdraw",
+            DebugView::MipLevel => "mip level",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|view| view.name() == name)
+    }
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|view| *view == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+    //the views that replace the opaque standard materials' shading, with their entry point in
+    //shader.wgsl
+    fn shading_entry_point(self) -> Option<&'static str> {
+        match self {
+            DebugView::UvChecker => Some("fs_debug_uv_checker"),
+            DebugView::Albedo => Some("fs_debug_albedo"),
+            DebugView::Lighting => Some("fs_debug_lighting"),
+            DebugView::Depth => Some("fs_debug_depth"),
+            DebugView::MipLevel => Some("fs_debug_mip_level"),
+            _ => None,
+        }
+    }
+}
+//what the line overlay in the opaque pass draws
+enum Overlay {
+    //the meshes' triangles with the line polygon mode, at the instances' levels of detail
+    Wireframe(wgpu::RenderPipeline),
+    //pulled from the meshes' buffers, for every instance at full detail
+    WireframeEdges(wgpu::RenderPipeline),
+    TangentFrames(wgpu::RenderPipeline),
+}
+struct Overdraw {
+    count: Texture,
+    bind_group: wgpu::BindGroup,
+}
+//the pipelines of the current debug view, built when it is selected
+pub struct DebugViews {
+    view: DebugView,
+    sample_count: u32,
+    //whether the adapter can rasterize triangles as lines
+    line_polygon_mode: bool,
+    shader: wgpu::ShaderModule,
+    mesh_layout: wgpu::PipelineLayout,
+    pulled_layout: wgpu::PipelineLayout,
+    geometry_layout: wgpu::BindGroupLayout,
+    heatmap_layout: wgpu::BindGroupLayout,
+    overdraw_pipeline: wgpu::RenderPipeline,
+    heatmap_pipeline: wgpu::RenderPipeline,
+    shading_pipeline: Option<wgpu::RenderPipeline>,
+    overlay: Option<Overlay>,
+    //each mesh's vertices and full detail indices, for the pulled overlays
+    geometry_bind_groups: Vec<wgpu::BindGroup>,
+    overdraw: Option<Overdraw>,
+    width: u32,
+    height: u32,
+}
+impl DebugViews {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let geometry_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Debug View Geometry Layout"),
+            entries: &[storage_entry(0), storage_entry(1)],
+        });
+        let heatmap_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Overdraw Heat Map Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let mesh_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug View Mesh Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pulled_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug View Pulled Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &geometry_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(shaders::descriptor("debug_view.wgsl"));
+        //every fragment is counted, whatever is in front of it
+        let overdraw_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overdraw Pipeline"),
+            layout: Some(&mesh_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_mesh",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_overdraw",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: OVERDRAW_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::REPLACE,
+                    }),
+                    write_mask: wgpu::ColorWrites::RED,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let heatmap_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Overdraw Heat Map Pipeline Layout"),
+                bind_group_layouts: &[&heatmap_layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Overdraw Heat Map Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_fullscreen",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_heatmap",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: hdr::HdrPipeline::FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        Self {
+            view: DebugView::Off,
+            sample_count: 1,
+            line_polygon_mode: device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE),
+            shader,
+            mesh_layout,
+            pulled_layout,
+            geometry_layout,
+            heatmap_layout,
+            overdraw_pipeline,
+            heatmap_pipeline,
+            shading_pipeline: None,
+            overlay: None,
+            geometry_bind_groups: Vec::new(),
+            overdraw: None,
+            width,
+            height,
+        }
+    }
+    pub fn view(&self) -> DebugView {
+        self.view
+    }
+    //whether the opaque standard materials are drawn with shading_pipeline, which the deferred
+    //path doesn't have, so it draws them forward instead
+    pub fn replaces_shading(&self) -> bool {
+        self.shading_pipeline.is_some()
+    }
+    pub fn shading_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        self.shading_pipeline.as_ref()
+    }
+    pub fn set_view(
+        &mut self,
+        device: &wgpu::Device,
+        view: DebugView,
+        model: &Model,
+        render_layout: &wgpu::PipelineLayout,
+    ) {
+        self.view = view;
+        self.rebuild(device, model, render_layout);
+    }
+    //the shading and overlay pipelines draw into the scene's targets
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        sample_count: u32,
+        model: &Model,
+        render_layout: &wgpu::PipelineLayout,
+    ) {
+        self.sample_count = sample_count;
+        self.rebuild(device, model, render_layout);
+    }
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        if self.overdraw.is_some() {
+            self.overdraw = Some(self.create_overdraw(device));
+        }
+    }
+    fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        model: &Model,
+        render_layout: &wgpu::PipelineLayout,
+    ) {
+        let depth_stencil = |depth_write_enabled| wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+        let multisample = wgpu::MultisampleState {
+            count: self.sample_count,
+            ..Default::default()
+        };
+        let color_targets = [Some(wgpu::ColorTargetState {
+            format: hdr::HdrPipeline::FORMAT,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
+        self.shading_pipeline = self.view.shading_entry_point().map(|entry_point| {
+            let shader = device.create_shader_module(shaders::descriptor("shader.wgsl"));
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Debug View Shading Pipeline"),
+                layout: Some(render_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &color_targets,
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(depth_stencil(true)),
+                multisample,
+                multiview: None,
+            })
+        });
+        //the lines are drawn over the shaded meshes, so they test against their depth without
+        //writing it
+        let line_pipeline =
+            |layout, vertex_entry_point, buffers: &[wgpu::VertexBufferLayout], primitive| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Debug View Line Pipeline"),
+                    layout: Some(layout),
+                    vertex: wgpu::VertexState {
+                        module: &self.shader,
+                        entry_point: vertex_entry_point,
+                        buffers,
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &self.shader,
+                        entry_point: "fs_color",
+                        targets: &color_targets,
+                    }),
+                    primitive,
+                    depth_stencil: Some(depth_stencil(false)),
+                    multisample,
+                    multiview: None,
+                })
+            };
+        self.overlay = match self.view {
+            DebugView::Wireframe if self.line_polygon_mode => {
+                Some(Overlay::Wireframe(line_pipeline(
+                    &self.mesh_layout,
+                    "vs_mesh",
+                    &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                    wgpu::PrimitiveState {
+                        cull_mode: Some(wgpu::Face::Back),
+                        polygon_mode: wgpu::PolygonMode::Line,
+                        ..Default::default()
+                    },
+                )))
+            }
+            DebugView::Wireframe => Some(Overlay::WireframeEdges(line_pipeline(
+                &self.pulled_layout,
+                "vs_wireframe_edges",
+                &[InstanceRaw::desc()],
+                wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+            ))),
+            DebugView::TangentFrames => Some(Overlay::TangentFrames(line_pipeline(
+                &self.pulled_layout,
+                "vs_tangent_frames",
+                &[InstanceRaw::desc()],
+                wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+            ))),
+            _ => None,
+        };
+        let pulled = matches!(
+            self.overlay,
+            Some(Overlay::WireframeEdges(_) | Overlay::TangentFrames(_))
+        );
+        if pulled && self.geometry_bind_groups.is_empty() {
+            self.geometry_bind_groups = model
+                .meshes
+                .iter()
+                .map(|mesh| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("Debug View Geometry Bind Group"),
+                        layout: &self.geometry_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: mesh.vertex_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: mesh.index_buffer.as_entire_binding(),
+                            },
+                        ],
+                    })
+                })
+                .collect();
+        }
+        self.overdraw = match (self.view, self.overdraw.take()) {
+            (DebugView::Overdraw, Some(overdraw)) => Some(overdraw),
+            (DebugView::Overdraw, None) => Some(self.create_overdraw(device)),
+            _ => None,
+        };
+    }
+    fn create_overdraw(&self, device: &wgpu::Device) -> Overdraw {
+        let count = Texture::create_2d_texture(
+            device,
+            Some("Overdraw Texture"),
+            self.width,
+            self.height,
+            OVERDRAW_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::FilterMode::Nearest,
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Overdraw Heat Map Bind Group"),
+            layout: &self.heatmap_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&count.view),
+            }],
+        });
+        Overdraw { count, bind_group }
+    }
+    //the wireframe or tangent frames, drawn at the end of the opaque pass. the pulled ones don't
+    //go through the culling, so they draw every instance in the instance buffer
+    pub fn draw_overlay<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        batches: &[LodInstances<'a>],
+        instance_buffer: &'a wgpu::Buffer,
+        instance_count: u32,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let Some(overlay) = &self.overlay else {
+            return;
+        };
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        let (pipeline, edges) = match overlay {
+            Overlay::Wireframe(pipeline) => {
+                render_pass.set_pipeline(pipeline);
+                draw_batches(render_pass, model, batches);
+                return;
+            }
+            Overlay::WireframeEdges(pipeline) => (pipeline, true),
+            Overlay::TangentFrames(pipeline) => (pipeline, false),
+        };
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        for (mesh, bind_group) in model.meshes.iter().zip(&self.geometry_bind_groups) {
+            if model.materials[mesh.material].is_transparent() {
+                continue;
+            }
+            render_pass.set_bind_group(1, bind_group, &[]);
+            let vertex_count = if edges {
+                mesh.num_elements * 2
+            } else {
+                let vertices =
+                    mesh.vertex_buffer.size() as usize / std::mem::size_of::<model::ModelVertex>();
+                vertices as u32 * 6
+            };
+            render_pass.draw(0..vertex_count, 0..instance_count);
+        }
+    }
+    //counts the opaque meshes' fragments, then replaces the target with the heat map. it runs
+    //after anti-aliasing so the heat map isn't blended with earlier frames
+    pub fn render_overdraw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        model: &Model,
+        batches: &[LodInstances],
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let Some(overdraw) = &self.overdraw else {
+            return;
+        };
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overdraw Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &overdraw.count.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.overdraw_pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            draw_batches(&mut render_pass, model, batches);
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overdraw Heat Map Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.heatmap_pipeline);
+        render_pass.set_bind_group(0, &overdraw.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+//the opaque meshes at each batch's level, with only their vertex and index buffers bound
+fn draw_batches<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    model: &'a Model,
+    batches: &[LodInstances<'a>],
+) {
+    for batch in batches {
+        render_pass.set_vertex_buffer(1, batch.instance_buffer);
+        for (index, mesh) in model.meshes.iter().enumerate() {
+            if model.materials[mesh.material].is_transparent() {
+                continue;
+            }
+            let (index_buffer, num_elements) = mesh.lod(batch.level);
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            match &batch.instances {
+                DrawInstances::Range(instances) => {
+                    render_pass.draw_indexed(0..num_elements, 0, instances.clone())
+                }
+                DrawInstances::Indirect(draw_buffer, first_draw) => render_pass
+                    .draw_indexed_indirect(
+                        draw_buffer,
+                        DrawInstances::indirect_offset(first_draw + index),
+                    ),
+            }
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use crate::{
+    model::{self, DrawModel, LodInstances, MaterialKind, Model, Vertex},
+    renderer::InstanceRaw,
+    shaders,
+    textures::Texture,
+};
+
+//albedo with the material's occlusion in alpha, normal, metallic and roughness, emissive
+const GBUFFER_FORMATS: [wgpu::TextureFormat; 4] = [
+    wgpu::TextureFormat::Rgba8UnormSrgb,
+    wgpu::TextureFormat::Rgba16Float,
+    wgpu::TextureFormat::Rg8Unorm,
+    wgpu::TextureFormat::Rgba16Float,
+];
+//the g-buffer bindings in deferred.wgsl follow the material's
+const FIRST_GBUFFER_BINDING: u32 = 16;
+
+//draws the opaque standard materials into a g-buffer and lights it into the hdr target,
+//custom and transparent materials are still drawn forward afterwards
+pub struct DeferredRenderer {
+    layout: wgpu::BindGroupLayout,
+    gbuffer_pipeline: wgpu::RenderPipeline,
+    lighting_pipeline: wgpu::RenderPipeline,
+    light_volume_pipeline: wgpu::RenderPipeline,
+    targets: [Texture; 4],
+    bind_group: wgpu::BindGroup,
+}
+impl DeferredRenderer {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        light_layout: &wgpu::BindGroupLayout,
+        environment_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let float = wgpu::TextureSampleType::Float { filterable: false };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("G-Buffer Layout"),
+            entries: &[
+                texture_entry(FIRST_GBUFFER_BINDING, float),
+                texture_entry(FIRST_GBUFFER_BINDING + 1, float),
+                texture_entry(FIRST_GBUFFER_BINDING + 2, float),
+                texture_entry(FIRST_GBUFFER_BINDING + 3, float),
+                texture_entry(FIRST_GBUFFER_BINDING + 4, wgpu::TextureSampleType::Depth),
+            ],
+        });
+        let gbuffer_pipeline = {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("G-Buffer Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::source("shader.wgsl")),
+            });
+            let targets = GBUFFER_FORMATS.map(|format| {
+                Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("G-Buffer Pipeline"),
+                layout: Some(render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_gbuffer",
+                    targets: &targets,
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred Lighting Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout, light_layout, environment_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Deferred Lighting Shader"),
+            source: wgpu::ShaderSource::Wgsl(shaders::composed("deferred.wgsl")),
+        });
+        let create_pipeline = |vertex_entry: &str,
+                               fragment_entry: &str,
+                               blend: wgpu::BlendState,
+                               cull_mode: Option<wgpu::Face>,
+                               label: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: vertex_entry,
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fragment_entry,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let lighting_pipeline = create_pipeline(
+            "vs_fullscreen",
+            "fs_deferred",
+            wgpu::BlendState::REPLACE,
+            None,
+            "Deferred Lighting Pipeline",
+        );
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let light_volume_pipeline = create_pipeline(
+            "vs_light_volume",
+            "fs_light_volume",
+            wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            },
+            Some(wgpu::Face::Front),
+            "Light Volume Pipeline",
+        );
+        let (targets, bind_group) =
+            Self::create_targets(device, &layout, depth_view, width, height);
+        Self {
+            layout,
+            gbuffer_pipeline,
+            lighting_pipeline,
+            light_volume_pipeline,
+            targets,
+            bind_group,
+        }
+    }
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> ([Texture; 4], wgpu::BindGroup) {
+        let targets = GBUFFER_FORMATS.map(|format| {
+            Texture::create_2d_texture(
+                device,
+                Some("G-Buffer Texture"),
+                width,
+                height,
+                format,
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                wgpu::FilterMode::Nearest,
+            )
+        });
+        let views = targets
+            .iter()
+            .map(|target| &target.view)
+            .chain([depth_view])
+            .collect::<Vec<_>>();
+        let entries = views
+            .iter()
+            .enumerate()
+            .map(|(i, view)| wgpu::BindGroupEntry {
+                binding: FIRST_GBUFFER_BINDING + i as u32,
+                resource: wgpu::BindingResource::TextureView(view),
+            })
+            .collect::<Vec<_>>();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("G-Buffer Bind Group"),
+            layout,
+            entries: &entries,
+        });
+        (targets, bind_group)
+    }
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        (self.targets, self.bind_group) =
+            Self::create_targets(device, &self.layout, depth_view, width, height);
+    }
+    //fills the g-buffer and the depth with the opaque standard materials
+    pub fn render_gbuffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        model: &Model,
+        batches: &[LodInstances],
+        camera_bind_group: &wgpu::BindGroup,
+        light_bind_group: &wgpu::BindGroup,
+    ) {
+        let color_attachments = self
+            .targets
+            .iter()
+            .map(|target| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("G-Buffer Pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.gbuffer_pipeline);
+        for batch in batches {
+            pass.set_vertex_buffer(1, batch.instance_buffer);
+            for (index, mesh) in model.meshes.iter().enumerate() {
+                let material = &model.materials[mesh.material];
+                if material.is_transparent() || matches!(material.kind, MaterialKind::Custom(_)) {
+                    continue;
+                }
+                pass.draw_mesh_instances(
+                    index,
+                    mesh,
+                    batch.level,
+                    material,
+                    &batch.instances,
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+        }
+    }
+    //lights the g-buffer into the target, leaving the background at the clear colour for the sky
+    pub fn render_lighting(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        light_count: u32,
+        camera_bind_group: &wgpu::BindGroup,
+        light_bind_group: &wgpu::BindGroup,
+        environment_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Deferred Lighting Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_bind_group(2, light_bind_group, &[]);
+        pass.set_bind_group(3, environment_bind_group, &[]);
+        pass.set_pipeline(&self.lighting_pipeline);
+        pass.draw(0..3, 0..1);
+        if light_count > 0 {
+            pass.set_pipeline(&self.light_volume_pipeline);
+            pass.draw(0..36, 0..light_count);
+        }
+    }
+}
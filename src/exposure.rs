@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use wgpu::util::DeviceExt;
+
+use crate::shaders;
+
+const BIN_COUNT: u64 = 256;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoExposureSettings {
+    pub enabled: bool,
+    //the range of log2 luminance the histogram covers, anything outside is clamped
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    //how quickly the eye adapts to a brighter and to a darker scene, per second
+    pub speed_up: f32,
+    pub speed_down: f32,
+    //the luminance the average is mapped to, middle grey by default
+    pub key: f32,
+}
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_log_luminance: -10.0,
+            max_log_luminance: 6.0,
+            speed_up: 3.0,
+            speed_down: 1.0,
+            key: 0.18,
+        }
+    }
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AutoExposureUniform {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    speed_up: f32,
+    speed_down: f32,
+    delta_time: f32,
+    key: f32,
+    pixel_count: u32,
+    _padding: u32,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureState {
+    luminance: f32,
+    exposure: f32,
+}
+//measures the hdr target's average luminance on the gpu and adapts the exposure to it over time
+pub struct AutoExposure {
+    settings: AutoExposureSettings,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    state_buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+}
+impl AutoExposure {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let settings = AutoExposureSettings::default();
+        let module = device.create_shader_module(shaders::descriptor("auto_exposure.wgsl"));
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Auto Exposure Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Auto Exposure Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let histogram_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Luminance Histogram Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "compute_histogram",
+        });
+        let average_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Average Luminance Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "compute_average",
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure Buffer"),
+            contents: bytemuck::cast_slice(&[uniform(settings, width, height, 0.0)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Histogram Buffer"),
+            size: BIN_COUNT * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        //start adapted to middle grey, an exposure of one
+        let state_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Exposure State Buffer"),
+            contents: bytemuck::cast_slice(&[ExposureState {
+                luminance: settings.key,
+                exposure: 1.0,
+            }]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = create_bind_group(
+            device,
+            &layout,
+            hdr_view,
+            &uniform_buffer,
+            &histogram_buffer,
+            &state_buffer,
+        );
+        Self {
+            settings,
+            layout,
+            bind_group,
+            histogram_pipeline,
+            average_pipeline,
+            uniform_buffer,
+            histogram_buffer,
+            state_buffer,
+            width,
+            height,
+        }
+    }
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.layout,
+            hdr_view,
+            &self.uniform_buffer,
+            &self.histogram_buffer,
+            &self.state_buffer,
+        );
+        self.width = width;
+        self.height = height;
+    }
+    //the adapted exposure the tone mapping pass multiplies the hdr colour by
+    pub fn state_buffer(&self) -> &wgpu::Buffer {
+        &self.state_buffer
+    }
+    pub fn settings(&self) -> AutoExposureSettings {
+        self.settings
+    }
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: AutoExposureSettings) {
+        if !settings.enabled {
+            queue.write_buffer(
+                &self.state_buffer,
+                0,
+                bytemuck::cast_slice(&[ExposureState {
+                    luminance: settings.key,
+                    exposure: 1.0,
+                }]),
+            );
+        }
+        self.settings = settings;
+    }
+    pub fn update(&self, queue: &wgpu::Queue, delta_time: Duration) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform(
+                self.settings,
+                self.width,
+                self.height,
+                delta_time.as_secs_f32(),
+            )]),
+        );
+    }
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.settings.enabled {
+            return;
+        }
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Auto Exposure Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(&self.histogram_pipeline);
+        pass.dispatch_workgroups(self.width.div_ceil(16), self.height.div_ceil(16), 1);
+        pass.set_pipeline(&self.average_pipeline);
+        pass.dispatch_workgroups(1, 1, 1);
+    }
+}
+fn uniform(
+    settings: AutoExposureSettings,
+    width: u32,
+    height: u32,
+    delta_time: f32,
+) -> AutoExposureUniform {
+    AutoExposureUniform {
+        min_log_luminance: settings.min_log_luminance,
+        log_luminance_range: (settings.max_log_luminance - settings.min_log_luminance).max(1e-3),
+        speed_up: settings.speed_up,
+        speed_down: settings.speed_down,
+        delta_time,
+        key: settings.key,
+        pixel_count: width * height,
+        _padding: 0,
+    }
+}
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    hdr_view: &wgpu::TextureView,
+    uniform_buffer: &wgpu::Buffer,
+    histogram_buffer: &wgpu::Buffer,
+    state_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Auto Exposure Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(hdr_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: histogram_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: state_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
@@ -0,0 +1,604 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use cgmath::{Matrix4, Point3, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    culling::{Aabb, BoundingSphere, Frustum},
+    lod::{LodSettings, MAX_LODS},
+    model::{DrawInstances, LodInstances, Model},
+    shaders,
+};
+
+//where the camera passes' instances are culled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CullingMode {
+    //on the cpu every update, with the visible instances uploaded to a vertex buffer
+    #[default]
+    Cpu,
+    //in a compute shader, which compacts the visible instances and writes indirect draws
+    Gpu,
+    //the compute shader also tests against last frame's hierarchical depth
+    GpuOcclusion,
+}
+impl CullingMode {
+    pub const ALL: [CullingMode; 3] = [
+        CullingMode::Cpu,
+        CullingMode::Gpu,
+        CullingMode::GpuOcclusion,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            CullingMode::Cpu => "cpu",
+            CullingMode::Gpu => "gpu",
+            CullingMode::GpuOcclusion => "gpu occlusion",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+    pub fn is_gpu(self) -> bool {
+        self != CullingMode::Cpu
+    }
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullingUniform {
+    planes: [[f32; 4]; 6],
+    prev_view_proj: [[f32; 4]; 4],
+    aabb_min: [f32; 3],
+    instance_count: u32,
+    aabb_max: [f32; 3],
+    occlusion: u32,
+    sphere: [f32; 4],
+    hiz_size: [f32; 2],
+    hiz_mips: u32,
+    mesh_count: u32,
+    camera_position: [f32; 3],
+    lod_count: u32,
+    projection_scale: f32,
+    lod_screen_size: f32,
+    lod_cross_fade: f32,
+    lod_enabled: u32,
+}
+//the visible instances, then the instances at each level of detail
+const COUNTS_SIZE: wgpu::BufferAddress = (1 + MAX_LODS as wgpu::BufferAddress) * 4;
+//the readback of the visible count, which the gpu finishes a frame or so later
+const READBACK_IDLE: u32 = 0;
+const READBACK_MAPPING: u32 = 1;
+const READBACK_MAPPED: u32 = 2;
+const READBACK_FAILED: u32 = 3;
+//the depth pyramid, every mip holding the furthest depth of the texels under it
+struct HiZ {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    size: [u32; 2],
+    mip_count: u32,
+    copy_bind_group: wgpu::BindGroup,
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+}
+//culls every instance against the camera frustum in a compute shader, compacting the visible
+//ones into a vertex buffer and writing an indexed indirect draw for each of the model's meshes
+pub struct GpuCulling {
+    uniform_buffer: wgpu::Buffer,
+    visible_instance_buffer: wgpu::Buffer,
+    count_buffer: wgpu::Buffer,
+    draw_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    readback_state: Arc<AtomicU32>,
+    readback_copied: bool,
+    visible_counts: Option<(u32, [u32; MAX_LODS])>,
+    cull_layout: wgpu::BindGroupLayout,
+    cull_bind_group: wgpu::BindGroup,
+    cull_pipeline: wgpu::ComputePipeline,
+    draw_pipeline: wgpu::ComputePipeline,
+    copy_layout: wgpu::BindGroupLayout,
+    downsample_layout: wgpu::BindGroupLayout,
+    copy_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+    hiz: HiZ,
+    //whether the depth pyramid holds last frame's depth, it doesn't after a resize
+    hiz_valid: bool,
+    prev_view_proj: Matrix4<f32>,
+    instance_count: u32,
+    mesh_count: u32,
+    lod_count: u32,
+}
+impl GpuCulling {
+    //the instance buffer needs storage usage, it is read as words
+    pub fn new(
+        device: &wgpu::Device,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        instance_count: u32,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culling Buffer"),
+            size: std::mem::size_of::<CullingUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        //a region the size of every instance for each level of detail
+        let lod_count = model.lod_count() as u32;
+        let visible_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Gpu Visible Instance Buffer"),
+            size: instance_buffer.size() * lod_count as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Count Buffer"),
+            size: COUNTS_SIZE,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let draws = (0..lod_count as usize)
+            .flat_map(|level| model.meshes.iter().map(move |mesh| (level, mesh)))
+            .flat_map(|(level, mesh)| {
+                wgpu::util::DrawIndexedIndirectArgs {
+                    index_count: mesh.lod(level).1,
+                    instance_count: 0,
+                    first_index: 0,
+                    base_vertex: 0,
+                    first_instance: 0,
+                }
+                .as_bytes()
+                .to_vec()
+            })
+            .collect::<Vec<_>>();
+        let draw_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            contents: &draws,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Count Readback Buffer"),
+            size: COUNTS_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let storage_texture_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::R32Float,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let unfilterable = wgpu::TextureSampleType::Float { filterable: false };
+        let cull_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Culling Layout"),
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+                buffer_entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+                buffer_entry(4, wgpu::BufferBindingType::Storage { read_only: false }),
+                texture_entry(5, unfilterable),
+            ],
+        });
+        let copy_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Hi-Z Copy Layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Depth),
+                storage_texture_entry,
+            ],
+        });
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Hi-Z Downsample Layout"),
+            entries: &[texture_entry(1, unfilterable), storage_texture_entry],
+        });
+
+        let compute_pipeline = |label, layout: &wgpu::BindGroupLayout, module, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module,
+                entry_point,
+            })
+        };
+        let cull_module = device.create_shader_module(shaders::descriptor("gpu_culling.wgsl"));
+        let hiz_module = device.create_shader_module(shaders::descriptor("hiz.wgsl"));
+        let cull_pipeline = compute_pipeline(
+            "Cull Instances Pipeline",
+            &cull_layout,
+            &cull_module,
+            "cull_instances",
+        );
+        let draw_pipeline = compute_pipeline(
+            "Write Draws Pipeline",
+            &cull_layout,
+            &cull_module,
+            "write_draws",
+        );
+        let copy_pipeline = compute_pipeline(
+            "Hi-Z Copy Pipeline",
+            &copy_layout,
+            &hiz_module,
+            "copy_depth",
+        );
+        let downsample_pipeline = compute_pipeline(
+            "Hi-Z Downsample Pipeline",
+            &downsample_layout,
+            &hiz_module,
+            "downsample",
+        );
+
+        let hiz = Self::create_hiz(
+            device,
+            &copy_layout,
+            &downsample_layout,
+            depth_view,
+            width,
+            height,
+        );
+        let cull_bind_group = Self::create_cull_bind_group(
+            device,
+            &cull_layout,
+            &uniform_buffer,
+            instance_buffer,
+            &visible_instance_buffer,
+            &count_buffer,
+            &draw_buffer,
+            &hiz.view,
+        );
+        Self {
+            uniform_buffer,
+            visible_instance_buffer,
+            count_buffer,
+            draw_buffer,
+            readback_buffer,
+            readback_state: Arc::new(AtomicU32::new(READBACK_IDLE)),
+            readback_copied: false,
+            visible_counts: None,
+            cull_layout,
+            cull_bind_group,
+            cull_pipeline,
+            draw_pipeline,
+            copy_layout,
+            downsample_layout,
+            copy_pipeline,
+            downsample_pipeline,
+            hiz,
+            hiz_valid: false,
+            prev_view_proj: Matrix4::identity(),
+            instance_count,
+            mesh_count: model.meshes.len() as u32,
+            lod_count,
+        }
+    }
+    fn create_hiz(
+        device: &wgpu::Device,
+        copy_layout: &wgpu::BindGroupLayout,
+        downsample_layout: &wgpu::BindGroupLayout,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> HiZ {
+        let size = [width.max(1), height.max(1)];
+        let mip_count = size[0].max(size[1]).ilog2() + 1;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Hi-Z Texture"),
+            size: wgpu::Extent3d {
+                width: size[0],
+                height: size[1],
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let mip_views = (0..mip_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Hi-Z Mip View"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let copy_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Hi-Z Copy Bind Group"),
+            layout: copy_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&mip_views[0]),
+                },
+            ],
+        });
+        let downsample_bind_groups = mip_views
+            .windows(2)
+            .map(|views| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Hi-Z Downsample Bind Group"),
+                    layout: downsample_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&views[0]),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&views[1]),
+                        },
+                    ],
+                })
+            })
+            .collect();
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        HiZ {
+            texture,
+            view,
+            size,
+            mip_count,
+            copy_bind_group,
+            downsample_bind_groups,
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn create_cull_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        instance_buffer: &wgpu::Buffer,
+        visible_instance_buffer: &wgpu::Buffer,
+        count_buffer: &wgpu::Buffer,
+        draw_buffer: &wgpu::Buffer,
+        hiz_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Culling Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: visible_instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: count_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: draw_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(hiz_view),
+                },
+            ],
+        })
+    }
+    //the depth pyramid follows the depth texture, and is stale until it is built again
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        instance_buffer: &wgpu::Buffer,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.hiz = Self::create_hiz(
+            device,
+            &self.copy_layout,
+            &self.downsample_layout,
+            depth_view,
+            width,
+            height,
+        );
+        self.cull_bind_group = Self::create_cull_bind_group(
+            device,
+            &self.cull_layout,
+            &self.uniform_buffer,
+            instance_buffer,
+            &self.visible_instance_buffer,
+            &self.count_buffer,
+            &self.draw_buffer,
+            &self.hiz.view,
+        );
+        self.hiz_valid = false;
+    }
+    //every level's region of the visible instances, with its indirect draws, one per mesh
+    pub fn batches(&self) -> Vec<LodInstances<'_>> {
+        let region = self.visible_instance_buffer.size() / self.lod_count as wgpu::BufferAddress;
+        (0..self.lod_count as usize)
+            .map(|level| {
+                let start = region * level as wgpu::BufferAddress;
+                LodInstances {
+                    level,
+                    instance_buffer: self.visible_instance_buffer.slice(start..start + region),
+                    instances: DrawInstances::Indirect(
+                        &self.draw_buffer,
+                        level * self.mesh_count as usize,
+                    ),
+                }
+            })
+            .collect()
+    }
+    //the visible count and the instances drawn at each level from the last readback that
+    //finished, none before the first
+    pub fn visible_counts(&self) -> Option<(u32, [u32; MAX_LODS])> {
+        self.visible_counts
+    }
+    //the view projection is the one the camera passes draw with this frame, occlusion is only
+    //tested when the pyramid holds last frame's depth
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        view_proj: Matrix4<f32>,
+        camera_position: Point3<f32>,
+        projection_scale: f32,
+        bounds: (Aabb, BoundingSphere),
+        occlusion: bool,
+        lod: &LodSettings,
+    ) {
+        let (aabb, sphere) = bounds;
+        let frustum = Frustum::from_matrix(view_proj);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[CullingUniform {
+                planes: frustum.planes().map(Into::into),
+                prev_view_proj: self.prev_view_proj.into(),
+                aabb_min: aabb.min.into(),
+                instance_count: self.instance_count,
+                aabb_max: aabb.max.into(),
+                occlusion: (occlusion && self.hiz_valid) as u32,
+                sphere: sphere.center.extend(sphere.radius).into(),
+                hiz_size: [self.hiz.size[0] as f32, self.hiz.size[1] as f32],
+                hiz_mips: self.hiz.mip_count,
+                mesh_count: self.mesh_count,
+                camera_position: camera_position.into(),
+                lod_count: self.lod_count,
+                projection_scale,
+                lod_screen_size: lod.screen_size,
+                lod_cross_fade: lod.cross_fade,
+                lod_enabled: lod.enabled as u32,
+            }]),
+        );
+        queue.write_buffer(
+            &self.count_buffer,
+            0,
+            bytemuck::cast_slice(&[0u32; 1 + MAX_LODS]),
+        );
+        self.prev_view_proj = view_proj;
+        //picks up a finished readback, the callback only runs while the device is polled
+        match self.readback_state.load(Ordering::Acquire) {
+            READBACK_MAPPED => {
+                let slice = self.readback_buffer.slice(..);
+                let counts = bytemuck::cast_slice::<u8, u32>(&slice.get_mapped_range()).to_vec();
+                let mut lods = [0; MAX_LODS];
+                lods.copy_from_slice(&counts[1..]);
+                self.visible_counts = Some((counts[0], lods));
+                self.readback_buffer.unmap();
+                self.readback_state.store(READBACK_IDLE, Ordering::Release);
+            }
+            READBACK_FAILED => self.readback_state.store(READBACK_IDLE, Ordering::Release),
+            _ => {}
+        }
+    }
+    //compacts the visible instances and fills in the draws' instance counts
+    pub fn cull(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Gpu Culling Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_bind_group(0, &self.cull_bind_group, &[]);
+            pass.set_pipeline(&self.cull_pipeline);
+            pass.dispatch_workgroups(self.instance_count.div_ceil(64), 1, 1);
+            pass.set_pipeline(&self.draw_pipeline);
+            pass.dispatch_workgroups((self.mesh_count * self.lod_count).div_ceil(64), 1, 1);
+        }
+        if self.readback_state.load(Ordering::Acquire) == READBACK_IDLE {
+            encoder.copy_buffer_to_buffer(
+                &self.count_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                COUNTS_SIZE,
+            );
+            self.readback_copied = true;
+        }
+    }
+    //rebuilds the pyramid from this frame's depth for the next frame's occlusion test
+    pub fn build_hiz(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Hi-Z Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.copy_pipeline);
+        pass.set_bind_group(0, &self.hiz.copy_bind_group, &[]);
+        let [width, height] = self.hiz.size;
+        pass.dispatch_workgroups(width.div_ceil(8), height.div_ceil(8), 1);
+        pass.set_pipeline(&self.downsample_pipeline);
+        for (mip, bind_group) in self.hiz.downsample_bind_groups.iter().enumerate() {
+            let mip_size = self
+                .hiz
+                .texture
+                .size()
+                .mip_level_size(mip as u32 + 1, wgpu::TextureDimension::D2);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(mip_size.width.div_ceil(8), mip_size.height.div_ceil(8), 1);
+        }
+        self.hiz_valid = true;
+    }
+    //has to be called after the encoder from cull is submitted
+    pub fn read_back(&mut self) {
+        if !self.readback_copied {
+            return;
+        }
+        self.readback_copied = false;
+        self.readback_state
+            .store(READBACK_MAPPING, Ordering::Release);
+        let state = self.readback_state.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let next = if result.is_ok() {
+                    READBACK_MAPPED
+                } else {
+                    READBACK_FAILED
+                };
+                state.store(next, Ordering::Release);
+            });
+    }
+}
@@ -1,9 +1,85 @@
-use wgpu::Operations;
+use std::time::Duration;
+
+use wgpu::{util::DeviceExt, Operations};
 
 use crate::{
+    bloom::{Bloom, BloomSettings},
+    exposure::{AutoExposure, AutoExposureSettings},
     renderer::create_render_pipeline,
+    shaders,
     textures::{self, Texture},
 };
+//the curve that maps hdr colour into the displayable range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapping {
+    None,
+    Reinhard,
+    //reinhard that reaches white at the white point instead of infinity
+    ReinhardExtended,
+    #[default]
+    Aces,
+    AgX,
+    Uncharted2,
+}
+impl ToneMapping {
+    pub const ALL: [ToneMapping; 6] = [
+        ToneMapping::None,
+        ToneMapping::Reinhard,
+        ToneMapping::ReinhardExtended,
+        ToneMapping::Aces,
+        ToneMapping::AgX,
+        ToneMapping::Uncharted2,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMapping::None => "none",
+            ToneMapping::Reinhard => "reinhard",
+            ToneMapping::ReinhardExtended => "reinhard_extended",
+            ToneMapping::Aces => "aces",
+            ToneMapping::AgX => "agx",
+            ToneMapping::Uncharted2 => "uncharted2",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mapping| mapping.name() == name)
+    }
+    pub fn next(self) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|mapping| *mapping == self)
+            .unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMappingSettings {
+    pub operator: ToneMapping,
+    //in stops, the hdr colour is scaled by 2^exposure before the curve
+    pub exposure: f32,
+    //the display's gamma, 2.2 matches the srgb encoding of the surface
+    pub gamma: f32,
+    //the hdr value mapped to white by reinhard extended and uncharted 2
+    pub white_point: f32,
+}
+impl Default for ToneMappingSettings {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapping::default(),
+            exposure: 0.0,
+            gamma: 2.2,
+            white_point: 11.2,
+        }
+    }
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMappingUniform {
+    exposure: f32,
+    //applied after the curve, an srgb surface already encodes gamma 2.2
+    gamma_exponent: f32,
+    white_point: f32,
+    operator: u32,
+}
 pub struct HdrPipeline {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
@@ -12,19 +88,28 @@ pub struct HdrPipeline {
     height: u32,
     format: wgpu::TextureFormat,
     layout: wgpu::BindGroupLayout,
+    tone_mapping: ToneMappingSettings,
+    tone_mapping_buffer: wgpu::Buffer,
+    output_is_srgb: bool,
+    auto_exposure: AutoExposure,
+    bloom: Bloom,
 }
 impl HdrPipeline {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
     pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
         let width = config.width;
         let height = config.height;
-        let format = wgpu::TextureFormat::Rgba16Float;
+        let format = Self::FORMAT;
         let texture = Texture::create_2d_texture(
             device,
             Some("HDR Texture Creation"),
             width,
             height,
             format,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            //copied into by temporal anti-aliasing
+            wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_DST,
             wgpu::FilterMode::Linear,
         );
         // Hdr Bind group layout
@@ -49,8 +134,61 @@ impl HdrPipeline {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                // Tone mapping settings
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Exposure from eye adaptation
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Blurred scene from bloom
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Bloom settings
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
+        let auto_exposure = AutoExposure::new(device, &texture.view, width, height);
+        let bloom = Bloom::new(device, &texture.view, width, height);
+        let tone_mapping = ToneMappingSettings::default();
+        let output_is_srgb = config.format.is_srgb();
+        let tone_mapping_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tone Mapping Buffer"),
+            contents: bytemuck::cast_slice(&[tone_mapping_uniform(tone_mapping, output_is_srgb)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("HDR Pipeline Bind Group"),
             layout: &layout,
@@ -63,9 +201,25 @@ impl HdrPipeline {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: tone_mapping_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: auto_exposure.state_buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(bloom.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: bloom.uniform_buffer().as_entire_binding(),
+                },
             ],
         });
-        let shader = wgpu::include_wgsl!("../shaders/hdr.wgsl");
+        let shader = shaders::descriptor("hdr.wgsl");
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("HDR Pipeline Layout"),
             bind_group_layouts: &[&layout],
@@ -79,6 +233,7 @@ impl HdrPipeline {
             &[],
             shader,
             wgpu::PrimitiveTopology::TriangleList,
+            1,
             "HDR Pipeline",
         );
         Self {
@@ -89,18 +244,30 @@ impl HdrPipeline {
             width,
             height,
             format,
+            tone_mapping,
+            tone_mapping_buffer,
+            output_is_srgb,
+            auto_exposure,
+            bloom,
         }
     }
-    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
         self.texture = Texture::create_2d_texture(
             device,
             Some("HDR Texture Creation"),
             width,
             height,
             self.format,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            //copied into by temporal anti-aliasing
+            wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_DST,
             wgpu::FilterMode::Linear,
         );
+        self.auto_exposure
+            .resize(device, &self.texture.view, width, height);
+        self.bloom
+            .resize(device, queue, &self.texture.view, width, height);
         self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("HDR Pipeline Bind Group"),
             layout: &self.layout,
@@ -113,6 +280,22 @@ impl HdrPipeline {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&self.texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.tone_mapping_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.auto_exposure.state_buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(self.bloom.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.bloom.uniform_buffer().as_entire_binding(),
+                },
             ],
         });
         self.width = width;
@@ -121,10 +304,41 @@ impl HdrPipeline {
     pub fn view(&self) -> &wgpu::TextureView {
         &self.texture.view
     }
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture.texture
+    }
     pub fn format(&self) -> wgpu::TextureFormat {
         self.format
     }
+    pub fn tone_mapping(&self) -> ToneMappingSettings {
+        self.tone_mapping
+    }
+    pub fn set_tone_mapping(&mut self, queue: &wgpu::Queue, settings: ToneMappingSettings) {
+        self.tone_mapping = settings;
+        queue.write_buffer(
+            &self.tone_mapping_buffer,
+            0,
+            bytemuck::cast_slice(&[tone_mapping_uniform(settings, self.output_is_srgb)]),
+        );
+    }
+    pub fn auto_exposure(&self) -> AutoExposureSettings {
+        self.auto_exposure.settings()
+    }
+    pub fn set_auto_exposure(&mut self, queue: &wgpu::Queue, settings: AutoExposureSettings) {
+        self.auto_exposure.set_settings(queue, settings);
+    }
+    pub fn bloom(&self) -> BloomSettings {
+        self.bloom.settings()
+    }
+    pub fn set_bloom(&mut self, queue: &wgpu::Queue, settings: BloomSettings) {
+        self.bloom.set_settings(queue, settings);
+    }
+    pub fn update(&self, queue: &wgpu::Queue, delta_time: Duration) {
+        self.auto_exposure.update(queue, delta_time);
+    }
     pub fn process(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        self.auto_exposure.process(encoder);
+        self.bloom.process(encoder);
         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("HDR Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -143,3 +357,19 @@ impl HdrPipeline {
         pass.draw(0..3, 0..1);
     }
 }
+fn tone_mapping_uniform(settings: ToneMappingSettings, output_is_srgb: bool) -> ToneMappingUniform {
+    let gamma = settings.gamma.max(0.01);
+    ToneMappingUniform {
+        exposure: settings.exposure.exp2(),
+        gamma_exponent: if output_is_srgb {
+            2.2 / gamma
+        } else {
+            1.0 / gamma
+        },
+        white_point: settings.white_point.max(1e-3),
+        operator: ToneMapping::ALL
+            .iter()
+            .position(|mapping| *mapping == settings.operator)
+            .unwrap() as u32,
+    }
+}
@@ -0,0 +1,442 @@
+use wgpu::util::DeviceExt;
+
+use crate::shaders;
+
+use crate::textures::{self, CubeTexture};
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTER_SIZE: u32 = 256;
+//roughness goes from 0.0 at the top mip to 1.0 at the last one
+const PREFILTER_MIP_LEVELS: u32 = 6;
+const BRDF_LUT_SIZE: u32 = 512;
+const IBL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PrefilterParams {
+    roughness: f32,
+    _padding: [u32; 3],
+}
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniform {
+    intensity: f32,
+    _padding: [u32; 3],
+}
+//bakes the maps used for image based lighting out of an environment cube map
+pub struct IblBaker {
+    layout: wgpu::BindGroupLayout,
+    lut_layout: wgpu::BindGroupLayout,
+    irradiance_pipeline: wgpu::ComputePipeline,
+    prefilter_pipeline: wgpu::ComputePipeline,
+    brdf_lut_pipeline: wgpu::ComputePipeline,
+    source_sampler: wgpu::Sampler,
+}
+pub struct EnvironmentMaps {
+    pub irradiance: CubeTexture,
+    pub prefiltered: CubeTexture,
+    pub brdf_lut: textures::Texture,
+}
+//the bind group the model pipeline reads the environment lighting from
+pub struct Environment {
+    maps: EnvironmentMaps,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+impl IblBaker {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let module = device.create_shader_module(shaders::descriptor("ibl.wgsl"));
+        let lut_module = device.create_shader_module(shaders::descriptor("brdf_lut.wgsl"));
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IBL Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: IBL_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let lut_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("BRDF LUT Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: IBL_FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let lut_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("BRDF LUT Pipeline Layout"),
+            bind_group_layouts: &[&lut_layout],
+            push_constant_ranges: &[],
+        });
+        let irradiance_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Irradiance Pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: "compute_irradiance",
+            });
+        let prefilter_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Prefilter Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "compute_prefilter",
+        });
+        let brdf_lut_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("BRDF LUT Pipeline"),
+            layout: Some(&lut_pipeline_layout),
+            module: &lut_module,
+            entry_point: "compute_brdf_lut",
+        });
+        let source_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Source Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            layout,
+            lut_layout,
+            irradiance_pipeline,
+            prefilter_pipeline,
+            brdf_lut_pipeline,
+            source_sampler,
+        }
+    }
+    pub fn bake(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &CubeTexture,
+    ) -> EnvironmentMaps {
+        let usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING;
+        let irradiance = CubeTexture::create_2d(
+            device,
+            IRRADIANCE_SIZE,
+            IRRADIANCE_SIZE,
+            IBL_FORMAT,
+            1,
+            usage,
+            wgpu::FilterMode::Linear,
+            Some("Irradiance Map"),
+        );
+        let prefiltered = CubeTexture::create_2d(
+            device,
+            PREFILTER_SIZE,
+            PREFILTER_SIZE,
+            IBL_FORMAT,
+            PREFILTER_MIP_LEVELS,
+            usage,
+            wgpu::FilterMode::Linear,
+            Some("Prefiltered Environment Map"),
+        );
+        let brdf_lut = textures::Texture::create_2d_texture(
+            device,
+            Some("BRDF LUT"),
+            BRDF_LUT_SIZE,
+            BRDF_LUT_SIZE,
+            IBL_FORMAT,
+            usage,
+            wgpu::FilterMode::Linear,
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Bake Encoder"),
+        });
+        //one dispatch per target: the irradiance map then every prefiltered mip level
+        let targets = std::iter::once((&self.irradiance_pipeline, irradiance.texture(), 0, 0.0))
+            .chain((0..PREFILTER_MIP_LEVELS).map(|mip| {
+                let roughness = mip as f32 / (PREFILTER_MIP_LEVELS - 1) as f32;
+                (
+                    &self.prefilter_pipeline,
+                    prefiltered.texture(),
+                    mip,
+                    roughness,
+                )
+            }));
+        for (pipeline, texture, mip, roughness) in targets {
+            let size = (texture.width() >> mip).max(1);
+            let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Prefilter Params"),
+                contents: bytemuck::cast_slice(&[PrefilterParams {
+                    roughness,
+                    _padding: [0; 3],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let dst_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("IBL Target View"),
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                array_layer_count: Some(6),
+                ..Default::default()
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("IBL Bind Group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source.view()),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.source_sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&dst_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: params.as_entire_binding(),
+                    },
+                ],
+            });
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("IBL Bake Pass"),
+                ..Default::default()
+            });
+            let workgroups = size.div_ceil(16);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(workgroups, workgroups, 6);
+        }
+        let lut_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("BRDF LUT Bind Group"),
+            layout: &self.lut_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&brdf_lut.view),
+            }],
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("BRDF LUT Pass"),
+                ..Default::default()
+            });
+            let workgroups = BRDF_LUT_SIZE.div_ceil(16);
+            pass.set_pipeline(&self.brdf_lut_pipeline);
+            pass.set_bind_group(0, &lut_bind_group, &[]);
+            pass.dispatch_workgroups(workgroups, workgroups, 1);
+        }
+        queue.submit([encoder.finish()]);
+        EnvironmentMaps {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+        }
+    }
+}
+impl Environment {
+    pub fn new(
+        device: &wgpu::Device,
+        maps: EnvironmentMaps,
+        ambient_occlusion: &wgpu::TextureView,
+    ) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[EnvironmentUniform {
+                intensity: 1.0,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Environment Lighting Bind Group Layout"),
+            entries: &[
+                cube_entry(0),
+                cube_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = create_bind_group(
+            device,
+            &layout,
+            &maps,
+            &sampler,
+            &uniform_buffer,
+            ambient_occlusion,
+        );
+        Self {
+            maps,
+            sampler,
+            uniform_buffer,
+            layout,
+            bind_group,
+        }
+    }
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+    //the ambient occlusion texture is recreated with the window size
+    pub fn set_ambient_occlusion(
+        &mut self,
+        device: &wgpu::Device,
+        ambient_occlusion: &wgpu::TextureView,
+    ) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.layout,
+            &self.maps,
+            &self.sampler,
+            &self.uniform_buffer,
+            ambient_occlusion,
+        );
+    }
+    pub fn set_intensity(&self, queue: &wgpu::Queue, intensity: f32) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[EnvironmentUniform {
+                intensity,
+                _padding: [0; 3],
+            }]),
+        );
+    }
+}
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    maps: &EnvironmentMaps,
+    sampler: &wgpu::Sampler,
+    uniform_buffer: &wgpu::Buffer,
+    ambient_occlusion: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Environment Lighting Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(maps.irradiance.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(maps.prefiltered.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&maps.brdf_lut.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(ambient_occlusion),
+            },
+        ],
+    })
+}
@@ -0,0 +1,354 @@
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{cluster::LightClusters, shadow::ShadowMaps};
+
+//how many point and spot lights the light storage buffer has room for
+pub const MAX_LIGHTS: usize = 4096;
+pub const LIGHT_KIND_POINT: u32 = 0;
+pub const LIGHT_KIND_SPOT: u32 = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight {
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub cast_shadows: bool,
+}
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    pub position: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+    pub cast_shadows: bool,
+}
+#[derive(Debug, Clone)]
+pub struct SpotLight {
+    pub position: Vector3<f32>,
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+    pub inner_angle: cgmath::Rad<f32>,
+    pub outer_angle: cgmath::Rad<f32>,
+    pub cast_shadows: bool,
+}
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DirectionalLightRaw {
+    direction: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    shadowed: u32,
+}
+// point and spot lights share one layout so they can live in a single storage buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 3],
+    range: f32,
+    direction: [f32; 3],
+    cos_outer: f32,
+    color: [f32; 3],
+    intensity: f32,
+    cos_inner: f32,
+    kind: u32,
+    shadow_index: i32,
+    _padding: u32,
+}
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightUniform {
+    directional: DirectionalLightRaw,
+    ambient: [f32; 3],
+    light_count: u32,
+}
+pub struct Lights {
+    pub directional: Option<DirectionalLight>,
+    pub point_lights: Vec<PointLight>,
+    pub spot_lights: Vec<SpotLight>,
+    pub ambient: [f32; 3],
+    uniform_buffer: wgpu::Buffer,
+    light_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    clusters: LightClusters,
+}
+impl PointLight {
+    fn to_raw(&self, shadow_index: Option<u32>) -> LightRaw {
+        LightRaw {
+            position: self.position.into(),
+            range: self.range,
+            direction: [0.0; 3],
+            cos_outer: -1.0,
+            color: self.color,
+            intensity: self.intensity,
+            cos_inner: -1.0,
+            kind: LIGHT_KIND_POINT,
+            shadow_index: shadow_index.map_or(-1, |i| i as i32),
+            _padding: 0,
+        }
+    }
+}
+impl SpotLight {
+    fn to_raw(&self, shadow_index: Option<u32>) -> LightRaw {
+        LightRaw {
+            position: self.position.into(),
+            range: self.range,
+            direction: self.direction.normalize().into(),
+            cos_outer: self.outer_angle.0.cos(),
+            color: self.color,
+            intensity: self.intensity,
+            cos_inner: self.inner_angle.0.cos(),
+            kind: LIGHT_KIND_SPOT,
+            shadow_index: shadow_index.map_or(-1, |i| i as i32),
+            _padding: 0,
+        }
+    }
+}
+impl Lights {
+    pub fn new(device: &wgpu::Device, shadows: &ShadowMaps) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[LightUniform {
+                directional: DirectionalLightRaw {
+                    direction: [0.0, -1.0, 0.0],
+                    intensity: 0.0,
+                    color: [0.0; 3],
+                    shadowed: 0,
+                },
+                ambient: [0.0; 3],
+                light_count: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Storage Buffer"),
+            size: (std::mem::size_of::<LightRaw>() * MAX_LIGHTS) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Shadow matrices and filtering settings
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Cascades followed by spot light shadow maps
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                // One depth cube per shadowed point light
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::CubeArray,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                // The light cluster grid and the lights assigned to each cluster
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Light bind group ly"),
+        });
+        let clusters = LightClusters::new(device, &uniform_buffer, &light_buffer);
+        let bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            &uniform_buffer,
+            &light_buffer,
+            shadows,
+            &clusters,
+        );
+        Self {
+            directional: None,
+            point_lights: vec![],
+            spot_lights: vec![],
+            ambient: [0.1, 0.1, 0.1],
+            uniform_buffer,
+            light_buffer,
+            layout,
+            bind_group,
+            clusters,
+        }
+    }
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        light_buffer: &wgpu::Buffer,
+        shadows: &ShadowMaps,
+        clusters: &LightClusters,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: shadows.uniform_buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&shadows.texture().view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&shadows.texture().sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(shadows.point_texture().view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: clusters.uniform_buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: clusters.cluster_buffer().as_entire_binding(),
+                },
+            ],
+            label: Some("Light Bind Group"),
+        })
+    }
+    //needs to be called whenever the shadow maps are recreated
+    pub fn rebuild_bind_group(&mut self, device: &wgpu::Device, shadows: &ShadowMaps) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            &self.uniform_buffer,
+            &self.light_buffer,
+            shadows,
+            &self.clusters,
+        );
+    }
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+    pub fn clusters(&self) -> &LightClusters {
+        &self.clusters
+    }
+    pub fn clusters_mut(&mut self) -> &mut LightClusters {
+        &mut self.clusters
+    }
+    pub fn point_light_count(&self) -> u32 {
+        self.point_lights.len().min(MAX_LIGHTS) as u32
+    }
+    //point and spot lights in the storage buffer
+    pub fn light_count(&self) -> u32 {
+        (self.point_lights.len() + self.spot_lights.len()).min(MAX_LIGHTS) as u32
+    }
+    pub fn write(&self, queue: &wgpu::Queue, shadows: &ShadowMaps) {
+        let directional = match &self.directional {
+            Some(light) => DirectionalLightRaw {
+                direction: light.direction.normalize().into(),
+                intensity: light.intensity,
+                color: light.color,
+                shadowed: (light.cast_shadows && shadows.settings().cascade_count > 0) as u32,
+            },
+            None => DirectionalLightRaw {
+                direction: [0.0, -1.0, 0.0],
+                intensity: 0.0,
+                color: [0.0; 3],
+                shadowed: 0,
+            },
+        };
+        //point lights come first so the light gizmos can index them by instance
+        let lights = self
+            .point_lights
+            .iter()
+            .enumerate()
+            .map(|(i, l)| l.to_raw(shadows.point_shadow_index(i)))
+            .chain(
+                self.spot_lights
+                    .iter()
+                    .enumerate()
+                    .map(|(i, l)| l.to_raw(shadows.spot_shadow_index(i))),
+            )
+            .take(MAX_LIGHTS)
+            .collect::<Vec<_>>();
+        let uniform = LightUniform {
+            directional,
+            ambient: self.ambient,
+            light_count: lights.len() as u32,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        if !lights.is_empty() {
+            queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&lights));
+        }
+    }
+}
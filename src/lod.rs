@@ -0,0 +1,78 @@
+//the full detail and the levels below it, the same limit is in build.rs and gpu_culling.wgsl
+pub const MAX_LODS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodSettings {
+    pub enabled: bool,
+    //the fraction of the screen's height a model's bounding sphere covers when the first lower
+    //level takes over, every further level takes over at half the size of the one before
+    pub screen_size: f32,
+    //how much of each halving before a switch is dithered between the two levels, 0 switches
+    //at once
+    pub cross_fade: f32,
+}
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            screen_size: 0.25,
+            cross_fade: 0.2,
+        }
+    }
+}
+//the level an instance is drawn at, and how far it has faded into the next level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodSelection {
+    pub level: usize,
+    pub fade: f32,
+}
+impl LodSelection {
+    //the lod_fade instance attribute for each level drawn, positive fades keep the pixels whose
+    //dither threshold is below them and negative ones the rest, so the two levels don't overlap
+    pub fn instance_fades(self) -> impl Iterator<Item = (usize, f32)> {
+        let fading = self.fade > 0.0;
+        let keep = 1.0 - self.fade;
+        [
+            (self.level, if fading { keep } else { 0.0 }),
+            (self.level + 1, -keep),
+        ]
+        .into_iter()
+        .take(if fading { 2 } else { 1 })
+    }
+}
+impl LodSettings {
+    //the fraction of the screen's height a sphere covers, projection_scale is the projection
+    //matrix's y scale, the cotangent of half the vertical field of view
+    pub fn screen_size(distance: f32, radius: f32, projection_scale: f32) -> f32 {
+        if distance <= radius {
+            return f32::INFINITY;
+        }
+        radius * projection_scale / distance
+    }
+    pub fn select(&self, level_count: usize, screen_size: f32) -> LodSelection {
+        if !self.enabled || level_count <= 1 {
+            return LodSelection {
+                level: 0,
+                fade: 0.0,
+            };
+        }
+        let last = level_count - 1;
+        let level = ((self.screen_size / screen_size).log2() + 1.0).max(0.0);
+        if level >= last as f32 {
+            return LodSelection {
+                level: last,
+                fade: 0.0,
+            };
+        }
+        let start = 1.0 - self.cross_fade;
+        let fraction = level.fract();
+        LodSelection {
+            level: level as usize,
+            fade: if self.cross_fade > 0.0 && fraction > start {
+                (fraction - start) / self.cross_fade
+            } else {
+                0.0
+            },
+        }
+    }
+}
@@ -1,10 +1,30 @@
+mod antialiasing;
 mod bindings;
+mod bloom;
+mod cluster;
+mod compressed;
+mod culling;
+mod debug_draw;
+mod debug_view;
+mod deferred;
+mod exposure;
+mod gpu_culling;
 mod hdr;
+mod ibl;
+mod light;
+mod lod;
+mod material;
 mod model;
+mod post;
 mod renderer;
 mod resources;
+mod scripting;
+mod shaders;
+mod shadow;
+mod ssao;
 mod text;
 mod textures;
+mod transparent;
 mod window;
 use tokio;
 mod camera;
@@ -13,11 +33,28 @@ pub trait Plugin {
     fn init(&self);
     fn update(&self);
     fn render(&self);
+    //effects added to the end of the post-process stack after init
+    fn post_effects(&self) -> Vec<post::PostEffectDescriptor> {
+        vec![]
+    }
 }
 #[tokio::main]
 async fn main() {
     env_logger::init();
     let engine = window::Engine::new().await;
+    //--bake-sky <file> writes the converted sky to a KTX2 file instead of running
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(index) = args.iter().position(|arg| arg == "--bake-sky") {
+        let Some(path) = args.get(index + 1) else {
+            eprintln!("--bake-sky needs a file to write");
+            std::process::exit(1);
+        };
+        if let Err(e) = engine.bake_sky(std::path::Path::new(path)) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
     engine.run();
 }
@@ -0,0 +1,678 @@
+use std::{collections::HashMap, rc::Rc};
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    model::{Material, MaterialKind, Model},
+    resources,
+    textures::{self, SamplerOptions, TextureLoader},
+};
+
+//custom shaders put their own resources in group 0, groups 1-3 are the camera, lights and environment
+pub const MATERIAL_GROUP: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    Premultiplied,
+    Additive,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RenderState {
+    pub blend: BlendMode,
+    pub cull_mode: Option<wgpu::Face>,
+    pub depth_write: bool,
+}
+impl Default for RenderState {
+    fn default() -> Self {
+        Self {
+            blend: BlendMode::Opaque,
+            cull_mode: Some(wgpu::Face::Back),
+            depth_write: true,
+        }
+    }
+}
+impl BlendMode {
+    pub fn blend_state(self) -> Option<wgpu::BlendState> {
+        match self {
+            BlendMode::Opaque => Some(wgpu::BlendState::REPLACE),
+            BlendMode::Alpha => Some(wgpu::BlendState::ALPHA_BLENDING),
+            BlendMode::Premultiplied => Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            BlendMode::Additive => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            }),
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub enum MaterialParam {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Texture {
+        file_name: String,
+        linear: bool,
+        options: SamplerOptions,
+    },
+}
+//a material asset from the materials folder, see the README for the format
+#[derive(Debug, Clone)]
+pub struct MaterialAsset {
+    pub name: String,
+    pub shader: String,
+    pub render_state: RenderState,
+    pub params: HashMap<String, MaterialParam>,
+}
+impl MaterialAsset {
+    pub fn load(file_name: &str) -> anyhow::Result<Self> {
+        let bytes = resources::read_game_assets(&format!("materials/{}", file_name))?;
+        if bytes.is_empty() {
+            anyhow::bail!("Could not find material {:?}", file_name);
+        }
+        Self::parse(file_name, &String::from_utf8(bytes)?)
+    }
+    pub fn parse(name: &str, source: &str) -> anyhow::Result<Self> {
+        let mut shader = None;
+        let mut render_state = RenderState::default();
+        let mut params = HashMap::new();
+        for (line_number, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let values = words.collect::<Vec<_>>();
+            let error = || anyhow::anyhow!("{}:{}: invalid line {:?}", name, line_number + 1, line);
+            let floats = |count: usize| -> anyhow::Result<Vec<f32>> {
+                let floats = values[1..]
+                    .iter()
+                    .map(|v| v.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()?;
+                if floats.len() != count {
+                    return Err(error());
+                }
+                Ok(floats)
+            };
+            match (keyword, values.as_slice()) {
+                ("shader", [file_name]) => shader = Some(file_name.to_string()),
+                ("blend", [mode]) => {
+                    render_state.blend = match *mode {
+                        "opaque" => BlendMode::Opaque,
+                        "alpha" => BlendMode::Alpha,
+                        "premultiplied" => BlendMode::Premultiplied,
+                        "additive" => BlendMode::Additive,
+                        _ => return Err(error()),
+                    }
+                }
+                ("cull", [mode]) => {
+                    render_state.cull_mode = match *mode {
+                        "back" => Some(wgpu::Face::Back),
+                        "front" => Some(wgpu::Face::Front),
+                        "none" => None,
+                        _ => return Err(error()),
+                    }
+                }
+                ("depth_write", [value]) => {
+                    render_state.depth_write = match *value {
+                        "on" | "true" => true,
+                        "off" | "false" => false,
+                        _ => return Err(error()),
+                    }
+                }
+                ("float", [param, ..]) => {
+                    params.insert(param.to_string(), MaterialParam::Float(floats(1)?[0]));
+                }
+                ("vec2", [param, ..]) => {
+                    let v = floats(2)?;
+                    params.insert(param.to_string(), MaterialParam::Vec2([v[0], v[1]]));
+                }
+                ("vec3", [param, ..]) => {
+                    let v = floats(3)?;
+                    params.insert(param.to_string(), MaterialParam::Vec3([v[0], v[1], v[2]]));
+                }
+                ("vec4", [param, ..]) => {
+                    let v = floats(4)?;
+                    params.insert(
+                        param.to_string(),
+                        MaterialParam::Vec4([v[0], v[1], v[2], v[3]]),
+                    );
+                }
+                //colors can leave out alpha
+                ("color", [param, ..]) => {
+                    let v = floats(3).or_else(|_| floats(4))?;
+                    let alpha = v.get(3).copied().unwrap_or(1.0);
+                    params.insert(
+                        param.to_string(),
+                        MaterialParam::Vec4([v[0], v[1], v[2], alpha]),
+                    );
+                }
+                //the file name can be preceded by the same sampler options as MTL maps
+                ("texture" | "texture_linear", [param, statement @ ..])
+                    if !statement.is_empty() =>
+                {
+                    let statement = statement.join(" ");
+                    let (file_name, options) = resources::parse_map_statement(&statement)?;
+                    params.insert(
+                        param.to_string(),
+                        MaterialParam::Texture {
+                            file_name: file_name.to_string(),
+                            linear: keyword == "texture_linear",
+                            options,
+                        },
+                    );
+                }
+                _ => return Err(error()),
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            shader: shader.ok_or_else(|| anyhow::anyhow!("{}: no shader given", name))?,
+            render_state,
+            params,
+        })
+    }
+}
+#[derive(Debug)]
+struct UniformMember {
+    name: String,
+    offset: u32,
+    //the number of f32 components, other member types can't be set from a material
+    components: Option<u32>,
+}
+#[derive(Debug)]
+enum BindingKind {
+    Texture,
+    Sampler,
+    Uniform {
+        size: u32,
+        members: Vec<UniformMember>,
+    },
+}
+#[derive(Debug)]
+struct ShaderBinding {
+    name: String,
+    binding: u32,
+    kind: BindingKind,
+}
+impl ShaderBinding {
+    //whether a material parameter with this name is written to the binding
+    fn sets(&self, param: &str) -> bool {
+        match &self.kind {
+            BindingKind::Uniform { members, .. } => members.iter().any(|m| m.name == param),
+            BindingKind::Texture => self.name == param,
+            BindingKind::Sampler => false,
+        }
+    }
+}
+//indices into the textures, samplers and buffers a custom material owns
+enum BoundResource {
+    Texture(usize),
+    TextureSampler(usize),
+    Sampler(usize),
+    Buffer(usize),
+}
+//a wgsl shader with its material bind group layout reflected from the source
+#[derive(Debug)]
+pub struct MaterialShader {
+    pub name: String,
+    module: wgpu::ShaderModule,
+    layout: wgpu::BindGroupLayout,
+    bindings: Vec<ShaderBinding>,
+}
+//parses and validates wgsl, errors are formatted with the offending source lines
+pub fn parse_wgsl(
+    name: &str,
+    source: &str,
+) -> anyhow::Result<(naga::Module, naga::valid::ModuleInfo)> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| anyhow::anyhow!(e.emit_to_string_with_path(source, name)))?;
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|e| anyhow::anyhow!(e.emit_to_string_with_path(source, name)))?;
+    Ok((module, info))
+}
+impl MaterialShader {
+    pub fn new(device: &wgpu::Device, name: &str, source: &str) -> anyhow::Result<Self> {
+        let (module, info) = parse_wgsl(name, source)?;
+        for entry_point in ["vs_main", "fs_main"] {
+            if !module.entry_points.iter().any(|e| e.name == entry_point) {
+                anyhow::bail!("{}: missing entry point {}", name, entry_point);
+            }
+        }
+        let mut entries = vec![];
+        let mut bindings = vec![];
+        for (handle, global) in module.global_variables.iter() {
+            let Some(resource) = &global.binding else {
+                continue;
+            };
+            if resource.group != MATERIAL_GROUP {
+                continue;
+            }
+            let global_name = global.name.clone().unwrap_or_default();
+            let mut visibility = wgpu::ShaderStages::NONE;
+            for (i, entry_point) in module.entry_points.iter().enumerate() {
+                if !info.get_entry_point(i)[handle].is_empty() {
+                    visibility |= match entry_point.stage {
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    };
+                }
+            }
+            if visibility.is_empty() {
+                visibility = wgpu::ShaderStages::VERTEX_FRAGMENT;
+            }
+            let unsupported =
+                || anyhow::anyhow!("{}: unsupported material binding {}", name, global_name);
+            let (ty, kind) = match &module.types[global.ty].inner {
+                naga::TypeInner::Image {
+                    dim,
+                    arrayed: false,
+                    class: naga::ImageClass::Sampled { kind, multi: false },
+                } => {
+                    let sample_type = match kind {
+                        naga::ScalarKind::Float => {
+                            wgpu::TextureSampleType::Float { filterable: true }
+                        }
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        _ => return Err(unsupported()),
+                    };
+                    if *dim != naga::ImageDimension::D2 {
+                        return Err(unsupported());
+                    }
+                    let ty = wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type,
+                    };
+                    (ty, BindingKind::Texture)
+                }
+                naga::TypeInner::Sampler { comparison: false } => (
+                    wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    BindingKind::Sampler,
+                ),
+                inner if global.space == naga::AddressSpace::Uniform => {
+                    let member = |name: String, offset: u32, ty: naga::Handle<naga::Type>| {
+                        let components = match module.types[ty].inner {
+                            naga::TypeInner::Scalar(naga::Scalar::F32) => Some(1),
+                            naga::TypeInner::Vector {
+                                size,
+                                scalar: naga::Scalar::F32,
+                            } => Some(size as u32),
+                            _ => None,
+                        };
+                        UniformMember {
+                            name,
+                            offset,
+                            components,
+                        }
+                    };
+                    let (size, members) = match inner {
+                        naga::TypeInner::Struct { members, span } => (
+                            *span,
+                            members
+                                .iter()
+                                .map(|m| member(m.name.clone().unwrap_or_default(), m.offset, m.ty))
+                                .collect(),
+                        ),
+                        inner => (
+                            inner.size(module.to_ctx()),
+                            vec![member(global_name.clone(), 0, global.ty)],
+                        ),
+                    };
+                    let ty = wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    };
+                    (ty, BindingKind::Uniform { size, members })
+                }
+                _ => return Err(unsupported()),
+            };
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: resource.binding,
+                visibility,
+                ty,
+                count: None,
+            });
+            bindings.push(ShaderBinding {
+                name: global_name,
+                binding: resource.binding,
+                kind,
+            });
+        }
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format!("{} Material Layout", name)),
+            entries: &entries,
+        });
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        Ok(Self {
+            name: name.to_string(),
+            module,
+            layout,
+            bindings,
+        })
+    }
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+}
+//a material drawn with its own shader instead of the built-in pbr one
+#[derive(Debug)]
+pub struct CustomMaterial {
+    pub shader: Rc<MaterialShader>,
+    pub render_state: RenderState,
+}
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    shader: String,
+    render_state: RenderState,
+    vertex_layouts: Vec<wgpu::VertexBufferLayout<'static>>,
+}
+//loads custom material shaders once and caches a pipeline per material and vertex layout
+pub struct MaterialLibrary {
+    color_format: wgpu::TextureFormat,
+    sample_count: u32,
+    textures: TextureLoader,
+    shaders: HashMap<String, Rc<MaterialShader>>,
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+}
+impl MaterialLibrary {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        Self {
+            color_format,
+            sample_count: 1,
+            textures: TextureLoader::new(device),
+            shaders: HashMap::new(),
+            pipelines: HashMap::new(),
+        }
+    }
+    //drops the cached pipelines when the count changes, the models have to be prepared again
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        if self.sample_count != sample_count {
+            self.sample_count = sample_count;
+            self.pipelines.clear();
+        }
+    }
+    pub fn textures(&self) -> &TextureLoader {
+        &self.textures
+    }
+    //shaders are read from the shaders folder of the game assets
+    pub fn shader(
+        &mut self,
+        device: &wgpu::Device,
+        file_name: &str,
+    ) -> anyhow::Result<Rc<MaterialShader>> {
+        if let Some(shader) = self.shaders.get(file_name) {
+            return Ok(shader.clone());
+        }
+        let bytes = resources::read_game_assets(&format!("shaders/{}", file_name))?;
+        if bytes.is_empty() {
+            anyhow::bail!("Could not find shader {:?}", file_name);
+        }
+        let shader = Rc::new(MaterialShader::new(
+            device,
+            file_name,
+            &String::from_utf8(bytes)?,
+        )?);
+        self.shaders.insert(file_name.to_string(), shader.clone());
+        Ok(shader)
+    }
+    pub fn load_material(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        file_name: &str,
+    ) -> anyhow::Result<Material> {
+        let asset = MaterialAsset::load(file_name)?;
+        let shader = self.shader(device, &asset.shader)?;
+        self.create_material(device, queue, &asset, shader)
+    }
+    pub fn create_material(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        asset: &MaterialAsset,
+        shader: Rc<MaterialShader>,
+    ) -> anyhow::Result<Material> {
+        let unknown = asset
+            .params
+            .keys()
+            .filter(|param| !shader.bindings.iter().any(|b| b.sets(param)))
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            anyhow::bail!(
+                "{}: parameters {:?} aren't used by {}",
+                asset.name,
+                unknown,
+                shader.name
+            );
+        }
+        let mut textures = vec![];
+        let mut texture_indices = HashMap::new();
+        let mut samplers = vec![];
+        let mut uniform_buffers = vec![];
+        let mut resources = vec![];
+        for binding in &shader.bindings {
+            match &binding.kind {
+                BindingKind::Texture => {
+                    let texture = match asset.params.get(&binding.name) {
+                        Some(MaterialParam::Texture {
+                            file_name,
+                            linear,
+                            options,
+                        }) => resources::load_texture(
+                            file_name,
+                            device,
+                            queue,
+                            *linear,
+                            &self.textures,
+                            *options,
+                        )?,
+                        Some(_) => anyhow::bail!(
+                            "{}: {} is a texture in {}",
+                            asset.name,
+                            binding.name,
+                            shader.name
+                        ),
+                        None => self.textures.from_color(
+                            device,
+                            queue,
+                            [255; 4],
+                            &format!("{} {}", asset.name, binding.name),
+                            false,
+                        ),
+                    };
+                    texture_indices.insert(binding.name.as_str(), textures.len());
+                    resources.push(BoundResource::Texture(textures.len()));
+                    textures.push(texture);
+                }
+                BindingKind::Sampler => {
+                    //s_name samples with t_name's sampler, like the built-in shader's naming
+                    let paired = binding
+                        .name
+                        .strip_prefix("s_")
+                        .and_then(|name| texture_indices.get(format!("t_{}", name).as_str()));
+                    resources.push(match paired {
+                        Some(index) => BoundResource::TextureSampler(*index),
+                        None => {
+                            samplers.push(self.textures.sampler(device, SamplerOptions::default()));
+                            BoundResource::Sampler(samplers.len() - 1)
+                        }
+                    });
+                }
+                BindingKind::Uniform { size, members } => {
+                    let mut contents = vec![0u8; *size as usize];
+                    for member in members {
+                        let Some(param) = asset.params.get(&member.name) else {
+                            continue;
+                        };
+                        let values: &[f32] = match param {
+                            MaterialParam::Float(v) => std::slice::from_ref(v),
+                            MaterialParam::Vec2(v) => v,
+                            MaterialParam::Vec3(v) => v,
+                            MaterialParam::Vec4(v) => v,
+                            MaterialParam::Texture { .. } => &[],
+                        };
+                        if member.components != Some(values.len() as u32) {
+                            anyhow::bail!(
+                                "{}: {} doesn't match its type in {}",
+                                asset.name,
+                                member.name,
+                                shader.name
+                            );
+                        }
+                        let offset = member.offset as usize;
+                        contents[offset..offset + values.len() * 4]
+                            .copy_from_slice(bytemuck::cast_slice(values));
+                    }
+                    resources.push(BoundResource::Buffer(uniform_buffers.len()));
+                    uniform_buffers.push(device.create_buffer_init(
+                        &wgpu::util::BufferInitDescriptor {
+                            label: Some(&format!("{} {}", asset.name, binding.name)),
+                            contents: &contents,
+                            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                        },
+                    ));
+                }
+            }
+        }
+        let entries = shader
+            .bindings
+            .iter()
+            .zip(resources)
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding.binding,
+                resource: match resource {
+                    BoundResource::Texture(i) => {
+                        wgpu::BindingResource::TextureView(&textures[i].view)
+                    }
+                    BoundResource::TextureSampler(i) => {
+                        wgpu::BindingResource::Sampler(&textures[i].sampler)
+                    }
+                    BoundResource::Sampler(i) => wgpu::BindingResource::Sampler(&samplers[i]),
+                    BoundResource::Buffer(i) => uniform_buffers[i].as_entire_binding(),
+                },
+            })
+            .collect::<Vec<_>>();
+        //the bind group keeps the textures, samplers and buffers it binds alive
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&asset.name),
+            layout: shader.layout(),
+            entries: &entries,
+        });
+        Ok(Material {
+            name: asset.name.clone(),
+            bind_group,
+            kind: MaterialKind::Custom(CustomMaterial {
+                shader,
+                render_state: asset.render_state,
+            }),
+        })
+    }
+    //creates the pipelines for any custom materials of the model that aren't cached yet
+    pub fn prepare_model(
+        &mut self,
+        device: &wgpu::Device,
+        model: &Model,
+        vertex_layouts: &[wgpu::VertexBufferLayout<'static>],
+        scene_layouts: &[&wgpu::BindGroupLayout],
+    ) {
+        for material in &model.materials {
+            if let MaterialKind::Custom(custom) = &material.kind {
+                self.prepare(device, custom, vertex_layouts, scene_layouts);
+            }
+        }
+    }
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        material: &CustomMaterial,
+        vertex_layouts: &[wgpu::VertexBufferLayout<'static>],
+        scene_layouts: &[&wgpu::BindGroupLayout],
+    ) {
+        let key = PipelineKey {
+            shader: material.shader.name.clone(),
+            render_state: material.render_state,
+            vertex_layouts: vertex_layouts.to_vec(),
+        };
+        if self.pipelines.contains_key(&key) {
+            return;
+        }
+        let shader = &material.shader;
+        let mut bind_group_layouts = vec![shader.layout()];
+        bind_group_layouts.extend_from_slice(scene_layouts);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Pipeline Layout", shader.name)),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let state = material.render_state;
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{} Pipeline", shader.name)),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader.module,
+                entry_point: "vs_main",
+                buffers: vertex_layouts,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader.module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.color_format,
+                    blend: state.blend.blend_state(),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: state.cull_mode,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: textures::Texture::DEPTH_FORMAT,
+                depth_write_enabled: state.depth_write,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: self.sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+        self.pipelines.insert(key, pipeline);
+    }
+    //None if prepare hasn't been called for this material and vertex layout
+    pub fn pipeline(
+        &self,
+        material: &CustomMaterial,
+        vertex_layouts: &[wgpu::VertexBufferLayout<'static>],
+    ) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(&PipelineKey {
+            shader: material.shader.name.clone(),
+            render_state: material.render_state,
+            vertex_layouts: vertex_layouts.to_vec(),
+        })
+    }
+}
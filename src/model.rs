@@ -1,8 +1,13 @@
 use std::ops::Range;
 
-use wgpu::Device;
+use wgpu::util::DeviceExt;
 
-use crate::{camera, textures};
+use crate::{
+    camera,
+    culling::{Aabb, BoundingSphere},
+    material::{BlendMode, CustomMaterial},
+    textures,
+};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -11,7 +16,8 @@ pub trait Vertex {
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
     pub position: [f32; 3],
-    pub tex_coords: [f32; 2],
+    //the third coordinate is the mesh's entry in a batched material, 0 for any other
+    pub tex_coords: [f32; 3],
     pub normal: [f32; 3],
     pub tangent: [f32; 3],
     pub bitangent: [f32; 3],
@@ -24,56 +30,485 @@ pub struct Model {
 #[derive(Debug)]
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: textures::Texture,
     pub bind_group: wgpu::BindGroup,
-    pub normal_texture: textures::Texture,
+    pub kind: MaterialKind,
+}
+#[derive(Debug)]
+pub enum MaterialKind {
+    //drawn with the built-in pbr shader
+    //boxed as its textures make it far larger than a custom material
+    Standard(Box<StandardMaterial>),
+    Custom(CustomMaterial),
+}
+//the size of an atlas page, tiles are packed into as many pages as they need
+pub const ATLAS_PAGE_SIZE: u32 = 2048;
+//how the textures of batched materials are packed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchPacking {
+    //a layer per source
+    Array,
+    //square tiles on the pages of an atlas, for small textures whose uvs don't repeat
+    Atlas { tile_size: u32 },
+}
+#[derive(Debug)]
+pub struct StandardMaterial {
+    pub textures: MaterialTextures,
+    //one entry per batched source, picked by the mesh's vertices and the instance's layer
+    pub factors: Vec<MaterialFactors>,
+}
+//metallic-roughness textures follow the gltf layout: roughness in green, metallic in blue
+#[derive(Debug)]
+pub struct MaterialTextures {
+    pub base_color: textures::Texture,
+    pub normal: textures::Texture,
+    pub metallic_roughness: textures::Texture,
+    pub occlusion: textures::Texture,
+    pub emissive: textures::Texture,
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    //fragments with a base color alpha below the cutoff are discarded
+    Mask(f32),
+    //drawn in the transparent pass
+    Blend,
+}
+//scalar factors are multiplied with their textures, so a missing map uses the factor alone
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub alpha_mode: AlphaMode,
+    pub emissive: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion_strength: f32,
+    pub normal_scale: f32,
+}
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            alpha_mode: AlphaMode::Opaque,
+            emissive: [0.0; 3],
+            metallic: 0.0,
+            roughness: 0.5,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+        }
+    }
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    alpha_cutoff: f32,
+    //where the entry's textures are in a batched material's arrays or atlas
+    uv_rect: [f32; 4],
+    layer: u32,
+    _padding: [u32; 3],
+}
+impl MaterialFactors {
+    fn to_raw(self, region: textures::TextureRegion) -> MaterialUniform {
+        let [offset_x, offset_y] = region.uv_offset;
+        let [scale_x, scale_y] = region.uv_scale;
+        MaterialUniform {
+            base_color: self.base_color,
+            emissive: self.emissive,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            occlusion_strength: self.occlusion_strength,
+            normal_scale: self.normal_scale,
+            alpha_cutoff: match self.alpha_mode {
+                AlphaMode::Mask(cutoff) => cutoff,
+                _ => 0.0,
+            },
+            uv_rect: [offset_x, offset_y, scale_x, scale_y],
+            layer: region.layer,
+            _padding: [0; 3],
+        }
+    }
 }
 #[derive(Debug)]
 pub struct Mesh {
-    pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    //model space bounds for culling
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    //the levels below the full detail, in decreasing detail
+    pub lods: Vec<MeshLod>,
+}
+//a lower level of detail, indexing into its mesh's vertex buffer
+#[derive(Debug)]
+pub struct MeshLod {
+    pub index_buffer: wgpu::Buffer,
+    pub num_elements: u32,
+}
+impl Mesh {
+    //the index buffer and count of a level, the levels past the mesh's last use its last
+    pub fn lod(&self, level: usize) -> (&wgpu::Buffer, u32) {
+        match level
+            .checked_sub(1)
+            .and_then(|lod| self.lods.get(lod).or(self.lods.last()))
+        {
+            Some(lod) => (&lod.index_buffer, lod.num_elements),
+            None => (&self.index_buffer, self.num_elements),
+        }
+    }
+}
+impl Model {
+    //the most levels any of the meshes has, including the full detail
+    pub fn lod_count(&self) -> usize {
+        1 + self
+            .meshes
+            .iter()
+            .map(|mesh| mesh.lods.len())
+            .max()
+            .unwrap_or(0)
+    }
+    //the box and sphere around every mesh, the sphere centered on the box and reaching the
+    //furthest mesh sphere unless the box's corners are closer
+    pub fn bounds(&self) -> (Aabb, BoundingSphere) {
+        use cgmath::InnerSpace;
+        let aabb = self
+            .meshes
+            .iter()
+            .map(|mesh| mesh.aabb)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or(Aabb::from_points([]));
+        let center = aabb.center();
+        let radius = self
+            .meshes
+            .iter()
+            .map(|mesh| {
+                (mesh.bounding_sphere.center - center).magnitude() + mesh.bounding_sphere.radius
+            })
+            .fold(0.0, f32::max)
+            .min(aabb.extents().magnitude());
+        (aabb, BoundingSphere { center, radius })
+    }
+}
+impl MaterialTextures {
+    //the longest side of any of the textures
+    pub fn largest_size(&self) -> u32 {
+        [
+            &self.base_color,
+            &self.normal,
+            &self.metallic_roughness,
+            &self.occlusion,
+            &self.emissive,
+        ]
+        .iter()
+        .map(|texture| texture.size.width.max(texture.size.height))
+        .max()
+        .unwrap_or(1)
+    }
 }
 impl Material {
+    pub fn is_transparent(&self) -> bool {
+        match &self.kind {
+            MaterialKind::Standard(standard) => standard
+                .factors
+                .iter()
+                .any(|factors| factors.alpha_mode == AlphaMode::Blend),
+            MaterialKind::Custom(custom) => custom.render_state.blend != BlendMode::Opaque,
+        }
+    }
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            //base color, normal, metallic-roughness, occlusion and emissive texture/sampler pairs,
+            //always array views so batched materials share the layout
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("texture_bind_group_layout"),
+        })
+    }
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: textures::Texture,
-        normal_texture: textures::Texture,
+        textures: MaterialTextures,
+        factors: MaterialFactors,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        let region = textures::TextureRegion::default();
+        Self::new_layered(device, name, textures, vec![factors], &[region], layout)
+    }
+    //a material with an entry of factors and a texture region per batched source
+    pub fn new_layered(
+        device: &wgpu::Device,
+        name: &str,
+        textures: MaterialTextures,
+        factors: Vec<MaterialFactors>,
+        regions: &[textures::TextureRegion],
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let raw = factors
+            .iter()
+            .zip(regions)
+            .map(|(factors, region)| factors.to_raw(*region))
+            .collect::<Vec<_>>();
+        //the bind group keeps the buffer alive
+        let factor_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Material Factors", name)),
+            contents: bytemuck::cast_slice(&raw),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let array_view = |texture: &textures::Texture| {
+            texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            })
+        };
+        let views = [
+            array_view(&textures.base_color),
+            array_view(&textures.normal),
+            array_view(&textures.metallic_roughness),
+            array_view(&textures.occlusion),
+            array_view(&textures.emissive),
+        ];
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&views[0]),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&textures.base_color.sampler),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&views[1]),
                 },
                 wgpu::BindGroupEntry {
                     binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&textures.normal.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&views[2]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(&textures.metallic_roughness.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&views[3]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&textures.occlusion.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&views[4]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(&textures.emissive.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: factor_buffer.as_entire_binding(),
                 },
             ],
             label: Some(name),
         });
         Self {
             name: name.to_string(),
-            diffuse_texture,
             bind_group,
-            normal_texture,
+            kind: MaterialKind::Standard(Box::new(StandardMaterial { textures, factors })),
+        }
+    }
+    //packs standard materials into one whose textures are arrays or atlases, so meshes using any of
+    //them draw with a single bind group. a mesh picks its source by the entry in its vertices
+    #[allow(clippy::too_many_arguments)]
+    pub fn batch(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        loader: &textures::TextureLoader,
+        name: &str,
+        materials: &[&Material],
+        packing: BatchPacking,
+        options: textures::SamplerOptions,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Material> {
+        let standard = materials
+            .iter()
+            .map(|material| match &material.kind {
+                MaterialKind::Standard(standard) if standard.factors.len() == 1 => {
+                    Ok(standard.as_ref())
+                }
+                MaterialKind::Standard(_) => {
+                    anyhow::bail!("Material {} is already batched", material.name)
+                }
+                MaterialKind::Custom(_) => {
+                    anyhow::bail!("Custom material {} can't be batched", material.name)
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if standard.is_empty() {
+            anyhow::bail!("No materials to batch");
         }
+        let blended =
+            |standard: &&StandardMaterial| standard.factors[0].alpha_mode == AlphaMode::Blend;
+        if standard.iter().any(blended) != standard.iter().all(blended) {
+            anyhow::bail!("Blended and opaque materials can't share a batch");
+        }
+        //every slot is packed in the same order so one region per source fits all of them
+        let mut regions = Vec::new();
+        let mut build = |slot: fn(&MaterialTextures) -> &textures::Texture, format, suffix| {
+            let label = format!("{} {}", name, suffix);
+            let sources = standard
+                .iter()
+                .map(|standard| &slot(&standard.textures).texture);
+            match packing {
+                //each array takes the size of its largest source, smaller ones are stretched up
+                BatchPacking::Array => {
+                    let (width, height) =
+                        standard.iter().fold((1, 1), |(width, height), standard| {
+                            let size = slot(&standard.textures).size;
+                            (width.max(size.width), height.max(size.height))
+                        });
+                    let mut builder = textures::TextureArrayBuilder::new(width, height, format);
+                    regions = sources.map(|source| builder.push(source)).collect();
+                    builder.build(device, queue, loader, Some(&label), options)
+                }
+                //the tiles don't repeat, so the atlas is sampled clamped
+                BatchPacking::Atlas { tile_size } => {
+                    let mut builder =
+                        textures::TextureAtlasBuilder::new(ATLAS_PAGE_SIZE, tile_size, format);
+                    regions = sources.map(|source| builder.push(source)).collect();
+                    let options = textures::SamplerOptions {
+                        address_mode: wgpu::AddressMode::ClampToEdge,
+                        ..options
+                    };
+                    builder.build(device, queue, loader, Some(&label), options)
+                }
+            }
+        };
+        let srgb = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let linear = wgpu::TextureFormat::Rgba8Unorm;
+        let textures = MaterialTextures {
+            base_color: build(|textures| &textures.base_color, srgb, "Base Color"),
+            normal: build(|textures| &textures.normal, linear, "Normal"),
+            metallic_roughness: build(
+                |textures| &textures.metallic_roughness,
+                linear,
+                "Metallic Roughness",
+            ),
+            occlusion: build(|textures| &textures.occlusion, linear, "Occlusion"),
+            emissive: build(|textures| &textures.emissive, srgb, "Emissive"),
+        };
+        let factors = standard
+            .iter()
+            .map(|standard| standard.factors[0])
+            .collect::<Vec<_>>();
+        Ok(Self::new_layered(
+            device, name, textures, factors, &regions, layout,
+        ))
     }
 }
+
 impl Vertex for ModelVertex {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
@@ -89,20 +524,20 @@ impl Vertex for ModelVertex {
                 wgpu::VertexAttribute {
                     offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                     shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
+                    format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    offset: mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
                     shader_location: 3,
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
                     shader_location: 4,
                     format: wgpu::VertexFormat::Float32x3,
                 },
@@ -110,14 +545,26 @@ impl Vertex for ModelVertex {
         }
     }
 }
+//the instances a mesh is drawn with, a range of the buffer in the instance slot or the indirect
+//draws gpu culling wrote, one per mesh in the model's order starting from the given draw
+#[derive(Debug, Clone)]
+pub enum DrawInstances<'a> {
+    Range(Range<u32>),
+    Indirect(&'a wgpu::Buffer, usize),
+}
+impl DrawInstances<'_> {
+    pub fn indirect_offset(draw: usize) -> wgpu::BufferAddress {
+        (draw * std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>()) as wgpu::BufferAddress
+    }
+}
+//the instances the camera passes draw at one level of detail, from a slice of an instance buffer
+#[derive(Debug, Clone)]
+pub struct LodInstances<'a> {
+    pub level: usize,
+    pub instance_buffer: wgpu::BufferSlice<'a>,
+    pub instances: DrawInstances<'a>,
+}
 pub trait DrawModel<'a> {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'a Mesh,
-        material: &'a Material,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
@@ -126,16 +573,26 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
-    fn draw_model(
+    #[allow(clippy::too_many_arguments)]
+    fn draw_mesh_indirect(
         &mut self,
-        model: &'a Model,
+        mesh: &'a Mesh,
+        level: usize,
+        material: &'a Material,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
-    fn draw_model_instanced(
+    //the mesh's instances at a level of detail, either drawn directly or from its indirect draw
+    #[allow(clippy::too_many_arguments)]
+    fn draw_mesh_instances(
         &mut self,
-        model: &'a Model,
-        instances: Range<u32>,
+        mesh_index: usize,
+        mesh: &'a Mesh,
+        level: usize,
+        material: &'a Material,
+        instances: &DrawInstances<'a>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
@@ -144,15 +601,6 @@ impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        material: &'a Material,
-        camera_bind_group: &'a wgpu::BindGroup,
-        light_bind_group: &'a wgpu::BindGroup,
-    ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
-    }
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
@@ -168,29 +616,53 @@ where
         self.set_bind_group(2, light_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
-    fn draw_model(
+    fn draw_mesh_indirect(
         &mut self,
-        model: &'b Model,
+        mesh: &'b Mesh,
+        level: usize,
+        material: &'a Material,
+        indirect_buffer: &'b wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     ) {
-        self.draw_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
+        let (index_buffer, _) = mesh.lod(level);
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect_buffer, indirect_offset);
     }
-    fn draw_model_instanced(
+    fn draw_mesh_instances(
         &mut self,
-        model: &'b Model,
-        instances: Range<u32>,
+        mesh_index: usize,
+        mesh: &'b Mesh,
+        level: usize,
+        material: &'a Material,
+        instances: &DrawInstances<'b>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     ) {
-        for mesh in &model.meshes {
-            self.draw_mesh_instanced(
+        match instances {
+            DrawInstances::Range(range) => {
+                let (index_buffer, num_elements) = mesh.lod(level);
+                self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                self.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                self.set_bind_group(0, &material.bind_group, &[]);
+                self.set_bind_group(1, camera_bind_group, &[]);
+                self.set_bind_group(2, light_bind_group, &[]);
+                self.draw_indexed(0..num_elements, 0, range.clone());
+            }
+            DrawInstances::Indirect(buffer, first_draw) => self.draw_mesh_indirect(
                 mesh,
-                &model.materials[mesh.material],
-                instances.clone(),
+                level,
+                material,
+                buffer,
+                DrawInstances::indirect_offset(first_draw + mesh_index),
                 camera_bind_group,
                 light_bind_group,
-            );
+            ),
         }
     }
 }
@@ -274,3 +746,45 @@ where
         }
     }
 }
+
+pub trait DrawShadow<'a> {
+    fn draw_shadow_mesh_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        instances: Range<u32>,
+        shadow_view_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_shadow_model_instanced(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        shadow_view_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawShadow<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_shadow_mesh_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        instances: Range<u32>,
+        shadow_view_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, shadow_view_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+    fn draw_shadow_model_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        shadow_view_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            self.draw_shadow_mesh_instanced(mesh, instances.clone(), shadow_view_bind_group);
+        }
+    }
+}
@@ -0,0 +1,641 @@
+use std::time::Duration;
+
+use wgpu::util::DeviceExt;
+
+use crate::{material::parse_wgsl, resources, shaders, textures::Texture};
+
+//a 3d colour lookup table, red varying fastest, then green, then blue
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    pub size: u32,
+    pub data: Vec<[f32; 3]>,
+}
+impl Lut3d {
+    pub fn identity(size: u32) -> Self {
+        let size = size.max(2);
+        let step = 1.0 / (size - 1) as f32;
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push([r as f32 * step, g as f32 * step, b as f32 * step]);
+                }
+            }
+        }
+        Self { size, data }
+    }
+    //reads a lut from the luts folder of the assets
+    pub fn load(file_name: &str) -> anyhow::Result<Self> {
+        let bytes = resources::read_game_assets(&format!("luts/{}", file_name))?;
+        if bytes.is_empty() {
+            anyhow::bail!("{}: not found in the luts folder", file_name);
+        }
+        Self::parse_cube(file_name, std::str::from_utf8(&bytes)?)
+    }
+    //parses the .cube format used by resolve, photoshop and most grading tools
+    pub fn parse_cube(name: &str, source: &str) -> anyhow::Result<Self> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut data = vec![];
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap();
+            let floats = |words: std::str::SplitWhitespace| -> anyhow::Result<[f32; 3]> {
+                let values = words
+                    .map(str::parse::<f32>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| anyhow::anyhow!("{}:{}: {}", name, number + 1, e))?;
+                values
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("{}:{}: expected 3 values", name, number + 1))
+            };
+            match keyword {
+                "LUT_1D_SIZE" => anyhow::bail!("{}: 1D luts are not supported", name),
+                "LUT_3D_SIZE" => {
+                    size = Some(
+                        words
+                            .next()
+                            .and_then(|size| size.parse::<u32>().ok())
+                            .filter(|size| (2..=256).contains(size))
+                            .ok_or_else(|| {
+                                anyhow::anyhow!("{}:{}: invalid lut size", name, number + 1)
+                            })?,
+                    );
+                }
+                "DOMAIN_MIN" => domain_min = floats(words)?,
+                "DOMAIN_MAX" => domain_max = floats(words)?,
+                //TITLE and any other keyword we don't use
+                _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+                _ => data.push(floats(line.split_whitespace())?),
+            }
+        }
+        let size = size.ok_or_else(|| anyhow::anyhow!("{}: missing LUT_3D_SIZE", name))?;
+        if data.len() != (size * size * size) as usize {
+            anyhow::bail!(
+                "{}: expected {} entries, found {}",
+                name,
+                size * size * size,
+                data.len()
+            );
+        }
+        if domain_min != [0.0; 3] || domain_max != [1.0; 3] {
+            anyhow::bail!("{}: only luts with a 0 to 1 domain are supported", name);
+        }
+        Ok(Self { size, data })
+    }
+    fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        let texels = self
+            .data
+            .iter()
+            .flat_map(|[r, g, b]| {
+                [*r, *g, *b, 1.0].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect::<Vec<_>>();
+        device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("LUT Texture"),
+                size: wgpu::Extent3d {
+                    width: self.size,
+                    height: self.size,
+                    depth_or_array_layers: self.size,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &texels,
+        )
+    }
+}
+//an effect to add to the post-process stack, the shader is appended to shaders/post.wgsl and needs an fs_main
+#[derive(Debug, Clone)]
+pub struct PostEffectDescriptor {
+    pub name: String,
+    pub shader: String,
+    //the effect's uniform in group 1 binding 0, laid out as the shader's struct
+    pub params: Vec<f32>,
+    //bound to lut_texture, an identity lut when there is none
+    pub lut: Option<Lut3d>,
+    pub enabled: bool,
+}
+impl PostEffectDescriptor {
+    pub fn new(name: &str, shader: &str, params: &[f32]) -> Self {
+        Self {
+            name: name.to_string(),
+            shader: shader.to_string(),
+            params: params.to_vec(),
+            lut: None,
+            enabled: true,
+        }
+    }
+    pub fn fxaa() -> Self {
+        Self::new(
+            "fxaa",
+            &shaders::source("post_fxaa.wgsl"),
+            &[8.0, 1.0 / 8.0, 1.0 / 128.0],
+        )
+    }
+    pub fn vignette() -> Self {
+        Self::new(
+            "vignette",
+            &shaders::source("post_vignette.wgsl"),
+            &[0.4, 0.75, 0.6],
+        )
+    }
+    pub fn chromatic_aberration() -> Self {
+        Self::new(
+            "chromatic_aberration",
+            &shaders::source("post_chromatic_aberration.wgsl"),
+            &[0.006],
+        )
+    }
+    pub fn film_grain() -> Self {
+        Self::new(
+            "film_grain",
+            &shaders::source("post_film_grain.wgsl"),
+            &[0.05, 1.5],
+        )
+    }
+    pub fn color_grading(lut: Lut3d) -> Self {
+        Self {
+            lut: Some(lut),
+            ..Self::new(
+                "color_grading",
+                &shaders::source("post_color_grading.wgsl"),
+                &[1.0],
+            )
+        }
+    }
+}
+//what the renderer hands the effects besides the colour
+pub struct PostInputs<'a> {
+    pub depth: &'a wgpu::TextureView,
+    pub normals: Option<&'a wgpu::TextureView>,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostGlobals {
+    resolution: [f32; 2],
+    time: f32,
+    frame: u32,
+    output_is_srgb: u32,
+    _padding: [u32; 3],
+}
+struct PostEffect {
+    name: String,
+    //without the prelude, which is recompiled with it when it changes
+    shader: String,
+    enabled: bool,
+    pipeline: wgpu::RenderPipeline,
+    params_buffer: wgpu::Buffer,
+    params_len: usize,
+    bind_group: wgpu::BindGroup,
+}
+//runs the enabled effects in order, ping-ponging between two targets and writing the last to the output
+pub struct PostProcessStack {
+    format: wgpu::TextureFormat,
+    layout: wgpu::BindGroupLayout,
+    effect_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    globals: PostGlobals,
+    globals_buffer: wgpu::Buffer,
+    targets: [Texture; 2],
+    //flat normals for when the renderer has no normal buffer
+    fallback_normals: wgpu::TextureView,
+    identity_lut: wgpu::TextureView,
+    effects: Vec<PostEffect>,
+}
+impl PostProcessStack {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture = |binding, sample_type, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let float = wgpu::TextureSampleType::Float { filterable: true };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Process Layout"),
+            entries: &[
+                texture(0, float, wgpu::TextureViewDimension::D2),
+                sampler(1),
+                texture(
+                    2,
+                    wgpu::TextureSampleType::Depth,
+                    wgpu::TextureViewDimension::D2,
+                ),
+                texture(3, float, wgpu::TextureViewDimension::D2),
+                uniform(4),
+            ],
+        });
+        let effect_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Effect Layout"),
+            entries: &[
+                uniform(0),
+                texture(1, float, wgpu::TextureViewDimension::D3),
+                sampler(2),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[&layout, &effect_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Process Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let globals = PostGlobals {
+            resolution: [width as f32, height as f32],
+            time: 0.0,
+            frame: 0,
+            output_is_srgb: format.is_srgb() as u32,
+            _padding: [0; 3],
+        };
+        let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Process Globals"),
+            contents: bytemuck::cast_slice(&[globals]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let fallback_normals = device
+            .create_texture_with_data(
+                queue,
+                &wgpu::TextureDescriptor {
+                    label: Some("Fallback Normals"),
+                    size: wgpu::Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+                wgpu::util::TextureDataOrder::LayerMajor,
+                &[128, 128, 255, 255],
+            )
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let identity_lut = Lut3d::identity(2)
+            .create_texture(device, queue)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            targets: Self::create_targets(device, format, width, height),
+            format,
+            layout,
+            effect_layout,
+            pipeline_layout,
+            sampler,
+            globals,
+            globals_buffer,
+            fallback_normals,
+            identity_lut,
+            effects: vec![],
+        }
+    }
+    fn create_targets(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> [Texture; 2] {
+        ["Post Process Target A", "Post Process Target B"].map(|label| {
+            Texture::create_2d_texture(
+                device,
+                Some(label),
+                width,
+                height,
+                format,
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                wgpu::FilterMode::Linear,
+            )
+        })
+    }
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.targets = Self::create_targets(device, self.format, width, height);
+        self.globals.resolution = [width as f32, height as f32];
+    }
+    pub fn update(&mut self, queue: &wgpu::Queue, delta_time: Duration) {
+        self.globals.time += delta_time.as_secs_f32();
+        self.globals.frame = self.globals.frame.wrapping_add(1);
+        queue.write_buffer(
+            &self.globals_buffer,
+            0,
+            bytemuck::cast_slice(&[self.globals]),
+        );
+    }
+    //compiles the effect and adds it after the others, or replaces the effect with the same name in place
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        descriptor: PostEffectDescriptor,
+    ) -> anyhow::Result<()> {
+        let index = self.effects.len();
+        let index = self.position(&descriptor.name).unwrap_or(index);
+        self.insert(device, queue, index, descriptor)
+    }
+    pub fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        index: usize,
+        descriptor: PostEffectDescriptor,
+    ) -> anyhow::Result<()> {
+        let pipeline = self.create_pipeline(device, &descriptor.name, &descriptor.shader)?;
+        //uniforms are at least 16 bytes and a multiple of 16
+        let mut params = descriptor.params.clone();
+        params.resize(params.len().div_ceil(4).max(1) * 4, 0.0);
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Effect Params"),
+            contents: bytemuck::cast_slice(&params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let lut = descriptor.lut.as_ref().map(|lut| {
+            lut.create_texture(device, queue)
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+        let bind_group = self.create_effect_bind_group(device, &params_buffer, lut.as_ref());
+        let effect = PostEffect {
+            name: descriptor.name,
+            shader: descriptor.shader,
+            enabled: descriptor.enabled,
+            pipeline,
+            params_buffer,
+            params_len: params.len(),
+            bind_group,
+        };
+        match self.position(&effect.name) {
+            Some(existing) => {
+                self.effects.remove(existing);
+                self.effects.insert(index.min(self.effects.len()), effect);
+            }
+            None => self.effects.insert(index.min(self.effects.len()), effect),
+        }
+        Ok(())
+    }
+    //recompiles the effects whose shader or the prelude changed, the built in effects reread
+    //their file
+    pub fn reload(&mut self, device: &wgpu::Device, changed: &[String]) -> anyhow::Result<()> {
+        let prelude_changed = changed.iter().any(|name| name == "post.wgsl");
+        for index in 0..self.effects.len() {
+            let file = format!("post_{}.wgsl", self.effects[index].name);
+            let shader = if changed.contains(&file) {
+                shaders::source(&file).into_owned()
+            } else if prelude_changed {
+                self.effects[index].shader.clone()
+            } else {
+                continue;
+            };
+            self.effects[index].pipeline =
+                self.create_pipeline(device, &self.effects[index].name, &shader)?;
+            self.effects[index].shader = shader;
+        }
+        Ok(())
+    }
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        name: &str,
+        shader: &str,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        let source = format!("{}\n{}", shaders::source("post.wgsl"), shader);
+        let (module, _) = parse_wgsl(name, &source)?;
+        if !module.entry_points.iter().any(|e| e.name == "fs_main") {
+            anyhow::bail!("{}: missing entry point fs_main", name);
+        }
+        shaders::build(device, || {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(name),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(name),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: self.format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        })
+    }
+    fn create_effect_bind_group(
+        &self,
+        device: &wgpu::Device,
+        params_buffer: &wgpu::Buffer,
+        lut: Option<&wgpu::TextureView>,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post Effect Bind Group"),
+            layout: &self.effect_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(lut.unwrap_or(&self.identity_lut)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+    fn position(&self, name: &str) -> Option<usize> {
+        self.effects.iter().position(|effect| effect.name == name)
+    }
+    pub fn remove(&mut self, name: &str) -> anyhow::Result<()> {
+        let index = self
+            .position(name)
+            .ok_or_else(|| anyhow::anyhow!("no post effect named {}", name))?;
+        self.effects.remove(index);
+        Ok(())
+    }
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.effects.iter().map(|effect| effect.name.as_str())
+    }
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.position(name)
+            .is_some_and(|index| self.effects[index].enabled)
+    }
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> anyhow::Result<()> {
+        let index = self
+            .position(name)
+            .ok_or_else(|| anyhow::anyhow!("no post effect named {}", name))?;
+        self.effects[index].enabled = enabled;
+        Ok(())
+    }
+    pub fn set_params(
+        &mut self,
+        queue: &wgpu::Queue,
+        name: &str,
+        params: &[f32],
+    ) -> anyhow::Result<()> {
+        let index = self
+            .position(name)
+            .ok_or_else(|| anyhow::anyhow!("no post effect named {}", name))?;
+        let effect = &self.effects[index];
+        if params.len() > effect.params_len {
+            anyhow::bail!(
+                "{}: takes at most {} params, got {}",
+                name,
+                effect.params_len,
+                params.len()
+            );
+        }
+        queue.write_buffer(&effect.params_buffer, 0, bytemuck::cast_slice(params));
+        Ok(())
+    }
+    pub fn set_lut(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        lut: &Lut3d,
+    ) -> anyhow::Result<()> {
+        let index = self
+            .position(name)
+            .ok_or_else(|| anyhow::anyhow!("no post effect named {}", name))?;
+        let view = lut
+            .create_texture(device, queue)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group =
+            self.create_effect_bind_group(device, &self.effects[index].params_buffer, Some(&view));
+        self.effects[index].bind_group = bind_group;
+        Ok(())
+    }
+    pub fn is_active(&self) -> bool {
+        self.effects.iter().any(|effect| effect.enabled)
+    }
+    //where the tone mapped scene should be written when the stack is active
+    pub fn input(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+    pub fn process(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        inputs: PostInputs,
+        output: &wgpu::TextureView,
+    ) {
+        let enabled = self
+            .effects
+            .iter()
+            .filter(|effect| effect.enabled)
+            .collect::<Vec<_>>();
+        for (i, effect) in enabled.iter().enumerate() {
+            let source = &self.targets[i % 2].view;
+            let target = if i + 1 == enabled.len() {
+                output
+            } else {
+                &self.targets[(i + 1) % 2].view
+            };
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Post Process Bind Group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(inputs.depth),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(
+                            inputs.normals.unwrap_or(&self.fallback_normals),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: self.globals_buffer.as_entire_binding(),
+                    },
+                ],
+            });
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(&effect.name),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                ..Default::default()
+            });
+            pass.set_pipeline(&effect.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.set_bind_group(1, &effect.bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -1,12 +1,29 @@
-use std::{rc::Rc, sync::Arc, time::Duration};
+use std::{ops::Range, rc::Rc, sync::Arc, time::Duration};
 
 use crate::{
+    antialiasing::{AntiAliasing, Multisampling, TemporalAa},
+    bloom::BloomSettings,
     camera::{Camera, CameraController, CameraUniform, Projection},
+    culling::Frustum,
+    debug_draw::{DebugDraw, DebugDrawPass},
+    debug_view::{DebugView, DebugViews},
+    deferred::DeferredRenderer,
+    exposure::AutoExposureSettings,
+    gpu_culling::{CullingMode, GpuCulling},
     hdr,
-    model::{self, Vertex},
+    ibl::{self, Environment},
+    light::{DirectionalLight, Lights, PointLight, SpotLight},
+    lod::{LodSettings, MAX_LODS},
+    material::MaterialLibrary,
+    model::{self, DrawInstances, LodInstances, MaterialKind, Vertex},
+    post::{Lut3d, PostEffectDescriptor, PostInputs, PostProcessStack},
     resources,
+    shaders::{self, ShaderWatcher},
+    shadow::{ShadowMaps, ShadowSettings},
+    ssao::{Ssao, SsaoSettings},
     text::TextEngine,
-    textures::Texture,
+    textures::{CubeTexture, Texture, TextureRegion},
+    transparent::{TransparencyMode, TransparentPass},
 };
 use bytemuck;
 use cgmath::prelude::*;
@@ -18,28 +35,73 @@ use winit::window::CursorGrabMode;
 
 const NUM_INSTANCES_PER_ROW: u32 = 10;
 
+//how the opaque standard materials are lit, chosen when the renderer is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderPath {
+    //every light is evaluated while drawing the meshes
+    #[default]
+    Forward,
+    //the meshes write their material to a g-buffer, which is then lit once per light volume
+    Deferred,
+    //forward, with each fragment only evaluating the lights assigned to its cluster
+    Clustered,
+}
+impl RenderPath {
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderPath::Forward => "forward",
+            RenderPath::Deferred => "deferred",
+            RenderPath::Clustered => "clustered",
+        }
+    }
+}
+
 struct Instance {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
+    //the layer and tile of a batched material's textures
+    texture: TextureRegion,
 }
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceRaw {
+pub struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
+    uv_transform: [f32; 4],
+    texture_layer: u32,
+    //the dithered cross-fade between levels of detail, 0 draws every pixel
+    lod_fade: f32,
 }
 impl Instance {
     fn to_raw(&self) -> InstanceRaw {
+        let TextureRegion {
+            layer,
+            uv_offset: [offset_x, offset_y],
+            uv_scale: [scale_x, scale_y],
+        } = self.texture;
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.position)
                 * cgmath::Matrix4::from(self.rotation))
             .into(),
             normal: cgmath::Matrix3::from(self.rotation).into(),
+            uv_transform: [offset_x, offset_y, scale_x, scale_y],
+            texture_layer: layer,
+            lod_fade: 0.0,
         }
     }
 }
 impl InstanceRaw {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
+    pub fn position(&self) -> cgmath::Vector3<f32> {
+        let [x, y, z, _] = self.model[3];
+        cgmath::Vector3::new(x, y, z)
+    }
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.model.into()
+    }
+    pub fn with_lod_fade(self, lod_fade: f32) -> Self {
+        Self { lod_fade, ..self }
+    }
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
@@ -80,28 +142,72 @@ impl InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 29]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 30]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
 }
 
+//counts from the last update, shown in the debug overlay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub instances: u32,
+    //instances inside the camera frustum, drawn by the camera passes. with gpu culling this is
+    //read back from the gpu and lags a frame or two behind
+    pub visible_instances: u32,
+    pub culled_instances: u32,
+    //instances drawn at each level of detail, cross-fading ones count at both levels
+    pub lod_instances: [u32; MAX_LODS],
+}
 pub struct Renderer<'a> {
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     surface: wgpu::Surface<'a>,
     render_pipeline: wgpu::RenderPipeline,
+    //kept to rebuild the scene pipelines when the sample count changes
+    render_pipeline_layout: wgpu::PipelineLayout,
+    light_pipeline_layout: wgpu::PipelineLayout,
+    sky_pipeline_layout: wgpu::PipelineLayout,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
     camera: Camera,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
     pub camera_controller: CameraController,
     instances: Vec<Instance>,
+    //every instance, for the shadow maps which also need casters outside the view
     instance_buffer: wgpu::Buffer,
+    //the instances left after frustum culling, rewritten every update, grouped by level of detail
+    visible_instance_buffer: wgpu::Buffer,
+    //each level's instances in the visible instance buffer
+    lod_ranges: Vec<Range<u32>>,
+    lod: LodSettings,
+    frame_stats: FrameStats,
+    culling_mode: CullingMode,
+    gpu_culling: GpuCulling,
+    debug_draw: DebugDraw,
+    debug_draw_pass: DebugDrawPass,
+    debug_views: DebugViews,
     depth_texture: Texture,
     obj_model: model::Model,
-    light_bind_group: wgpu::BindGroup,
-    light_uniform: LightUniform,
+    lights: Lights,
+    shadow_maps: ShadowMaps,
     light_render_pipeline: wgpu::RenderPipeline,
     projection: Projection,
     window: Arc<winit::window::Window>,
@@ -111,19 +217,28 @@ pub struct Renderer<'a> {
     text_renderer: TextRenderer,
     atlas: TextAtlas,
     hdr_pipeline: hdr::HdrPipeline,
+    post_process: PostProcessStack,
     sky_pipeline: wgpu::RenderPipeline,
+    //kept so it can be baked back to a file
+    sky_texture: CubeTexture,
     environment_bind_group: wgpu::BindGroup,
-}
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct LightUniform {
-    position: [f32; 3],
-    _padding: u32,
-    color: [f32; 3],
-    _padding2: u32,
+    environment: Environment,
+    ssao: Ssao,
+    render_path: RenderPath,
+    //only created for the deferred path
+    deferred: Option<DeferredRenderer>,
+    materials: MaterialLibrary,
+    transparent: TransparentPass,
+    anti_aliasing: AntiAliasing,
+    //the msaa sample counts the adapter supports for the hdr and depth formats
+    msaa_sample_counts: Vec<u32>,
+    multisampling: Option<Multisampling>,
+    taa: Option<TemporalAa>,
+    debug_overlay: bool,
+    shader_watcher: ShaderWatcher,
 }
 impl<'a> Renderer<'a> {
-    pub async fn new(window: Arc<winit::window::Window>) -> Self {
+    pub async fn new(window: Arc<winit::window::Window>, render_path: RenderPath) -> Self {
         let instance = wgpu::Instance::default();
         let surface = instance.create_surface(window.clone()).unwrap();
 
@@ -146,6 +261,26 @@ impl<'a> Renderer<'a> {
             )
             .await
             .unwrap();
+        //without the adapter specific format features only 4x is guaranteed
+        let msaa_sample_counts = [2, 4, 8]
+            .into_iter()
+            .filter(|&count| {
+                if !device
+                    .features()
+                    .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+                {
+                    return count == 4;
+                }
+                [hdr::HdrPipeline::FORMAT, Texture::DEPTH_FORMAT]
+                    .into_iter()
+                    .all(|format| {
+                        adapter
+                            .get_texture_format_features(format)
+                            .flags
+                            .sample_count_supported(count)
+                    })
+            })
+            .collect::<Vec<_>>();
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface
@@ -164,54 +299,8 @@ impl<'a> Renderer<'a> {
             desired_maximum_frame_latency: 1,
         };
         surface.configure(&device, &config);
-        let light_uniform = LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
         let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-
-                label: Some("texture_bind_group_layout"),
-            });
+        let texture_bind_group_layout = model::Material::create_bind_group_layout(&device);
 
         let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
         let projection =
@@ -245,81 +334,137 @@ impl<'a> Renderer<'a> {
             }],
             label: Some("camera_bind_group"),
         });
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light Buffer"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        let shadow_maps = ShadowMaps::new(&device, ShadowSettings::default());
+        let mut lights = Lights::new(&device, &shadow_maps);
+        lights.directional = Some(DirectionalLight {
+            direction: cgmath::Vector3::new(-0.4, -1.0, -0.3),
+            color: [1.0, 0.95, 0.9],
+            intensity: 3.0,
+            cast_shadows: true,
         });
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("Light bind group ly"),
-            });
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: Some("Light Bind Group"),
+        lights.point_lights.push(PointLight {
+            position: cgmath::Vector3::new(2.0, 2.0, 2.0),
+            color: [1.0, 1.0, 1.0],
+            intensity: 20.0,
+            range: 20.0,
+            cast_shadows: true,
         });
-        let render_pipeline_ly = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render pipeline"),
-            bind_group_layouts: &[
-                &texture_bind_group_layout,
-                &camera_bind_group_layout,
-                &light_bind_group_layout,
-            ],
-            push_constant_ranges: &[],
+        lights.spot_lights.push(SpotLight {
+            position: cgmath::Vector3::new(0.0, 8.0, 0.0),
+            direction: cgmath::Vector3::new(0.0, -1.0, 0.0),
+            color: [1.0, 0.8, 0.6],
+            intensity: 60.0,
+            range: 25.0,
+            inner_angle: cgmath::Deg(20.0).into(),
+            outer_angle: cgmath::Deg(30.0).into(),
+            cast_shadows: true,
         });
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shader.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_ly,
-                config.format,
-                Some(Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-                wgpu::PrimitiveTopology::TriangleList,
-                "Main Render Pipeline",
-            )
+        lights
+            .clusters_mut()
+            .set_enabled(render_path == RenderPath::Clustered);
+        //the environment map provides the ambient light now
+        lights.ambient = [0.0; 3];
+        let hdr_loader = resources::HdrLoader::new(&device);
+        //a sky baked with --bake-sky skips the equirectangular conversion
+        let sky_file = match resources::read_game_assets("sky.ktx2") {
+            Ok(bytes) if !bytes.is_empty() => "sky.ktx2",
+            _ => "sky.hdr",
         };
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Render Pipeline"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+        let sky_texture =
+            resources::load_cube_texture(sky_file, &device, &queue, &hdr_loader, 1080)
+                .expect("Failed to load sky texture");
+        let ssao = Ssao::new(
+            &device,
+            &texture_bind_group_layout,
+            &camera_bind_group_layout,
+            &depth_texture.view,
+            config.width,
+            config.height,
+        );
+        let ibl_baker = ibl::IblBaker::new(&device);
+        let environment = Environment::new(
+            &device,
+            ibl_baker.bake(&device, &queue, &sky_texture),
+            ssao.view(),
+        );
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render pipeline"),
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    lights.layout(),
+                    environment.layout(),
+                ],
                 push_constant_ranges: &[],
             });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/light.wgsl").into()),
-            };
-            create_render_pipeline(
+        let transparent = TransparentPass::new(
+            &device,
+            &render_pipeline_layout,
+            hdr::HdrPipeline::FORMAT,
+            1,
+            config.width,
+            config.height,
+        );
+        let deferred = (render_path == RenderPath::Deferred).then(|| {
+            DeferredRenderer::new(
                 &device,
-                &layout,
-                config.format,
-                Some(Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-                wgpu::PrimitiveTopology::TriangleList,
-                "Light Render Pipeline",
+                &render_pipeline_layout,
+                &camera_bind_group_layout,
+                lights.layout(),
+                environment.layout(),
+                hdr::HdrPipeline::FORMAT,
+                &depth_texture.view,
+                config.width,
+                config.height,
             )
-        };
-        let obj_model =
-            resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout).unwrap();
+        });
+        let light_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light Render Pipeline"),
+                bind_group_layouts: &[&camera_bind_group_layout, lights.layout()],
+                push_constant_ranges: &[],
+            });
+        let hdr_pipeline = hdr::HdrPipeline::new(&device, &config);
+        let mut post_process =
+            PostProcessStack::new(&device, &queue, config.format, config.width, config.height);
+        for effect in [
+            PostEffectDescriptor::fxaa(),
+            PostEffectDescriptor::chromatic_aberration(),
+            PostEffectDescriptor::color_grading(Lut3d::identity(2)),
+            PostEffectDescriptor::vignette(),
+            PostEffectDescriptor::film_grain(),
+        ] {
+            post_process
+                .add(
+                    &device,
+                    &queue,
+                    PostEffectDescriptor {
+                        enabled: false,
+                        ..effect
+                    },
+                )
+                .expect("Failed to create the built in post effects");
+        }
+        let mut materials = MaterialLibrary::new(&device, hdr_pipeline.format());
+        let obj_model = resources::load_model(
+            "cube.obj",
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            &mut materials,
+        )
+        .unwrap();
+        materials.prepare_model(
+            &device,
+            &obj_model,
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            &[
+                &camera_bind_group_layout,
+                lights.layout(),
+                environment.layout(),
+            ],
+        );
         let camera_controller = CameraController::new(1000.0, 10.0);
         const SPACE_BETWEEN: f32 = 3.0;
         let instances = (0..NUM_INSTANCES_PER_ROW)
@@ -338,7 +483,11 @@ impl<'a> Renderer<'a> {
                     } else {
                         cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
                     };
-                    Instance { position, rotation }
+                    Instance {
+                        position,
+                        rotation,
+                        texture: TextureRegion::default(),
+                    }
                 })
             })
             .collect::<Vec<_>>();
@@ -346,7 +495,17 @@ impl<'a> Renderer<'a> {
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance Buffer"),
             contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
+            //read as storage by the gpu culling, rewritten when an instance's texture changes
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
+        let visible_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            //cross-fading instances are drawn at two levels
+            size: 2 * std::mem::size_of_val(instance_data.as_slice()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
         let mut atlas = TextAtlas::new(&device, &queue, config.format);
 
@@ -364,13 +523,6 @@ impl<'a> Renderer<'a> {
             }),
         );
         text_engine.add_buffer(30.0, 42.0);
-        let hdr_pipeline = hdr::HdrPipeline::new(&device, &config);
-        let hdr_loader = resources::HdrLoader::new(&device);
-        let sky = resources::read_game_assets("sky.hdr").unwrap();
-        let sky = sky.as_slice();
-        let sky_texture = hdr_loader
-            .from_ecuirectangular_bytes(&device, &queue, sky, 1080, Some("Sky Texture"))
-            .expect("Failed to load sky texture");
 
         let environment_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -409,41 +561,65 @@ impl<'a> Renderer<'a> {
                 },
             ],
         });
-        let sky_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Sky Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &environment_layout],
-                push_constant_ranges: &[],
-            });
-            let shader = wgpu::include_wgsl!("../shaders/sky.wgsl");
-            create_render_pipeline(
-                &device,
-                &layout,
-                hdr_pipeline.format(),
-                Some(Texture::DEPTH_FORMAT),
-                &[],
-                shader,
-                wgpu::PrimitiveTopology::TriangleList,
-                "Sky Pipeline",
-            )
-        };
+        let sky_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[&camera_bind_group_layout, &environment_layout],
+            push_constant_ranges: &[],
+        });
+        let (render_pipeline, light_render_pipeline, sky_pipeline) = create_scene_pipelines(
+            &device,
+            &render_pipeline_layout,
+            &light_pipeline_layout,
+            &sky_pipeline_layout,
+            1,
+        );
+        let gpu_culling = GpuCulling::new(
+            &device,
+            &obj_model,
+            &instance_buffer,
+            instances.len() as u32,
+            &depth_texture.view,
+            config.width,
+            config.height,
+        );
+        let debug_draw_pass = DebugDrawPass::new(&device, hdr_pipeline.format());
+        let debug_views = DebugViews::new(
+            &device,
+            &camera_bind_group_layout,
+            config.width,
+            config.height,
+        );
         Self {
             device,
             queue,
             surface,
             config,
             render_pipeline,
+            render_pipeline_layout,
+            light_pipeline_layout,
+            sky_pipeline_layout,
+            texture_bind_group_layout,
+            camera_bind_group_layout,
             obj_model,
             camera,
             camera_uniform,
             camera_buffer,
             camera_bind_group,
-            light_bind_group,
+            lights,
+            shadow_maps,
             light_render_pipeline,
-            light_uniform,
             camera_controller,
             instances,
             instance_buffer,
+            visible_instance_buffer,
+            lod_ranges: Vec::new(),
+            lod: LodSettings::default(),
+            frame_stats: FrameStats::default(),
+            culling_mode: CullingMode::default(),
+            gpu_culling,
+            debug_draw: DebugDraw::default(),
+            debug_draw_pass,
+            debug_views,
             depth_texture,
             projection,
             window,
@@ -452,8 +628,22 @@ impl<'a> Renderer<'a> {
             text_renderer,
             text_engine,
             hdr_pipeline,
+            post_process,
+            sky_texture,
             environment_bind_group,
+            environment,
+            ssao,
+            render_path,
+            deferred,
             sky_pipeline,
+            materials,
+            transparent,
+            anti_aliasing: AntiAliasing::None,
+            msaa_sample_counts,
+            multisampling: None,
+            taa: None,
+            debug_overlay: false,
+            shader_watcher: ShaderWatcher::new(),
         }
     }
     pub fn render(&mut self) {
@@ -466,25 +656,100 @@ impl<'a> Renderer<'a> {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
+        self.shadow_maps.render(
+            &mut encoder,
+            &self.obj_model,
+            &self.instance_buffer,
+            0..self.instances.len() as u32,
+        );
+        self.lights.clusters().assign(&mut encoder);
+        if self.culling_mode.is_gpu() {
+            self.gpu_culling.cull(&mut encoder);
+        }
+        //the camera passes draw the instances left by whichever culling ran
+        let batches = if self.culling_mode.is_gpu() {
+            self.gpu_culling.batches()
+        } else {
+            self.lod_ranges
+                .iter()
+                .enumerate()
+                .filter(|(_, range)| !range.is_empty())
+                .map(|(level, range)| LodInstances {
+                    level,
+                    instance_buffer: self.visible_instance_buffer.slice(..),
+                    instances: DrawInstances::Range(range.clone()),
+                })
+                .collect()
+        };
+        self.ssao.render_prepass(
+            &mut encoder,
+            &self.depth_texture.view,
+            &self.obj_model,
+            &batches,
+            &self.camera_bind_group,
+            self.lights.bind_group(),
+        );
+        self.ssao.process(&mut encoder);
+        //the debug views that replace the shading draw every opaque mesh forward
+        let deferred = self
+            .deferred
+            .as_ref()
+            .filter(|_| !self.debug_views.replaces_shading());
+        if let Some(deferred) = deferred {
+            deferred.render_gbuffer(
+                &mut encoder,
+                &self.depth_texture.view,
+                &self.obj_model,
+                &batches,
+                &self.camera_bind_group,
+                self.lights.bind_group(),
+            );
+            deferred.render_lighting(
+                &mut encoder,
+                self.hdr_pipeline.view(),
+                self.lights.light_count(),
+                &self.camera_bind_group,
+                self.lights.bind_group(),
+                self.environment.bind_group(),
+            );
+        }
+        //with msaa the scene is drawn multisampled and resolved into the hdr target at the end
+        let (color_view, depth_view, resolve_target) = match &self.multisampling {
+            Some(multisampling) => (
+                multisampling.color_view(),
+                multisampling.depth_view(),
+                Some(self.hdr_pipeline.view()),
+            ),
+            None => (self.hdr_pipeline.view(), &self.depth_texture.view, None),
+        };
+        //the deferred path has already lit the opaque standard materials, the sky and the rest
+        //are drawn over them
+        let (color_load, depth_load) = match deferred {
+            Some(_) => (wgpu::LoadOp::Load, wgpu::LoadOp::Load),
+            None => (
+                wgpu::LoadOp::Clear(wgpu::Color {
+                    r: 0.1,
+                    g: 0.2,
+                    b: 0.3,
+                    a: 1.0,
+                }),
+                wgpu::LoadOp::Clear(1.0),
+            ),
+        };
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: color_load,
                         store: wgpu::StoreOp::Store,
                     },
-                    view: &self.hdr_pipeline.view(),
+                    view: color_view,
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
+                    view: depth_view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: depth_load,
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -494,30 +759,201 @@ impl<'a> Renderer<'a> {
                 occlusion_query_set: None,
             });
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-
             use crate::model::DrawLight;
             render_pass.set_pipeline(&self.light_render_pipeline);
-            render_pass.draw_light_model(
+            render_pass.draw_light_model_instanced(
                 &self.obj_model,
+                0..self.lights.point_light_count(),
                 &self.camera_bind_group,
-                &self.light_bind_group,
+                self.lights.bind_group(),
             );
             render_pass.set_pipeline(&self.sky_pipeline);
             render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
             render_pass.set_bind_group(1, &self.environment_bind_group, &[]);
             render_pass.draw(0..3, 0..1);
-            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(3, self.environment.bind_group(), &[]);
             use model::DrawModel;
-            render_pass.draw_model_instanced(
+            let vertex_layouts = [model::ModelVertex::desc(), InstanceRaw::desc()];
+            for batch in &batches {
+                render_pass.set_vertex_buffer(1, batch.instance_buffer);
+                for (index, mesh) in self.obj_model.meshes.iter().enumerate() {
+                    let material = &self.obj_model.materials[mesh.material];
+                    if material.is_transparent() {
+                        continue;
+                    }
+                    let pipeline = match &material.kind {
+                        MaterialKind::Custom(custom) => self
+                            .materials
+                            .pipeline(custom, &vertex_layouts)
+                            .unwrap_or(&self.render_pipeline),
+                        MaterialKind::Standard(_) if deferred.is_some() => continue,
+                        MaterialKind::Standard(_) => self
+                            .debug_views
+                            .shading_pipeline()
+                            .unwrap_or(&self.render_pipeline),
+                    };
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.draw_mesh_instances(
+                        index,
+                        mesh,
+                        batch.level,
+                        material,
+                        &batch.instances,
+                        &self.camera_bind_group,
+                        self.lights.bind_group(),
+                    );
+                }
+            }
+            self.debug_views.draw_overlay(
+                &mut render_pass,
                 &self.obj_model,
-                0..self.instances.len() as u32,
+                &batches,
+                &self.instance_buffer,
+                self.instances.len() as u32,
                 &self.camera_bind_group,
-                &self.light_bind_group,
             );
+        }
+        self.transparent.render_order_independent(
+            &mut encoder,
+            color_view,
+            depth_view,
+            &self.obj_model,
+            &self.camera_bind_group,
+            self.lights.bind_group(),
+            self.environment.bind_group(),
+        );
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    view: color_view,
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                label: Some("Transparent pass"),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_bind_group(3, self.environment.bind_group(), &[]);
+            self.transparent.render_sorted(
+                &mut render_pass,
+                &self.obj_model,
+                &self.materials,
+                &self.camera_bind_group,
+                self.lights.bind_group(),
+            );
+        }
+        if let Some(multisampling) = &self.multisampling {
+            multisampling.resolve_depth(&mut encoder, &self.depth_texture.view);
+        }
+        if let Some(taa) = &mut self.taa {
+            taa.process(&mut encoder, self.hdr_pipeline.texture());
+        }
+        self.debug_views.render_overdraw(
+            &mut encoder,
+            self.hdr_pipeline.view(),
+            &self.obj_model,
+            &batches,
+            &self.camera_bind_group,
+        );
+        //before the debug lines and the text, which write depth too
+        if self.culling_mode == CullingMode::GpuOcclusion {
+            self.gpu_culling.build_hiz(&mut encoder);
+        }
+        self.debug_draw_pass.render(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            self.hdr_pipeline.view(),
+            &self.depth_texture.view,
+            self.projection.calc_unjittered_matrix() * self.camera.calc_matrix(),
+            [self.config.width as f32, self.config.height as f32],
+            &mut self.debug_draw,
+        );
+        //the text is drawn after anti-aliasing so it isn't jittered or blended over time
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    view: self.hdr_pipeline.view(),
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                label: Some("Text pass"),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
 
+            let mut text = std::format!("FPS {:?} ", 60);
+            if let Some(error) = self.shader_watcher.error() {
+                text += &std::format!("\n{}", error);
+            }
+            if self.debug_overlay {
+                let tone_mapping = self.hdr_pipeline.tone_mapping();
+                let bloom = self.hdr_pipeline.bloom();
+                text += &std::format!(
+                    "\nTone mapping {} (T)\nExposure {:+.2} EV (-/=)\nAuto exposure {} (E)\nGamma {:.2}\nWhite point {:.1}\nBloom {:.2} threshold {:.2}\nInstances {} drawn, {} culled\nLevels of detail {}\nCulling {} (C)\nDebug view {} (V)\nRender path {}\nAnti-aliasing {} (M)\nAmbient occlusion {} (O)\nTransparency {} (B)\nPost {}",
+                    tone_mapping.operator.name(),
+                    tone_mapping.exposure,
+                    if self.hdr_pipeline.auto_exposure().enabled {
+                        "on"
+                    } else {
+                        "off"
+                    },
+                    tone_mapping.gamma,
+                    tone_mapping.white_point,
+                    bloom.intensity,
+                    bloom.threshold,
+                    self.frame_stats.visible_instances,
+                    self.frame_stats.culled_instances,
+                    if self.lod.enabled {
+                        self.frame_stats.lod_instances[..self.obj_model.lod_count()]
+                            .iter()
+                            .map(u32::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" / ")
+                    } else {
+                        "off".to_string()
+                    },
+                    self.culling_mode.name(),
+                    self.debug_views.view().name(),
+                    self.render_path.name(),
+                    self.anti_aliasing.name(),
+                    if self.ssao.settings().enabled {
+                        "on"
+                    } else {
+                        "off"
+                    },
+                    self.transparent.mode().name(),
+                    self.post_process
+                        .names()
+                        .filter(|name| self.post_process.is_enabled(name))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                );
+            }
             self.text_engine.set_text(
-                std::format!("FPS {:?} ", 60).as_str(),
+                text.as_str(),
                 [255, 128, 200, 255],
                 self.window.scale_factor(),
                 &self.config,
@@ -535,9 +971,27 @@ impl<'a> Renderer<'a> {
             );
         }
 
-        self.hdr_pipeline
-            .process(&mut encoder, &surface_texture_view);
+        //tone map straight to the surface unless there are post effects to run
+        if self.post_process.is_active() {
+            self.hdr_pipeline
+                .process(&mut encoder, self.post_process.input());
+            self.post_process.process(
+                &self.device,
+                &mut encoder,
+                PostInputs {
+                    depth: &self.depth_texture.view,
+                    normals: self.ssao.normals_view(),
+                },
+                &surface_texture_view,
+            );
+        } else {
+            self.hdr_pipeline
+                .process(&mut encoder, &surface_texture_view);
+        }
         self.queue.submit([encoder.finish()]);
+        if self.culling_mode.is_gpu() {
+            self.gpu_culling.read_back();
+        }
         surface_texture.present();
 
         self.atlas.trim();
@@ -548,22 +1002,140 @@ impl<'a> Renderer<'a> {
         // } else {
         //     self.window.set_cursor_hittest(true)?
         // }
+        //a failed reload is returned once the rest of the update has run, the old pipelines keep
+        //drawing in the meantime
+        let changed = self.shader_watcher.poll();
+        let reloaded = if changed.is_empty() {
+            Ok(())
+        } else {
+            let result = self.reload_shaders(&changed);
+            let loading_only = shaders::check_loading_only(&changed);
+            self.shader_watcher.report(changed, &result);
+            result.and(loading_only)
+        };
         self.camera_controller
             .update_camera(&mut self.camera, delta_time);
+        let jitter = match &mut self.taa {
+            Some(taa) => taa.next_jitter(),
+            None => [0.0; 2],
+        };
+        self.projection.set_jitter(jitter);
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
-                * old_position)
-                .into();
+        self.ssao.update(&self.queue, self.projection.calc_matrix());
+        self.lights
+            .clusters()
+            .update(&self.queue, &self.camera_uniform, &self.projection);
+        if let Some(taa) = &mut self.taa {
+            let view = self.camera.calc_matrix();
+            taa.update(
+                &self.queue,
+                self.projection.calc_matrix() * view,
+                self.projection.calc_unjittered_matrix() * view,
+            );
+        }
+        if let Some(light) = self.lights.point_lights.first_mut() {
+            light.position =
+                cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
+                    * light.position;
+        }
+        self.shadow_maps
+            .update(&self.queue, &self.camera, &self.projection, &self.lights);
+        self.hdr_pipeline.update(&self.queue, delta_time);
+        self.post_process.update(&self.queue, delta_time);
+        self.lights.write(&self.queue, &self.shadow_maps);
+        //instances whose bounds are outside the view frustum are left out of the camera passes
+        let view_proj = self.projection.calc_matrix() * self.camera.calc_matrix();
+        let (aabb, bounding_sphere) = self.obj_model.bounds();
+        let instance_count = self.instances.len() as u32;
+        //the projection's y scale turns a bounding sphere's radius into a fraction of the screen
+        let projection_scale = self.projection.calc_matrix().y.y;
+        let instance_data = if self.culling_mode.is_gpu() {
+            self.device.poll(wgpu::Maintain::Poll);
+            self.gpu_culling.update(
+                &self.queue,
+                view_proj,
+                self.camera.position,
+                projection_scale,
+                (aabb, bounding_sphere),
+                self.culling_mode == CullingMode::GpuOcclusion,
+                &self.lod,
+            );
+            let (visible_instances, lod_instances) = self
+                .gpu_culling
+                .visible_counts()
+                .unwrap_or((instance_count, [0; MAX_LODS]));
+            self.frame_stats = FrameStats {
+                instances: instance_count,
+                visible_instances,
+                culled_instances: instance_count.saturating_sub(visible_instances),
+                lod_instances,
+            };
+            //the transparent meshes are sorted on the cpu, and are few enough to go unculled
+            self.instances.iter().map(Instance::to_raw).collect()
+        } else {
+            let frustum = Frustum::from_matrix(view_proj);
+            let instance_data = self
+                .instances
+                .iter()
+                .map(Instance::to_raw)
+                .filter(|instance| {
+                    let model = instance.model_matrix();
+                    frustum.intersects_sphere(&bounding_sphere.transform(&model))
+                        && frustum.intersects_aabb(&aabb.transform(&model))
+                })
+                .collect::<Vec<_>>();
+            //the visible instances grouped by the level they are drawn at
+            let mut levels = vec![Vec::new(); self.obj_model.lod_count()];
+            for instance in &instance_data {
+                let sphere = bounding_sphere.transform(&instance.model_matrix());
+                let screen_size = LodSettings::screen_size(
+                    self.camera.position.to_vec().distance(sphere.center),
+                    sphere.radius,
+                    projection_scale,
+                );
+                let selection = self.lod.select(levels.len(), screen_size);
+                for (level, fade) in selection.instance_fades() {
+                    levels[level].push(instance.with_lod_fade(fade));
+                }
+            }
+            let mut lod_instances = [0; MAX_LODS];
+            self.lod_ranges.clear();
+            for (level, level_data) in levels.iter().enumerate() {
+                let start = self.lod_ranges.last().map_or(0, |range| range.end);
+                self.lod_ranges.push(start..start + level_data.len() as u32);
+                lod_instances[level] = level_data.len() as u32;
+            }
+            let level_data = levels.concat();
+            if !level_data.is_empty() {
+                self.queue.write_buffer(
+                    &self.visible_instance_buffer,
+                    0,
+                    bytemuck::cast_slice(&level_data),
+                );
+            }
+            self.frame_stats = FrameStats {
+                instances: instance_count,
+                visible_instances: instance_data.len() as u32,
+                culled_instances: instance_count - instance_data.len() as u32,
+                lod_instances,
+            };
+            instance_data
+        };
+        self.transparent.prepare(
+            &self.device,
+            &self.queue,
+            self.camera.calc_matrix(),
+            &self.obj_model,
+            &instance_data,
+        );
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
         self.window.request_redraw();
-        Ok(())
+        reloaded
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -573,8 +1145,430 @@ impl<'a> Renderer<'a> {
         self.projection.resize(new_size.width, new_size.height);
         self.depth_texture =
             Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        self.ssao.resize(
+            &self.device,
+            &self.depth_texture.view,
+            new_size.width,
+            new_size.height,
+        );
+        self.environment
+            .set_ambient_occlusion(&self.device, self.ssao.view());
+        self.debug_views
+            .resize(&self.device, new_size.width, new_size.height);
+        self.gpu_culling.resize(
+            &self.device,
+            &self.instance_buffer,
+            &self.depth_texture.view,
+            new_size.width,
+            new_size.height,
+        );
+        if let Some(deferred) = &mut self.deferred {
+            deferred.resize(
+                &self.device,
+                &self.depth_texture.view,
+                new_size.width,
+                new_size.height,
+            );
+        }
         self.hdr_pipeline
+            .resize(&self.device, &self.queue, new_size.width, new_size.height);
+        self.transparent
+            .resize(&self.device, new_size.width, new_size.height);
+        self.post_process
             .resize(&self.device, new_size.width, new_size.height);
+        if let Some(multisampling) = &mut self.multisampling {
+            multisampling.resize(&self.device, new_size.width, new_size.height);
+        }
+        if let Some(taa) = &mut self.taa {
+            taa.resize(
+                &self.device,
+                self.hdr_pipeline.view(),
+                &self.depth_texture.view,
+                new_size.width,
+                new_size.height,
+            );
+        }
+    }
+    pub fn shadow_settings(&self) -> ShadowSettings {
+        *self.shadow_maps.settings()
+    }
+    //map sizes and point shadow counts the adapter can't hold are an error
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) -> anyhow::Result<()> {
+        let limits = self.device.limits();
+        for size in [settings.map_size, settings.point_map_size] {
+            if size == 0 || size > limits.max_texture_dimension_2d {
+                anyhow::bail!(
+                    "shadow map size {} is outside 1 to {}",
+                    size,
+                    limits.max_texture_dimension_2d
+                );
+            }
+        }
+        if 6 * settings.max_shadowed_point_lights > limits.max_texture_array_layers {
+            anyhow::bail!(
+                "at most {} point lights can cast shadows",
+                limits.max_texture_array_layers / 6
+            );
+        }
+        self.shadow_maps.set_settings(&self.device, settings);
+        self.lights
+            .rebuild_bind_group(&self.device, &self.shadow_maps);
+        Ok(())
+    }
+    pub fn transparency_mode(&self) -> TransparencyMode {
+        self.transparent.mode()
+    }
+    pub fn set_transparency_mode(&mut self, mode: TransparencyMode) {
+        self.transparent.set_mode(mode);
+    }
+    pub fn set_environment_intensity(&self, intensity: f32) {
+        self.environment.set_intensity(&self.queue, intensity);
+    }
+    //writes the sky cube with its mips to a KTX2 file
+    pub fn bake_sky(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        self.sky_texture.save_ktx2(&self.device, &self.queue, path)
+    }
+    pub fn tone_mapping(&self) -> hdr::ToneMappingSettings {
+        self.hdr_pipeline.tone_mapping()
+    }
+    pub fn set_tone_mapping(&mut self, settings: hdr::ToneMappingSettings) {
+        self.hdr_pipeline.set_tone_mapping(&self.queue, settings);
+    }
+    pub fn auto_exposure(&self) -> AutoExposureSettings {
+        self.hdr_pipeline.auto_exposure()
+    }
+    pub fn set_auto_exposure(&mut self, settings: AutoExposureSettings) {
+        self.hdr_pipeline.set_auto_exposure(&self.queue, settings);
+    }
+    pub fn bloom(&self) -> BloomSettings {
+        self.hdr_pipeline.bloom()
+    }
+    pub fn set_bloom(&mut self, settings: BloomSettings) {
+        self.hdr_pipeline.set_bloom(&self.queue, settings);
+    }
+    //adds an effect after the others, replacing any effect with the same name
+    pub fn add_post_effect(&mut self, descriptor: PostEffectDescriptor) -> anyhow::Result<()> {
+        self.post_process.add(&self.device, &self.queue, descriptor)
+    }
+    pub fn remove_post_effect(&mut self, name: &str) -> anyhow::Result<()> {
+        self.post_process.remove(name)
+    }
+    pub fn set_post_effect_enabled(&mut self, name: &str, enabled: bool) -> anyhow::Result<()> {
+        self.post_process.set_enabled(name, enabled)
+    }
+    pub fn set_post_effect_params(&mut self, name: &str, params: &[f32]) -> anyhow::Result<()> {
+        self.post_process.set_params(&self.queue, name, params)
+    }
+    //loads a .cube lut from the assets' luts folder into the color grading effect and enables it
+    pub fn set_color_grading_lut(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let lut = Lut3d::load(file_name)?;
+        self.post_process
+            .set_lut(&self.device, &self.queue, "color_grading", &lut)?;
+        self.post_process.set_enabled("color_grading", true)
+    }
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_views.view()
+    }
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        self.debug_views.set_view(
+            &self.device,
+            view,
+            &self.obj_model,
+            &self.render_pipeline_layout,
+        );
+    }
+    //lines and shapes drawn over the next frame only
+    pub fn debug_draw(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+    pub fn lod(&self) -> LodSettings {
+        self.lod
+    }
+    pub fn set_lod(&mut self, lod: LodSettings) {
+        self.lod = lod;
+    }
+    pub fn culling_mode(&self) -> CullingMode {
+        self.culling_mode
+    }
+    pub fn set_culling_mode(&mut self, mode: CullingMode) {
+        self.culling_mode = mode;
+    }
+    //shifts which entry of its batched materials an instance draws with and transforms its uvs
+    pub fn set_instance_texture(
+        &mut self,
+        index: usize,
+        texture: TextureRegion,
+    ) -> anyhow::Result<()> {
+        let Some(instance) = self.instances.get_mut(index) else {
+            anyhow::bail!("no instance {}", index);
+        };
+        instance.texture = texture;
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            (index * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(&[instance.to_raw()]),
+        );
+        Ok(())
+    }
+    pub fn ssao(&self) -> SsaoSettings {
+        self.ssao.settings()
+    }
+    pub fn set_ssao(&mut self, settings: SsaoSettings) {
+        self.ssao.set_settings(settings);
+    }
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        self.anti_aliasing
+    }
+    //drops the taa history, for cuts where reprojecting the last frame would smear
+    pub fn reset_taa_history(&mut self) {
+        if let Some(taa) = &mut self.taa {
+            taa.reset();
+        }
+    }
+    //the msaa counts the adapter doesn't support are an error, as is msaa on the deferred path
+    pub fn set_anti_aliasing(&mut self, mode: AntiAliasing) -> anyhow::Result<()> {
+        let sample_count = mode.sample_count();
+        if sample_count > 1 && !self.msaa_sample_counts.contains(&sample_count) {
+            anyhow::bail!("{}x msaa is not supported by this adapter", sample_count);
+        }
+        //the g-buffer isn't multisampled
+        if sample_count > 1 && self.render_path == RenderPath::Deferred {
+            anyhow::bail!("msaa is not supported by the deferred render path");
+        }
+        if mode == AntiAliasing::Fxaa {
+            self.post_process.set_enabled("fxaa", true)?;
+        } else if self.post_process.is_enabled("fxaa") {
+            self.post_process.set_enabled("fxaa", false)?;
+        }
+        if sample_count != self.anti_aliasing.sample_count() {
+            self.set_sample_count(sample_count);
+        }
+        self.taa = (mode == AntiAliasing::Taa).then(|| {
+            TemporalAa::new(
+                &self.device,
+                self.hdr_pipeline.view(),
+                &self.depth_texture.view,
+                self.config.width,
+                self.config.height,
+            )
+        });
+        self.anti_aliasing = mode;
+        Ok(())
+    }
+    //rebuilds everything that draws into the scene's colour and depth targets
+    fn set_sample_count(&mut self, sample_count: u32) {
+        (
+            self.render_pipeline,
+            self.light_render_pipeline,
+            self.sky_pipeline,
+        ) = create_scene_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.light_pipeline_layout,
+            &self.sky_pipeline_layout,
+            sample_count,
+        );
+        let mode = self.transparent.mode();
+        self.transparent = TransparentPass::new(
+            &self.device,
+            &self.render_pipeline_layout,
+            hdr::HdrPipeline::FORMAT,
+            sample_count,
+            self.config.width,
+            self.config.height,
+        );
+        self.transparent.set_mode(mode);
+        self.materials.set_sample_count(sample_count);
+        self.debug_views.set_sample_count(
+            &self.device,
+            sample_count,
+            &self.obj_model,
+            &self.render_pipeline_layout,
+        );
+        self.materials.prepare_model(
+            &self.device,
+            &self.obj_model,
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            &[
+                &self.camera_bind_group_layout,
+                self.lights.layout(),
+                self.environment.layout(),
+            ],
+        );
+        self.multisampling = (sample_count > 1).then(|| {
+            Multisampling::new(
+                &self.device,
+                sample_count,
+                self.config.width,
+                self.config.height,
+            )
+        });
+    }
+    //rebuilds what uses the changed shaders, each part is only swapped in once it has built
+    //without errors so a broken shader leaves the old pipelines running
+    fn reload_shaders(&mut self, changed: &[String]) -> anyhow::Result<()> {
+        shaders::validate(changed)?;
+        let uses = |names: &[&str]| changed.iter().any(|name| names.contains(&name.as_str()));
+        let sample_count = self.anti_aliasing.sample_count();
+        if uses(&["shader.wgsl", "light.wgsl", "sky.wgsl"]) {
+            (
+                self.render_pipeline,
+                self.light_render_pipeline,
+                self.sky_pipeline,
+            ) = shaders::build(&self.device, || {
+                create_scene_pipelines(
+                    &self.device,
+                    &self.render_pipeline_layout,
+                    &self.light_pipeline_layout,
+                    &self.sky_pipeline_layout,
+                    sample_count,
+                )
+            })?;
+        }
+        if uses(&["shader.wgsl", "oit_composite.wgsl"]) {
+            let mut transparent = shaders::build(&self.device, || {
+                TransparentPass::new(
+                    &self.device,
+                    &self.render_pipeline_layout,
+                    hdr::HdrPipeline::FORMAT,
+                    sample_count,
+                    self.config.width,
+                    self.config.height,
+                )
+            })?;
+            transparent.set_mode(self.transparent.mode());
+            self.transparent = transparent;
+        }
+        if uses(&["shader.wgsl", "deferred.wgsl"]) && self.deferred.is_some() {
+            self.deferred = Some(shaders::build(&self.device, || {
+                DeferredRenderer::new(
+                    &self.device,
+                    &self.render_pipeline_layout,
+                    &self.camera_bind_group_layout,
+                    self.lights.layout(),
+                    self.environment.layout(),
+                    hdr::HdrPipeline::FORMAT,
+                    &self.depth_texture.view,
+                    self.config.width,
+                    self.config.height,
+                )
+            })?);
+        }
+        if uses(&["shader.wgsl", "ssao.wgsl"]) {
+            let mut ssao = shaders::build(&self.device, || {
+                Ssao::new(
+                    &self.device,
+                    &self.texture_bind_group_layout,
+                    &self.camera_bind_group_layout,
+                    &self.depth_texture.view,
+                    self.config.width,
+                    self.config.height,
+                )
+            })?;
+            ssao.set_settings(self.ssao.settings());
+            self.ssao = ssao;
+            self.environment
+                .set_ambient_occlusion(&self.device, self.ssao.view());
+        }
+        if uses(&["shader.wgsl", "debug_view.wgsl"]) {
+            self.debug_views = shaders::build(&self.device, || {
+                let mut debug_views = DebugViews::new(
+                    &self.device,
+                    &self.camera_bind_group_layout,
+                    self.config.width,
+                    self.config.height,
+                );
+                debug_views.set_sample_count(
+                    &self.device,
+                    sample_count,
+                    &self.obj_model,
+                    &self.render_pipeline_layout,
+                );
+                debug_views.set_view(
+                    &self.device,
+                    self.debug_views.view(),
+                    &self.obj_model,
+                    &self.render_pipeline_layout,
+                );
+                debug_views
+            })?;
+        }
+        if uses(&["debug.wgsl"]) {
+            self.debug_draw_pass = shaders::build(&self.device, || {
+                DebugDrawPass::new(&self.device, self.hdr_pipeline.format())
+            })?;
+        }
+        if uses(&["shadow.wgsl"]) {
+            self.shadow_maps = shaders::build(&self.device, || {
+                ShadowMaps::new(&self.device, *self.shadow_maps.settings())
+            })?;
+            self.lights
+                .rebuild_bind_group(&self.device, &self.shadow_maps);
+        }
+        if uses(&["hdr.wgsl", "bloom.wgsl", "auto_exposure.wgsl"]) {
+            let mut hdr_pipeline = shaders::build(&self.device, || {
+                hdr::HdrPipeline::new(&self.device, &self.config)
+            })?;
+            hdr_pipeline.set_tone_mapping(&self.queue, self.hdr_pipeline.tone_mapping());
+            hdr_pipeline.set_auto_exposure(&self.queue, self.hdr_pipeline.auto_exposure());
+            hdr_pipeline.set_bloom(&self.queue, self.hdr_pipeline.bloom());
+            self.hdr_pipeline = hdr_pipeline;
+            //the history is copied into the new hdr target
+            if let Some(taa) = &mut self.taa {
+                taa.resize(
+                    &self.device,
+                    self.hdr_pipeline.view(),
+                    &self.depth_texture.view,
+                    self.config.width,
+                    self.config.height,
+                );
+            }
+        }
+        if uses(&["taa.wgsl"]) && self.taa.is_some() {
+            self.taa = Some(shaders::build(&self.device, || {
+                TemporalAa::new(
+                    &self.device,
+                    self.hdr_pipeline.view(),
+                    &self.depth_texture.view,
+                    self.config.width,
+                    self.config.height,
+                )
+            })?);
+        }
+        if uses(&["depth_resolve.wgsl"]) && self.multisampling.is_some() {
+            self.multisampling = Some(shaders::build(&self.device, || {
+                Multisampling::new(
+                    &self.device,
+                    sample_count,
+                    self.config.width,
+                    self.config.height,
+                )
+            })?);
+        }
+        if uses(&["cluster.wgsl"]) {
+            self.lights.clusters_mut().reload(&self.device)?;
+        }
+        if uses(&["gpu_culling.wgsl", "hiz.wgsl"]) {
+            self.gpu_culling = shaders::build(&self.device, || {
+                GpuCulling::new(
+                    &self.device,
+                    &self.obj_model,
+                    &self.instance_buffer,
+                    self.instances.len() as u32,
+                    &self.depth_texture.view,
+                    self.config.width,
+                    self.config.height,
+                )
+            })?;
+        }
+        self.post_process.reload(&self.device, changed)?;
+        Ok(())
+    }
+    pub fn debug_overlay(&self) -> bool {
+        self.debug_overlay
+    }
+    pub fn toggle_debug_overlay(&mut self) {
+        self.debug_overlay = !self.debug_overlay;
     }
     pub fn cursor_grab(&self) {
         self.window
@@ -583,6 +1577,59 @@ impl<'a> Renderer<'a> {
             .unwrap();
     }
 }
+//the main, light and sky pipelines, which draw into the hdr target
+fn create_scene_pipelines(
+    device: &wgpu::Device,
+    render_pipeline_layout: &wgpu::PipelineLayout,
+    light_pipeline_layout: &wgpu::PipelineLayout,
+    sky_pipeline_layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+) -> (
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+) {
+    let render_pipeline = create_render_pipeline(
+        device,
+        render_pipeline_layout,
+        hdr::HdrPipeline::FORMAT,
+        Some(Texture::DEPTH_FORMAT),
+        &[model::ModelVertex::desc(), InstanceRaw::desc()],
+        wgpu::ShaderModuleDescriptor {
+            label: Some("Shader"),
+            source: wgpu::ShaderSource::Wgsl(shaders::source("shader.wgsl")),
+        },
+        wgpu::PrimitiveTopology::TriangleList,
+        sample_count,
+        "Main Render Pipeline",
+    );
+    let light_render_pipeline = create_render_pipeline(
+        device,
+        light_pipeline_layout,
+        hdr::HdrPipeline::FORMAT,
+        Some(Texture::DEPTH_FORMAT),
+        &[model::ModelVertex::desc()],
+        wgpu::ShaderModuleDescriptor {
+            label: Some("Light Shader"),
+            source: wgpu::ShaderSource::Wgsl(shaders::source("light.wgsl")),
+        },
+        wgpu::PrimitiveTopology::TriangleList,
+        sample_count,
+        "Light Render Pipeline",
+    );
+    let sky_pipeline = create_render_pipeline(
+        device,
+        sky_pipeline_layout,
+        hdr::HdrPipeline::FORMAT,
+        Some(Texture::DEPTH_FORMAT),
+        &[],
+        shaders::descriptor("sky.wgsl"),
+        wgpu::PrimitiveTopology::TriangleList,
+        sample_count,
+        "Sky Pipeline",
+    );
+    (render_pipeline, light_render_pipeline, sky_pipeline)
+}
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
@@ -591,6 +1638,7 @@ pub fn create_render_pipeline(
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
     topology: wgpu::PrimitiveTopology,
+    sample_count: u32,
     label: &str,
 ) -> wgpu::RenderPipeline {
     let shader = device.create_shader_module(shader);
@@ -628,7 +1676,7 @@ pub fn create_render_pipeline(
             bias: wgpu::DepthBiasState::default(),
         }),
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
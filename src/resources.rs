@@ -4,12 +4,20 @@ use std::{
     path,
 };
 
-use image::codecs::hdr::HdrDecoder;
+use anyhow::Context;
 use wgpu::util::DeviceExt;
 
 use crate::{
-    model::{self, Material, Mesh, ModelVertex},
-    textures,
+    compressed::CompressedImage,
+    culling::{Aabb, BoundingSphere},
+    lod::MAX_LODS,
+    material::MaterialLibrary,
+    model::{
+        self, AlphaMode, BatchPacking, Material, MaterialFactors, MaterialKind, MaterialTextures,
+        Mesh, MeshLod, ModelVertex,
+    },
+    shaders,
+    textures::{self, SamplerOptions, TextureLoader},
 };
 
 pub fn load_string(path: &str) -> Result<String, std::io::Error> {
@@ -30,7 +38,67 @@ pub fn load_texture(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     is_normal_map: bool,
+    loader: &TextureLoader,
+    options: SamplerOptions,
 ) -> Result<textures::Texture, anyhow::Error> {
+    let extension = path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    if let Some("ktx2" | "dds") = extension.as_deref() {
+        let bytes = read_texture_bytes(file_name)?;
+        let image = CompressedImage::parse(&bytes, is_normal_map)
+            .with_context(|| format!("Failed to load {}", file_name))?;
+        if image.layers != 1 {
+            anyhow::bail!("{} is not a 2D texture", file_name);
+        }
+        return loader.from_compressed(device, queue, &image, Some(file_name), options);
+    }
+    let img = load_image(file_name)?;
+    let texture = loader.from_image(device, queue, &img, Some(file_name), is_normal_map, options);
+    Ok(texture)
+}
+//splits a map statement into its file name, which always comes last, and the sampler options
+//-clamp on, plus the -mirror on, -aniso <n> and -filter nearest|linear extensions
+pub fn parse_map_statement(statement: &str) -> Result<(&str, SamplerOptions), anyhow::Error> {
+    let words = statement.split_whitespace().collect::<Vec<_>>();
+    let Some((file_name, options)) = words.split_last() else {
+        anyhow::bail!("Map statement without a file name");
+    };
+    let mut sampler = SamplerOptions::default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match *option {
+            "-clamp" if options.next() == Some(&"on") => {
+                sampler.address_mode = wgpu::AddressMode::ClampToEdge
+            }
+            "-mirror" if options.next() == Some(&"on") => {
+                sampler.address_mode = wgpu::AddressMode::MirrorRepeat
+            }
+            "-aniso" => {
+                let value = options.next().copied().unwrap_or_default();
+                sampler.anisotropy = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid -aniso value {:?}", value))?;
+            }
+            "-filter" => {
+                sampler.filter = match options.next() {
+                    Some(&"nearest") => wgpu::FilterMode::Nearest,
+                    Some(&"linear") => wgpu::FilterMode::Linear,
+                    value => anyhow::bail!("Invalid -filter value {:?}", value),
+                }
+            }
+            //the other options and their values don't change how the texture is sampled
+            _ => {}
+        }
+    }
+    Ok((file_name, sampler))
+}
+pub fn load_image(file_name: &str) -> Result<image::DynamicImage, anyhow::Error> {
+    let bytes = read_texture_bytes(file_name)?;
+    Ok(image::load_from_memory(&bytes)?)
+}
+fn read_texture_bytes(file_name: &str) -> Result<Vec<u8>, anyhow::Error> {
     use std::fs;
     let f = fs::File::open(get_location_of_assets()).unwrap();
     let mut archive = tar::Archive::new(f);
@@ -47,8 +115,7 @@ pub fn load_texture(
             break;
         }
     }
-    let texture = textures::Texture::from_bytes(device, &bytes, file_name, queue, is_normal_map)?;
-    Ok(texture)
+    Ok(bytes)
 }
 pub fn read_game_assets(file_name: &str) -> Result<Vec<u8>, anyhow::Error> {
     use std::fs;
@@ -76,6 +143,7 @@ pub fn load_model(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    material_library: &mut MaterialLibrary,
 ) -> Result<crate::model::Model, anyhow::Error> {
     use std::fs;
 
@@ -97,128 +165,593 @@ pub fn load_model(
     if obj_text == "" {
         panic!("Could not find model {:?}", file_name);
     }
-    let obj_cursor = std::io::Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
-    let (models, obj_materials) = tobj::load_obj_buf(
-        &mut obj_reader,
-        &tobj::LoadOptions {
-            triangulate: true,
-            single_index: true,
-            ..Default::default()
-        },
-        |p| {
-            //set parent of path to textures folder
-            let mut path = path::PathBuf::from("res");
-            path.push(p);
-            let mat_text = read_game_assets(path.to_str().unwrap_or("")).unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
-        },
-    )?;
+    let (models, obj_materials) = load_obj(obj_text)?;
+    //name_LOD1.obj and so on, authored or made by build.rs when the assets were packed, with
+    //their meshes in the same order as the full detail's
+    let stem = file_name.strip_suffix(".obj").unwrap_or(file_name);
+    let mut lod_levels = vec![];
+    for level in 1..MAX_LODS {
+        let bytes = read_game_assets(&format!("res/{stem}_LOD{level}.obj"))?;
+        if bytes.is_empty() {
+            break;
+        }
+        let (lod_models, _) = load_obj(String::from_utf8(bytes)?)?;
+        lod_levels.push(lod_models);
+    }
 
+    let obj_materials = obj_materials?;
     let mut materials: Vec<Material> = Vec::new();
-    for mat in obj_materials? {
-        let diffuse_texture = load_texture(&mat.diffuse_texture.unwrap(), device, queue, false)?;
-        let normal_texture = load_texture(&mat.normal_texture.unwrap(), device, queue, true)?;
-        materials.push(Material::new(
-            device,
-            &mat.name,
-            diffuse_texture,
-            normal_texture,
-            layout,
-        ));
+    for mat in &obj_materials {
+        //a material key in the MTL swaps the built-in shading for a material asset
+        match mat.unknown_param.get("material") {
+            Some(file_name) => {
+                materials.push(material_library.load_material(device, queue, file_name.trim())?)
+            }
+            None => materials.push(load_material(
+                mat,
+                device,
+                queue,
+                layout,
+                material_library.textures(),
+            )?),
+        }
     }
+    //the standard materials that can share a bind group are packed into one, which the meshes
+    //using them pick their entry of with their vertices
+    let sources = materials
+        .iter()
+        .zip(&obj_materials)
+        .enumerate()
+        .map(|(index, (material, mat))| {
+            let MaterialKind::Standard(standard) = &material.kind else {
+                return Ok(None);
+            };
+            let Some(options) = map_options(mat)? else {
+                return Ok(None);
+            };
+            let uvs_in_tile = models
+                .iter()
+                .chain(lod_levels.iter().flatten())
+                .filter(|m| m.mesh.material_id.unwrap_or(0) == index)
+                .flat_map(|m| &m.mesh.texcoords)
+                .all(|uv| (0.0..=1.0).contains(uv));
+            Ok(Some(BatchSource {
+                blended: material.is_transparent(),
+                options,
+                texture_size: standard.textures.largest_size(),
+                uvs_in_tile,
+            }))
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    let (materials, placement) = batch_materials(
+        materials,
+        &plan_batches(&sources),
+        file_name,
+        device,
+        queue,
+        layout,
+        material_library.textures(),
+    )?;
     let meshes = models
         .into_iter()
-        .map(|m| {
-            let mut vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                    tangent: [0.0; 3],
-                    bitangent: [0.0; 3],
-                })
-                .collect::<Vec<ModelVertex>>();
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-                let pos0: cgmath::Vector3<_> = v0.position.into();
-                let pos1: cgmath::Vector3<_> = v1.position.into();
-                let pos2: cgmath::Vector3<_> = v2.position.into();
-                let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-                let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-                let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-                vertices[c[0] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
+        .enumerate()
+        .map(|(index, m)| {
+            let material_id = m.mesh.material_id.unwrap_or(0);
+            let (material, entry) = placement
+                .get(material_id)
+                .copied()
+                .unwrap_or((material_id, 0));
+            let mut vertices = mesh_vertices(&m.mesh);
+            let aabb = Aabb::from_points(vertices.iter().map(|v| v.position));
+            let bounding_sphere =
+                BoundingSphere::from_points(aabb.center(), vertices.iter().map(|v| v.position));
+            //the levels' vertices go after the full detail's in the one vertex buffer
+            let mut lod_indices = vec![];
+            for lod_models in &lod_levels {
+                let Some(lod) = lod_models.get(index) else {
+                    break;
+                };
+                let base = vertices.len() as u32;
+                vertices.extend(mesh_vertices(&lod.mesh));
+                lod_indices.push(
+                    lod.mesh
+                        .indices
+                        .iter()
+                        .map(|index| index + base)
+                        .collect::<Vec<_>>(),
+                );
             }
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let v = &mut vertices[i];
-                v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-                v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+            for vertex in &mut vertices {
+                vertex.tex_coords[2] = entry as f32;
             }
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex buffer", file_name)),
                 contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                //the debug views' line overlays read the vertices and indices as storage
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index buffer", file_name)),
                 contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::STORAGE,
             });
+            let lods = lod_indices
+                .iter()
+                .map(|indices| MeshLod {
+                    index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("{:?} LOD Index buffer", file_name)),
+                        contents: bytemuck::cast_slice(indices),
+                        usage: wgpu::BufferUsages::INDEX,
+                    }),
+                    num_elements: indices.len() as u32,
+                })
+                .collect();
             Mesh {
-                name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+                material,
+                aabb,
+                bounding_sphere,
+                lods,
             }
         })
         .collect::<Vec<Mesh>>();
     Ok(model::Model { meshes, materials })
 }
+//the largest texture packed as an atlas tile, larger ones get a layer of their own
+const ATLAS_MAX_TILE: u32 = 256;
+//what decides whether a standard material can share a batch with another
+#[derive(Debug, Clone, Copy)]
+struct BatchSource {
+    blended: bool,
+    options: SamplerOptions,
+    texture_size: u32,
+    //the uvs of the meshes using it stay in 0 to 1, so it can be a clamped atlas tile
+    uvs_in_tile: bool,
+}
+//materials, by their index in the model, packed into one
+#[derive(Debug, PartialEq)]
+struct MaterialBatch {
+    materials: Vec<usize>,
+    packing: BatchPacking,
+    options: SamplerOptions,
+}
+//groups the sources that are all opaque or all blended and sample their textures the same way,
+//a source without a match or that can't be batched is left on its own
+fn plan_batches(sources: &[Option<BatchSource>]) -> Vec<MaterialBatch> {
+    let mut groups: Vec<(bool, SamplerOptions, Vec<usize>)> = vec![];
+    for (index, source) in sources.iter().enumerate() {
+        let Some(source) = source else {
+            continue;
+        };
+        match groups
+            .iter_mut()
+            .find(|(blended, options, _)| *blended == source.blended && *options == source.options)
+        {
+            Some((_, _, materials)) => materials.push(index),
+            None => groups.push((source.blended, source.options, vec![index])),
+        }
+    }
+    groups
+        .into_iter()
+        .filter(|(_, _, materials)| materials.len() > 1)
+        .map(|(_, options, materials)| {
+            let sources = materials.iter().filter_map(|&index| sources[index]);
+            let largest = sources.clone().map(|s| s.texture_size).max().unwrap_or(1);
+            let packing = if largest <= ATLAS_MAX_TILE && sources.clone().all(|s| s.uvs_in_tile) {
+                BatchPacking::Atlas {
+                    tile_size: largest.next_power_of_two(),
+                }
+            } else {
+                BatchPacking::Array
+            };
+            MaterialBatch {
+                materials,
+                packing,
+                options,
+            }
+        })
+        .collect()
+}
+//the sampler options every map of a material uses, none when they differ
+fn map_options(mat: &tobj::Material) -> Result<Option<SamplerOptions>, anyhow::Error> {
+    let statements = [
+        mat.diffuse_texture.as_deref(),
+        mat.normal_texture.as_deref(),
+    ]
+    .into_iter()
+    .chain(
+        ["map_Pr", "map_Pm", "map_ao", "map_Ke"]
+            .iter()
+            .map(|key| mat.unknown_param.get(*key).map(|v| v.trim())),
+    )
+    .flatten();
+    let mut common = None;
+    for statement in statements {
+        let (_, options) = parse_map_statement(statement)?;
+        match common {
+            Some(common) if common != options => return Ok(None),
+            _ => common = Some(options),
+        }
+    }
+    Ok(Some(common.unwrap_or_default()))
+}
+//the index a material ends up at and the entry the meshes using it pick
+type MaterialPlacement = (usize, u32);
+//replaces the batched materials with their batches, which go first. every old index maps to its
+//new index and the entry the meshes using it pick
+fn batch_materials(
+    materials: Vec<Material>,
+    batches: &[MaterialBatch],
+    name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    loader: &TextureLoader,
+) -> Result<(Vec<Material>, Vec<MaterialPlacement>), anyhow::Error> {
+    let mut placement = vec![None; materials.len()];
+    let mut batched = vec![];
+    for (index, batch) in batches.iter().enumerate() {
+        let sources = batch
+            .materials
+            .iter()
+            .map(|&material| &materials[material])
+            .collect::<Vec<_>>();
+        let material = Material::batch(
+            device,
+            queue,
+            loader,
+            &format!("{} batch {}", name, index),
+            &sources,
+            batch.packing,
+            batch.options,
+            layout,
+        )?;
+        for (entry, &source) in batch.materials.iter().enumerate() {
+            placement[source] = Some((batched.len(), entry as u32));
+        }
+        batched.push(material);
+    }
+    for (index, material) in materials.into_iter().enumerate() {
+        if placement[index].is_none() {
+            placement[index] = Some((batched.len(), 0));
+            batched.push(material);
+        }
+    }
+    Ok((batched, placement.into_iter().flatten().collect()))
+}
+fn load_obj(obj_text: String) -> tobj::LoadResult {
+    let obj_cursor = std::io::Cursor::new(obj_text);
+    let mut obj_reader = BufReader::new(obj_cursor);
+    tobj::load_obj_buf(
+        &mut obj_reader,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        |p| {
+            //set parent of path to textures folder
+            let mut path = path::PathBuf::from("res");
+            path.push(p);
+            let mat_text = read_game_assets(path.to_str().unwrap_or("")).unwrap();
+            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        },
+    )
+}
+//the mesh's vertices with tangents and bitangents averaged over the triangles using them
+fn mesh_vertices(mesh: &tobj::Mesh) -> Vec<ModelVertex> {
+    let mut vertices = (0..mesh.positions.len() / 3)
+        .map(|i| model::ModelVertex {
+            position: [
+                mesh.positions[i * 3],
+                mesh.positions[i * 3 + 1],
+                mesh.positions[i * 3 + 2],
+            ],
+            tex_coords: [mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1], 0.0],
+            normal: [
+                mesh.normals[i * 3],
+                mesh.normals[i * 3 + 1],
+                mesh.normals[i * 3 + 2],
+            ],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        })
+        .collect::<Vec<ModelVertex>>();
+    let indices = &mesh.indices;
+    let mut triangles_included = vec![0; vertices.len()];
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+        let pos0: cgmath::Vector3<_> = v0.position.into();
+        let pos1: cgmath::Vector3<_> = v1.position.into();
+        let pos2: cgmath::Vector3<_> = v2.position.into();
+        let uv0 = cgmath::Vector2::new(v0.tex_coords[0], v0.tex_coords[1]);
+        let uv1 = cgmath::Vector2::new(v1.tex_coords[0], v1.tex_coords[1]);
+        let uv2 = cgmath::Vector2::new(v2.tex_coords[0], v2.tex_coords[1]);
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+        let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+        vertices[c[0] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
+        vertices[c[1] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
+        vertices[c[2] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
+        vertices[c[0] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
+        vertices[c[1] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
+        vertices[c[2] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
+        triangles_included[c[0] as usize] += 1;
+        triangles_included[c[1] as usize] += 1;
+        triangles_included[c[2] as usize] += 1;
+    }
+    for (i, n) in triangles_included.into_iter().enumerate() {
+        let denom = 1.0 / n as f32;
+        let v = &mut vertices[i];
+        v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
+        v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+    }
+    vertices
+}
+//reads the standard MTL values plus the PBR extension (Pr, Pm, Ke, map_Pr, map_Pm, map_Ke),
+//an occlusion map under map_ao and alpha_mode/alpha_cutoff
+fn load_material(
+    mat: &tobj::Material,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    loader: &TextureLoader,
+) -> Result<Material, anyhow::Error> {
+    let param = |key: &str| mat.unknown_param.get(key).map(|v| v.trim());
+    let factors = material_factors(mat)?;
+    let texture_or = |statement: Option<&str>, color: [u8; 4], linear: bool| match statement {
+        Some(statement) => {
+            let (file_name, options) = parse_map_statement(statement)?;
+            load_texture(file_name, device, queue, linear, loader, options)
+        }
+        None => Ok(loader.from_color(
+            device,
+            queue,
+            color,
+            &format!("{} default", mat.name),
+            linear,
+        )),
+    };
+    let textures = MaterialTextures {
+        base_color: texture_or(mat.diffuse_texture.as_deref(), [255; 4], false)?,
+        normal: texture_or(mat.normal_texture.as_deref(), [128, 128, 255, 255], true)?,
+        metallic_roughness: load_metallic_roughness(
+            param("map_Pr"),
+            param("map_Pm"),
+            &mat.name,
+            device,
+            queue,
+            loader,
+        )?,
+        occlusion: texture_or(param("map_ao"), [255; 4], true)?,
+        emissive: texture_or(param("map_Ke"), [255; 4], false)?,
+    };
+    Ok(Material::new(device, &mat.name, textures, factors, layout))
+}
+//the scalar factors, a map without its scalar is used as is with a factor of one
+fn material_factors(mat: &tobj::Material) -> Result<MaterialFactors, anyhow::Error> {
+    let param = |key: &str| mat.unknown_param.get(key).map(|v| v.trim());
+    let mut factors = MaterialFactors::default();
+    if let Some(diffuse) = mat.diffuse {
+        factors.base_color = [
+            diffuse[0],
+            diffuse[1],
+            diffuse[2],
+            mat.dissolve.unwrap_or(1.0),
+        ];
+    }
+    //alpha_mode is opaque, mask or blend, a dissolve below one blends unless a mode is given
+    factors.alpha_mode = match param("alpha_mode") {
+        Some("opaque") => AlphaMode::Opaque,
+        Some("mask") => AlphaMode::Mask(param("alpha_cutoff").unwrap_or("0.5").parse()?),
+        Some("blend") => AlphaMode::Blend,
+        Some(mode) => anyhow::bail!("Invalid alpha_mode {:?} in material {}", mode, mat.name),
+        None if mat.dissolve.is_some_and(|d| d < 1.0) => AlphaMode::Blend,
+        None => AlphaMode::Opaque,
+    };
+    if let Some(roughness) = param("Pr") {
+        factors.roughness = roughness.parse()?;
+    } else if param("map_Pr").is_some() {
+        factors.roughness = 1.0;
+    }
+    if let Some(metallic) = param("Pm") {
+        factors.metallic = metallic.parse()?;
+    } else if param("map_Pm").is_some() {
+        factors.metallic = 1.0;
+    }
+    if let Some(emissive) = param("Ke") {
+        let values = emissive
+            .split_whitespace()
+            .map(|v| v.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        factors.emissive = match values[..] {
+            [r, g, b] => [r, g, b],
+            [v] => [v; 3],
+            _ => anyhow::bail!("Invalid Ke value {:?} in material {}", emissive, mat.name),
+        };
+    } else if param("map_Ke").is_some() {
+        factors.emissive = [1.0; 3];
+    }
+    Ok(factors)
+}
+//MTL keeps roughness and metallic in separate maps, the shader wants them packed together
+fn load_metallic_roughness(
+    roughness: Option<&str>,
+    metallic: Option<&str>,
+    name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    loader: &TextureLoader,
+) -> Result<textures::Texture, anyhow::Error> {
+    let label = format!("{} metallic roughness", name);
+    let roughness = roughness.map(parse_map_statement).transpose()?;
+    let metallic = metallic.map(parse_map_statement).transpose()?;
+    //the packed map samples with the roughness map's options
+    let options = roughness
+        .or(metallic)
+        .map_or(SamplerOptions::default(), |(_, o)| o);
+    let roughness = roughness
+        .map(|(f, _)| load_image(f))
+        .transpose()?
+        .map(|i| i.to_luma8());
+    let metallic = metallic
+        .map(|(f, _)| load_image(f))
+        .transpose()?
+        .map(|i| i.to_luma8());
+    let (width, height) = match (&roughness, &metallic) {
+        (Some(map), _) | (None, Some(map)) => map.dimensions(),
+        (None, None) => return Ok(loader.from_color(device, queue, [255; 4], &label, true)),
+    };
+    let resize = |map: image::GrayImage| {
+        if map.dimensions() == (width, height) {
+            map
+        } else {
+            image::imageops::resize(&map, width, height, image::imageops::FilterType::Triangle)
+        }
+    };
+    let roughness = roughness.map(resize);
+    let metallic = metallic.map(resize);
+    let packed = image::RgbaImage::from_fn(width, height, |x, y| {
+        let r = roughness.as_ref().map_or(255, |m| m.get_pixel(x, y)[0]);
+        let m = metallic.as_ref().map_or(255, |m| m.get_pixel(x, y)[0]);
+        image::Rgba([255, r, m, 255])
+    });
+    Ok(loader.from_image(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgba8(packed),
+        Some(&label),
+        true,
+        options,
+    ))
+}
+//faces in cube layer order, substituted for the * of a six image cube map's file name
+const CUBE_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+//loads a cube map from the assets as an equirectangular .hdr or .exr, a KTX2 or DDS cube, or six images
+//named like sky_*.png where * is one of px, nx, py, ny, pz and nz
+pub fn load_cube_texture(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    hdr_loader: &HdrLoader,
+    equirect_size: u32,
+) -> anyhow::Result<textures::CubeTexture> {
+    let label = Some(file_name);
+    if file_name.contains('*') {
+        let faces = CUBE_FACES.map(|face| read_game_assets(&file_name.replace('*', face)));
+        let image = cube_from_faces(faces)
+            .with_context(|| format!("Failed to load cube faces {}", file_name))?;
+        return textures::CubeTexture::from_compressed(device, queue, &image, label);
+    }
+    let bytes = read_game_assets(file_name)?;
+    if bytes.is_empty() {
+        anyhow::bail!("Could not find {}", file_name);
+    }
+    match path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("ktx2" | "dds") => {
+            let image = CompressedImage::parse(&bytes, true)
+                .with_context(|| format!("Failed to load {}", file_name))?;
+            textures::CubeTexture::from_compressed(device, queue, &image, label)
+        }
+        _ => hdr_loader.from_ecuirectangular_bytes(device, queue, &bytes, equirect_size, label),
+    }
+}
+//packs six square faces into one rgba32 float cube, radiance .hdr faces keep their range
+//while 8 bit images are taken as srgb and linearized
+fn cube_from_faces(faces: [Result<Vec<u8>, anyhow::Error>; 6]) -> anyhow::Result<CompressedImage> {
+    let mut size = None;
+    let mut data = Vec::new();
+    for (face, bytes) in CUBE_FACES.iter().zip(faces) {
+        let bytes = bytes?;
+        if bytes.is_empty() {
+            anyhow::bail!("Missing the {} face", face);
+        }
+        let img = image::load_from_memory(&bytes)?;
+        let face_size = (img.width(), img.height());
+        if face_size.0 != face_size.1 || size.is_some_and(|size| size != face_size) {
+            anyhow::bail!("Cube faces must be square and the same size");
+        }
+        size = Some(face_size);
+        let is_hdr = matches!(
+            img.color(),
+            image::ColorType::Rgb32F | image::ColorType::Rgba32F
+        );
+        let mut pixels = img.to_rgba32f();
+        if !is_hdr {
+            for pixel in pixels.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = srgb_to_linear(*channel);
+                }
+            }
+        }
+        data.extend_from_slice(bytemuck::cast_slice(pixels.as_raw()));
+    }
+    let (width, height) = size.unwrap_or((1, 1));
+    Ok(CompressedImage {
+        format: wgpu::TextureFormat::Rgba32Float,
+        width,
+        height,
+        layers: 6,
+        is_cube: true,
+        levels: vec![data],
+    })
+}
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
 pub struct HdrLoader {
     texture_format: wgpu::TextureFormat,
     equirect_layout: wgpu::BindGroupLayout,
     equirect_to_cubemap: wgpu::ComputePipeline,
+    mips_layout: wgpu::BindGroupLayout,
+    downsample: wgpu::ComputePipeline,
 }
 impl HdrLoader {
     pub fn new(device: &wgpu::Device) -> Self {
-        let module =
-            device.create_shader_module(wgpu::include_wgsl!("../shaders/equirectangular.wgsl"));
-        let texture_format = wgpu::TextureFormat::Rgba32Float;
+        Self::with_format(device, wgpu::TextureFormat::Rgba32Float)
+    }
+    //cube maps can be stored as Rgba16Float to halve their memory
+    pub fn with_format(device: &wgpu::Device, texture_format: wgpu::TextureFormat) -> Self {
+        let storage_format = match texture_format {
+            wgpu::TextureFormat::Rgba32Float => "rgba32float",
+            wgpu::TextureFormat::Rgba16Float => "rgba16float",
+            format => panic!("HdrLoader can't write {:?} cube maps", format),
+        };
+        //the shaders declare rgba32float storage, swapped for the chosen format
+        let shader = |label, source: &str| {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(
+                    source.replace("rgba32float", storage_format).into(),
+                ),
+            })
+        };
+        let module = shader(
+            "Equirectangular Shader",
+            &shaders::source("equirectangular.wgsl"),
+        );
+        let mips_module = shader("Cube Mips Shader", &shaders::source("cube_mips.wgsl"));
         let equirect_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Equirectangular Layout"),
             entries: &[
@@ -256,12 +789,53 @@ impl HdrLoader {
                 module: &module,
                 entry_point: "compute_equirect_to_cubemap",
             });
+        //each mip reads the level above as a 2d array
+        let mips_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cube Mips Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: texture_format,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let downsample = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cube Mips Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Cube Mips Pipeline Layout"),
+                    bind_group_layouts: &[&mips_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            module: &mips_module,
+            entry_point: "compute_downsample",
+        });
         Self {
             texture_format,
             equirect_layout,
             equirect_to_cubemap,
+            mips_layout,
+            downsample,
         }
     }
+    //takes a Radiance .hdr or OpenEXR equirectangular image and builds a cube with a full mip chain
     pub fn from_ecuirectangular_bytes(
         &self,
         device: &wgpu::Device,
@@ -270,24 +844,13 @@ impl HdrLoader {
         dst_size: u32,
         label: Option<&str>,
     ) -> anyhow::Result<textures::CubeTexture> {
-        let hdr_decoder = HdrDecoder::new(Cursor::new(data))?;
-        let metadata = hdr_decoder.metadata();
-        let mut pixels =
-            vec![[0.0, 0.0, 0.0, 0.0]; metadata.width as usize * metadata.height as usize];
-        hdr_decoder.read_image_transform(
-            |pix| {
-                let rgb = pix.to_hdr();
-                [rgb[0] as f32, rgb[1] as f32, rgb[2] as f32, 1.0]
-            },
-            &mut pixels[..],
-        )?;
-
+        let pixels = image::load_from_memory(data)?.to_rgba32f();
         let src = textures::Texture::create_2d_texture(
             device,
             label,
-            metadata.width,
-            metadata.height,
-            self.texture_format,
+            pixels.width(),
+            pixels.height(),
+            wgpu::TextureFormat::Rgba32Float,
             wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             wgpu::FilterMode::Linear,
         );
@@ -298,7 +861,7 @@ impl HdrLoader {
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            &bytemuck::cast_slice(&pixels),
+            bytemuck::cast_slice(pixels.as_raw()),
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(src.size.width * std::mem::size_of::<[f32; 4]>() as u32),
@@ -306,22 +869,35 @@ impl HdrLoader {
             },
             src.size,
         );
+        let mip_level_count = wgpu::Extent3d {
+            width: dst_size,
+            height: dst_size,
+            depth_or_array_layers: 1,
+        }
+        .max_mips(wgpu::TextureDimension::D2);
         let dst = textures::CubeTexture::create_2d(
             device,
             dst_size,
             dst_size,
             self.texture_format,
-            1,
-            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            mip_level_count,
+            wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
             wgpu::FilterMode::Nearest,
             label,
         );
-        let dst_view = dst.texture().create_view(&wgpu::TextureViewDescriptor {
-            label,
-            dimension: Some(wgpu::TextureViewDimension::D2Array),
-            array_layer_count: Some(6),
-            ..Default::default()
-        });
+        let mip_view = |mip| {
+            dst.texture().create_view(&wgpu::TextureViewDescriptor {
+                label,
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                array_layer_count: Some(6),
+                ..Default::default()
+            })
+        };
+        let dst_view = mip_view(0);
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label,
             layout: &self.equirect_layout,
@@ -336,17 +912,125 @@ impl HdrLoader {
                 },
             ],
         });
+        let mip_bind_groups = (1..mip_level_count)
+            .map(|mip| {
+                let src_view = mip_view(mip - 1);
+                let dst_view = mip_view(mip);
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Cube Mips Bind Group"),
+                    layout: &self.mips_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&src_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&dst_view),
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
         let mut encoder = device.create_command_encoder(&Default::default());
         let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label,
             ..Default::default()
         });
-        let workgroups = (dst_size + 15) / 16;
+        let workgroups = dst_size.div_ceil(16);
         pass.set_pipeline(&self.equirect_to_cubemap);
         pass.set_bind_group(0, &bind_group, &[]);
         pass.dispatch_workgroups(workgroups, workgroups, 6);
+        pass.set_pipeline(&self.downsample);
+        for (mip, bind_group) in (1..mip_level_count).zip(&mip_bind_groups) {
+            let workgroups = (dst_size >> mip).max(1).div_ceil(8);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups, workgroups, 6);
+        }
         drop(pass);
         queue.submit([encoder.finish()]);
         Ok(dst)
     }
 }
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+
+    fn parse_material(mtl: &str) -> tobj::Material {
+        let (materials, _) = tobj::load_mtl_buf(&mut BufReader::new(mtl.as_bytes())).unwrap();
+        materials.into_iter().next().unwrap()
+    }
+    #[test]
+    fn metallic_map_without_factor_is_used_as_is() {
+        let factors =
+            material_factors(&parse_material("newmtl metal\nmap_Pm metal.png\n")).unwrap();
+        assert_eq!(factors.metallic, 1.0);
+        assert_eq!(factors.roughness, MaterialFactors::default().roughness);
+    }
+    #[test]
+    fn scalars_scale_their_maps() {
+        let factors = material_factors(&parse_material(
+            "newmtl metal\nPm 0.25\nmap_Pm metal.png\nmap_Pr rough.png\n",
+        ))
+        .unwrap();
+        assert_eq!(factors.metallic, 0.25);
+        assert_eq!(factors.roughness, 1.0);
+    }
+    fn source(texture_size: u32) -> Option<BatchSource> {
+        Some(BatchSource {
+            blended: false,
+            options: SamplerOptions::default(),
+            texture_size,
+            uvs_in_tile: true,
+        })
+    }
+    #[test]
+    fn small_materials_share_an_atlas() {
+        let batches = plan_batches(&[source(128), source(200)]);
+        assert_eq!(
+            batches,
+            [MaterialBatch {
+                materials: vec![0, 1],
+                packing: BatchPacking::Atlas { tile_size: 256 },
+                options: SamplerOptions::default(),
+            }]
+        );
+    }
+    #[test]
+    fn repeating_or_large_materials_share_an_array() {
+        let repeating = source(128).map(|s| BatchSource {
+            uvs_in_tile: false,
+            ..s
+        });
+        for sources in [[source(128), repeating], [source(128), source(1024)]] {
+            let batches = plan_batches(&sources);
+            assert_eq!(batches.len(), 1);
+            assert_eq!(batches[0].packing, BatchPacking::Array);
+        }
+    }
+    #[test]
+    fn unmatched_materials_stay_apart() {
+        let blended = source(128).map(|s| BatchSource { blended: true, ..s });
+        let clamped = source(128).map(|s| BatchSource {
+            options: SamplerOptions {
+                address_mode: wgpu::AddressMode::ClampToEdge,
+                ..s.options
+            },
+            ..s
+        });
+        assert!(plan_batches(&[source(128), blended, clamped, None]).is_empty());
+    }
+    #[test]
+    fn maps_with_other_options_are_not_batched() {
+        let same = parse_material("newmtl a\nmap_Kd -clamp on a.png\nmap_Pr -clamp on r.png\n");
+        let other = parse_material("newmtl b\nmap_Kd -clamp on b.png\nmap_Pr r.png\n");
+        let clamped = SamplerOptions {
+            address_mode: wgpu::AddressMode::ClampToEdge,
+            ..Default::default()
+        };
+        assert_eq!(map_options(&same).unwrap(), Some(clamped));
+        assert_eq!(map_options(&other).unwrap(), None);
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use mlua::{FromLuaMulti, Function, Lua};
+
+use crate::{
+    antialiasing::AntiAliasing,
+    bloom::BloomSettings,
+    debug_draw::DebugShape,
+    debug_view::DebugView,
+    exposure::AutoExposureSettings,
+    gpu_culling::CullingMode,
+    hdr::{ToneMapping, ToneMappingSettings},
+    lod::LodSettings,
+    renderer::Renderer,
+    resources,
+    shadow::ShadowSettings,
+    ssao::SsaoSettings,
+    textures::TextureRegion,
+    transparent::TransparencyMode,
+};
+//changes a script asked of the renderer, applied between frames
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptCommand {
+    ToneMapping(ToneMapping),
+    Exposure(f32),
+    Gamma(f32),
+    WhitePoint(f32),
+    AutoExposure(bool),
+    //rates for adapting to a brighter and to a darker scene
+    AdaptationSpeed(f32, f32),
+    BloomIntensity(f32),
+    BloomThreshold(f32),
+    PostEffectEnabled(String, bool),
+    PostEffectParams(String, Vec<f32>),
+    RemovePostEffect(String),
+    ColorGradingLut(String),
+    AntiAliasing(AntiAliasing),
+    ResetTaaHistory,
+    AmbientOcclusion(bool),
+    AmbientOcclusionRadius(f32),
+    AmbientOcclusionIntensity(f32),
+    Culling(CullingMode),
+    Transparency(TransparencyMode),
+    Lod(bool),
+    LodScreenSize(f32),
+    LodCrossFade(f32),
+    DebugDraw(DebugShape, [f32; 3]),
+    DebugOnTop(bool),
+    DebugView(DebugView),
+    ShadowMapSize(u32),
+    ShadowCascades(u32),
+    ShadowDistance(f32),
+    ShadowPcfRadius(u32),
+    PointShadowMapSize(u32),
+    PointShadowCount(u32),
+    EnvironmentIntensity(f32),
+    InstanceTexture(usize, TextureRegion),
+}
+impl ScriptCommand {
+    pub fn apply(self, renderer: &mut Renderer) -> anyhow::Result<()> {
+        let tone_mapping = renderer.tone_mapping();
+        let auto_exposure = renderer.auto_exposure();
+        let bloom = renderer.bloom();
+        let ssao = renderer.ssao();
+        let lod = renderer.lod();
+        let shadows = renderer.shadow_settings();
+        match self {
+            ScriptCommand::ToneMapping(operator) => {
+                renderer.set_tone_mapping(ToneMappingSettings {
+                    operator,
+                    ..tone_mapping
+                })
+            }
+            ScriptCommand::Exposure(exposure) => renderer.set_tone_mapping(ToneMappingSettings {
+                exposure,
+                ..tone_mapping
+            }),
+            ScriptCommand::Gamma(gamma) => renderer.set_tone_mapping(ToneMappingSettings {
+                gamma,
+                ..tone_mapping
+            }),
+            ScriptCommand::WhitePoint(white_point) => {
+                renderer.set_tone_mapping(ToneMappingSettings {
+                    white_point,
+                    ..tone_mapping
+                })
+            }
+            ScriptCommand::AutoExposure(enabled) => {
+                renderer.set_auto_exposure(AutoExposureSettings {
+                    enabled,
+                    ..auto_exposure
+                })
+            }
+            ScriptCommand::AdaptationSpeed(speed_up, speed_down) => {
+                renderer.set_auto_exposure(AutoExposureSettings {
+                    speed_up,
+                    speed_down,
+                    ..auto_exposure
+                })
+            }
+            ScriptCommand::BloomIntensity(intensity) => {
+                renderer.set_bloom(BloomSettings { intensity, ..bloom })
+            }
+            ScriptCommand::BloomThreshold(threshold) => {
+                renderer.set_bloom(BloomSettings { threshold, ..bloom })
+            }
+            ScriptCommand::PostEffectEnabled(name, enabled) => {
+                return renderer.set_post_effect_enabled(&name, enabled)
+            }
+            ScriptCommand::PostEffectParams(name, params) => {
+                return renderer.set_post_effect_params(&name, &params)
+            }
+            ScriptCommand::RemovePostEffect(name) => return renderer.remove_post_effect(&name),
+            ScriptCommand::ColorGradingLut(file_name) => {
+                return renderer.set_color_grading_lut(&file_name)
+            }
+            ScriptCommand::AntiAliasing(mode) => return renderer.set_anti_aliasing(mode),
+            ScriptCommand::ResetTaaHistory => renderer.reset_taa_history(),
+            ScriptCommand::AmbientOcclusion(enabled) => {
+                renderer.set_ssao(SsaoSettings { enabled, ..ssao })
+            }
+            ScriptCommand::AmbientOcclusionRadius(radius) => {
+                renderer.set_ssao(SsaoSettings { radius, ..ssao })
+            }
+            ScriptCommand::AmbientOcclusionIntensity(intensity) => {
+                renderer.set_ssao(SsaoSettings { intensity, ..ssao })
+            }
+            ScriptCommand::Culling(mode) => renderer.set_culling_mode(mode),
+            ScriptCommand::Transparency(mode) => renderer.set_transparency_mode(mode),
+            ScriptCommand::Lod(enabled) => renderer.set_lod(LodSettings { enabled, ..lod }),
+            ScriptCommand::LodScreenSize(screen_size) => {
+                renderer.set_lod(LodSettings { screen_size, ..lod })
+            }
+            ScriptCommand::LodCrossFade(cross_fade) => {
+                renderer.set_lod(LodSettings { cross_fade, ..lod })
+            }
+            ScriptCommand::DebugDraw(shape, color) => shape.draw(renderer.debug_draw(), color),
+            ScriptCommand::DebugOnTop(on_top) => renderer.debug_draw().set_on_top(on_top),
+            ScriptCommand::DebugView(view) => renderer.set_debug_view(view),
+            ScriptCommand::EnvironmentIntensity(intensity) => {
+                renderer.set_environment_intensity(intensity)
+            }
+            ScriptCommand::InstanceTexture(index, texture) => {
+                return renderer.set_instance_texture(index, texture)
+            }
+            ScriptCommand::ShadowMapSize(map_size) => {
+                return renderer.set_shadow_settings(ShadowSettings {
+                    map_size,
+                    ..shadows
+                })
+            }
+            ScriptCommand::ShadowCascades(cascade_count) => {
+                return renderer.set_shadow_settings(ShadowSettings {
+                    cascade_count,
+                    ..shadows
+                })
+            }
+            ScriptCommand::ShadowDistance(shadow_distance) => {
+                return renderer.set_shadow_settings(ShadowSettings {
+                    shadow_distance,
+                    ..shadows
+                })
+            }
+            ScriptCommand::ShadowPcfRadius(pcf_radius) => {
+                return renderer.set_shadow_settings(ShadowSettings {
+                    pcf_radius,
+                    ..shadows
+                })
+            }
+            ScriptCommand::PointShadowMapSize(point_map_size) => {
+                return renderer.set_shadow_settings(ShadowSettings {
+                    point_map_size,
+                    ..shadows
+                })
+            }
+            ScriptCommand::PointShadowCount(max_shadowed_point_lights) => {
+                return renderer.set_shadow_settings(ShadowSettings {
+                    max_shadowed_point_lights,
+                    ..shadows
+                })
+            }
+        }
+        Ok(())
+    }
+}
+pub struct Scripting {
+    lua: Lua,
+    commands: Arc<Mutex<Vec<ScriptCommand>>>,
+}
+impl Scripting {
+    pub const MAIN: &'static str = "scripts/main.lua";
+    pub fn new() -> mlua::Result<Self> {
+        let lua = Lua::new();
+        let commands = Arc::new(Mutex::new(vec![]));
+        let renderer = lua.create_table()?;
+        renderer.set(
+            "set_tone_mapping",
+            command_function(&lua, &commands, |name: String| {
+                ToneMapping::from_name(&name)
+                    .map(ScriptCommand::ToneMapping)
+                    .ok_or_else(|| {
+                        mlua::Error::RuntimeError(format!("unknown tone mapping {name}"))
+                    })
+            })?,
+        )?;
+        let setters = [
+            (
+                "set_exposure",
+                ScriptCommand::Exposure as fn(f32) -> ScriptCommand,
+            ),
+            ("set_gamma", ScriptCommand::Gamma),
+            ("set_white_point", ScriptCommand::WhitePoint),
+            ("set_bloom_intensity", ScriptCommand::BloomIntensity),
+            ("set_bloom_threshold", ScriptCommand::BloomThreshold),
+            (
+                "set_ambient_occlusion_radius",
+                ScriptCommand::AmbientOcclusionRadius,
+            ),
+            (
+                "set_ambient_occlusion_intensity",
+                ScriptCommand::AmbientOcclusionIntensity,
+            ),
+            ("set_lod_screen_size", ScriptCommand::LodScreenSize),
+            ("set_lod_cross_fade", ScriptCommand::LodCrossFade),
+            ("set_shadow_distance", ScriptCommand::ShadowDistance),
+            (
+                "set_environment_intensity",
+                ScriptCommand::EnvironmentIntensity,
+            ),
+        ];
+        for (name, command) in setters {
+            renderer.set(
+                name,
+                command_function(&lua, &commands, move |value: f32| Ok(command(value)))?,
+            )?;
+        }
+        let count_setters = [
+            (
+                "set_shadow_map_size",
+                ScriptCommand::ShadowMapSize as fn(u32) -> ScriptCommand,
+            ),
+            ("set_shadow_cascades", ScriptCommand::ShadowCascades),
+            ("set_shadow_pcf_radius", ScriptCommand::ShadowPcfRadius),
+            (
+                "set_point_shadow_map_size",
+                ScriptCommand::PointShadowMapSize,
+            ),
+            ("set_point_shadow_count", ScriptCommand::PointShadowCount),
+        ];
+        for (name, command) in count_setters {
+            renderer.set(
+                name,
+                command_function(&lua, &commands, move |value: u32| Ok(command(value)))?,
+            )?;
+        }
+        renderer.set(
+            "set_auto_exposure",
+            command_function(&lua, &commands, |enabled: bool| {
+                Ok(ScriptCommand::AutoExposure(enabled))
+            })?,
+        )?;
+        renderer.set(
+            "set_ambient_occlusion",
+            command_function(&lua, &commands, |enabled: bool| {
+                Ok(ScriptCommand::AmbientOcclusion(enabled))
+            })?,
+        )?;
+        renderer.set(
+            "set_lod",
+            command_function(&lua, &commands, |enabled: bool| {
+                Ok(ScriptCommand::Lod(enabled))
+            })?,
+        )?;
+        renderer.set(
+            "set_adaptation_speed",
+            command_function(&lua, &commands, |(speed_up, speed_down): (f32, f32)| {
+                Ok(ScriptCommand::AdaptationSpeed(speed_up, speed_down))
+            })?,
+        )?;
+        renderer.set(
+            "set_post_effect",
+            command_function(&lua, &commands, |(name, enabled): (String, bool)| {
+                Ok(ScriptCommand::PostEffectEnabled(name, enabled))
+            })?,
+        )?;
+        renderer.set(
+            "set_post_effect_params",
+            command_function(&lua, &commands, |(name, params): (String, Vec<f32>)| {
+                Ok(ScriptCommand::PostEffectParams(name, params))
+            })?,
+        )?;
+        renderer.set(
+            "remove_post_effect",
+            command_function(&lua, &commands, |name: String| {
+                Ok(ScriptCommand::RemovePostEffect(name))
+            })?,
+        )?;
+        renderer.set(
+            "set_color_grading_lut",
+            command_function(&lua, &commands, |file_name: String| {
+                Ok(ScriptCommand::ColorGradingLut(file_name))
+            })?,
+        )?;
+        renderer.set(
+            "set_anti_aliasing",
+            command_function(&lua, &commands, |name: String| {
+                AntiAliasing::from_name(&name)
+                    .map(ScriptCommand::AntiAliasing)
+                    .ok_or_else(|| {
+                        mlua::Error::RuntimeError(format!("unknown anti-aliasing {name}"))
+                    })
+            })?,
+        )?;
+        renderer.set(
+            "reset_taa_history",
+            command_function(&lua, &commands, |()| Ok(ScriptCommand::ResetTaaHistory))?,
+        )?;
+        renderer.set(
+            "set_culling",
+            command_function(&lua, &commands, |name: String| {
+                CullingMode::from_name(&name)
+                    .map(ScriptCommand::Culling)
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("unknown culling {name}")))
+            })?,
+        )?;
+        renderer.set(
+            "set_transparency_mode",
+            command_function(&lua, &commands, |name: String| {
+                TransparencyMode::from_name(&name)
+                    .map(ScriptCommand::Transparency)
+                    .ok_or_else(|| {
+                        mlua::Error::RuntimeError(format!("unknown transparency mode {name}"))
+                    })
+            })?,
+        )?;
+        renderer.set(
+            "set_debug_view",
+            command_function(&lua, &commands, |name: String| {
+                DebugView::from_name(&name)
+                    .map(ScriptCommand::DebugView)
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("unknown debug view {name}")))
+            })?,
+        )?;
+        //the uv offset and scale are optional, leaving the uvs as they are
+        renderer.set(
+            "set_instance_texture",
+            command_function(
+                &lua,
+                &commands,
+                |(index, layer, offset, scale): (
+                    usize,
+                    u32,
+                    Option<[f32; 2]>,
+                    Option<[f32; 2]>,
+                )| {
+                    Ok(ScriptCommand::InstanceTexture(
+                        index,
+                        TextureRegion {
+                            layer,
+                            uv_offset: offset.unwrap_or([0.0; 2]),
+                            uv_scale: scale.unwrap_or([1.0; 2]),
+                        },
+                    ))
+                },
+            )?,
+        )?;
+        //the debug shapes take an optional color last, white by default
+        let debug_command = |shape, color: Option<[f32; 3]>| {
+            Ok(ScriptCommand::DebugDraw(shape, color.unwrap_or([1.0; 3])))
+        };
+        renderer.set(
+            "debug_line",
+            command_function(&lua, &commands, move |(from, to, color)| {
+                debug_command(DebugShape::Line(from, to), color)
+            })?,
+        )?;
+        renderer.set(
+            "debug_box",
+            command_function(&lua, &commands, move |(min, max, color)| {
+                debug_command(DebugShape::Box(min, max), color)
+            })?,
+        )?;
+        renderer.set(
+            "debug_sphere",
+            command_function(&lua, &commands, move |(center, radius, color)| {
+                debug_command(DebugShape::Sphere(center, radius), color)
+            })?,
+        )?;
+        renderer.set(
+            "debug_arrow",
+            command_function(&lua, &commands, move |(from, to, color)| {
+                debug_command(DebugShape::Arrow(from, to), color)
+            })?,
+        )?;
+        renderer.set(
+            "debug_frustum",
+            command_function(&lua, &commands, move |(view_proj, color)| {
+                debug_command(DebugShape::Frustum(view_proj), color)
+            })?,
+        )?;
+        renderer.set(
+            "debug_grid",
+            command_function(&lua, &commands, move |(center, size, divisions, color)| {
+                debug_command(DebugShape::Grid(center, size, divisions), color)
+            })?,
+        )?;
+        renderer.set(
+            "debug_text",
+            command_function(&lua, &commands, move |(position, text, color)| {
+                debug_command(DebugShape::Text(position, text), color)
+            })?,
+        )?;
+        renderer.set(
+            "debug_on_top",
+            command_function(&lua, &commands, |on_top: bool| {
+                Ok(ScriptCommand::DebugOnTop(on_top))
+            })?,
+        )?;
+        lua.globals().set("renderer", renderer)?;
+        Ok(Self { lua, commands })
+    }
+    //runs the main script from the assets, if the game has one
+    pub fn load_main(&self) -> mlua::Result<()> {
+        let source = resources::read_game_assets(Self::MAIN).unwrap_or_default();
+        if source.is_empty() {
+            return Ok(());
+        }
+        self.lua.load(source).set_name(Self::MAIN).exec()
+    }
+    //calls the script's global update(dt) and applies what it asked for, a command that fails
+    //doesn't keep the ones after it from applying and all the errors are returned together
+    pub fn update(&self, renderer: &mut Renderer, delta_time: Duration) -> mlua::Result<()> {
+        if let Ok(update) = self.lua.globals().get::<_, Function>("update") {
+            update.call::<_, ()>(delta_time.as_secs_f32())?;
+        }
+        let errors = self
+            .commands
+            .lock()
+            .unwrap()
+            .drain(..)
+            .filter_map(|command| command.apply(renderer).err())
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return Err(mlua::Error::RuntimeError(errors.join("\n")));
+        }
+        Ok(())
+    }
+}
+//a lua function that queues the command built from its arguments
+fn command_function<'lua, A: FromLuaMulti<'lua>>(
+    lua: &'lua Lua,
+    commands: &Arc<Mutex<Vec<ScriptCommand>>>,
+    command: impl Fn(A) -> mlua::Result<ScriptCommand> + Send + 'static,
+) -> mlua::Result<Function<'lua>> {
+    let commands = commands.clone();
+    lua.create_function(move |_, args: A| {
+        commands.lock().unwrap().push(command(args)?);
+        Ok(())
+    })
+}
@@ -0,0 +1,215 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    future::Future,
+    path::PathBuf,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::material::parse_wgsl;
+
+macro_rules! embedded {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_str!(concat!("../shaders/", $name)))),*]
+    };
+}
+//every shader in the shaders folder, release builds only ever use these
+const EMBEDDED: &[(&str, &str)] = embedded!(
+    "auto_exposure.wgsl",
+    "blit.wgsl",
+    "bloom.wgsl",
+    "brdf_lut.wgsl",
+    "cluster.wgsl",
+    "cube_mips.wgsl",
+    "debug.wgsl",
+    "debug_view.wgsl",
+    "deferred.wgsl",
+    "depth_resolve.wgsl",
+    "equirectangular.wgsl",
+    "gpu_culling.wgsl",
+    "hdr.wgsl",
+    "hiz.wgsl",
+    "ibl.wgsl",
+    "light.wgsl",
+    "oit_composite.wgsl",
+    "post.wgsl",
+    "post_chromatic_aberration.wgsl",
+    "post_color_grading.wgsl",
+    "post_film_grain.wgsl",
+    "post_fxaa.wgsl",
+    "post_vignette.wgsl",
+    "shader.wgsl",
+    "shadow.wgsl",
+    "sky.wgsl",
+    "ssao.wgsl",
+    "taa.wgsl",
+);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+//the environment and textures are baked once while loading
+const LOADING_ONLY: &[&str] = &[
+    "blit.wgsl",
+    "brdf_lut.wgsl",
+    "cube_mips.wgsl",
+    "equirectangular.wgsl",
+    "ibl.wgsl",
+];
+
+fn directory() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("shaders")
+}
+//debug builds read the file so edits show up without a rebuild, falling back to the copy built in
+pub fn source(name: &str) -> Cow<'static, str> {
+    let embedded = EMBEDDED
+        .iter()
+        .find(|(embedded, _)| *embedded == name)
+        .map(|(_, source)| *source)
+        .unwrap_or_else(|| panic!("no shader named {}", name));
+    if cfg!(debug_assertions) {
+        if let Ok(source) = fs::read_to_string(directory().join(name)) {
+            return Cow::Owned(source);
+        }
+    }
+    Cow::Borrowed(embedded)
+}
+//the files that are compiled with another file's source in front of them
+fn prelude(name: &str) -> Option<&'static str> {
+    match name {
+        "deferred.wgsl" => Some("shader.wgsl"),
+        name if name.starts_with("post_") => Some("post.wgsl"),
+        _ => None,
+    }
+}
+//the source with its prelude, if it has one
+pub fn composed(name: &str) -> Cow<'static, str> {
+    match prelude(name) {
+        Some(prelude) => Cow::Owned(format!("{}\n{}", source(prelude), source(name))),
+        None => source(name),
+    }
+}
+pub fn descriptor(name: &'static str) -> wgpu::ShaderModuleDescriptor<'static> {
+    wgpu::ShaderModuleDescriptor {
+        label: Some(name),
+        source: wgpu::ShaderSource::Wgsl(composed(name)),
+    }
+}
+//checks every shader that includes one of the changed files with naga
+pub fn validate(changed: &[String]) -> anyhow::Result<()> {
+    for (name, _) in EMBEDDED {
+        if changed
+            .iter()
+            .any(|changed| changed == name || prelude(name) == Some(changed.as_str()))
+        {
+            parse_wgsl(&format!("shaders/{}", name), &composed(name))?;
+        }
+    }
+    Ok(())
+}
+//an error naming the changed shaders that only take effect on the next start
+pub fn check_loading_only(changed: &[String]) -> anyhow::Result<()> {
+    let names = changed
+        .iter()
+        .filter(|name| LOADING_ONLY.contains(&name.as_str()))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return Ok(());
+    }
+    anyhow::bail!(
+        "{} only run while loading, restart to see the change",
+        names.join(", ")
+    )
+}
+//runs build with wgpu's validation errors caught instead of panicking, so an object that fails to
+//build can be thrown away
+pub fn build<T>(device: &wgpu::Device, build: impl FnOnce() -> T) -> anyhow::Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = build();
+    //native error scopes are resolved by the time they are popped
+    let error = std::pin::pin!(device.pop_error_scope());
+    match error.poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(Some(error)) => Err(anyhow::anyhow!("{}", error)),
+        _ => Ok(value),
+    }
+}
+//polls the shaders folder for saved files in debug builds
+pub struct ShaderWatcher {
+    modified: HashMap<String, SystemTime>,
+    last_poll: Instant,
+    //retried along with the next change, as they might depend on it
+    failed: Vec<String>,
+    error: Option<String>,
+}
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        Self {
+            modified: if cfg!(debug_assertions) {
+                Self::scan()
+            } else {
+                HashMap::new()
+            },
+            last_poll: Instant::now(),
+            failed: Vec::new(),
+            error: None,
+        }
+    }
+    fn scan() -> HashMap<String, SystemTime> {
+        let Ok(entries) = fs::read_dir(directory()) else {
+            return HashMap::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                name.ends_with(".wgsl").then_some((name, modified))
+            })
+            .collect()
+    }
+    //the shaders saved since the last poll along with the ones that failed to reload, always
+    //empty in release builds
+    pub fn poll(&mut self) -> Vec<String> {
+        if !cfg!(debug_assertions) || self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+        let modified = Self::scan();
+        let mut changed = modified
+            .iter()
+            .filter(|(name, time)| self.modified.get(*name) != Some(time))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        self.modified = modified;
+        if !changed.is_empty() {
+            for name in self.failed.drain(..) {
+                if !changed.contains(&name) {
+                    changed.push(name);
+                }
+            }
+        }
+        changed
+    }
+    //the error itself goes back to the engine to print
+    pub fn report(&mut self, changed: Vec<String>, result: &anyhow::Result<()>) {
+        match result {
+            Ok(()) => self.error = None,
+            Err(e) => {
+                //the first lines say what went wrong and where, the rest is in the terminal
+                let summary = e
+                    .to_string()
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .take(2)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.error = Some(format!("Shader reload failed\n{}", summary));
+                self.failed = changed;
+            }
+        }
+    }
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
@@ -0,0 +1,624 @@
+use std::ops::Range;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3};
+
+use crate::{
+    camera::{Camera, Projection, OPENGL_TO_WGPU_MATRIX},
+    light::Lights,
+    model::{self, DrawShadow, Vertex},
+    renderer::InstanceRaw,
+    shaders,
+    textures::{CubeTexture, Texture},
+};
+
+pub const MAX_CASCADES: usize = 4;
+pub const MAX_SHADOWED_SPOT_LIGHTS: usize = 4;
+//the shadow array holds every cascade first and then one layer per shadowed spot light
+const SHADOW_LAYERS: usize = MAX_CASCADES + MAX_SHADOWED_SPOT_LIGHTS;
+//uniform buffer offsets have to be aligned to 256 bytes
+const VIEW_UNIFORM_STRIDE: wgpu::BufferAddress = 256;
+const POINT_SHADOW_NEAR: f32 = 0.05;
+//forward and up vectors for each cube face, rendered with a mirrored projection so the
+//faces line up with how cube maps are sampled
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowSettings {
+    pub map_size: u32,
+    pub cascade_count: u32,
+    //0.0 splits the cascades uniformly, 1.0 splits them logarithmically
+    pub cascade_split_lambda: f32,
+    //how far from the camera the directional light still casts shadows
+    pub shadow_distance: f32,
+    //how far behind a cascade casters are still rendered into it
+    pub caster_distance: f32,
+    pub pcf_radius: u32,
+    pub depth_bias_constant: i32,
+    pub depth_bias_slope_scale: f32,
+    pub depth_bias_clamp: f32,
+    pub normal_bias: f32,
+    pub point_map_size: u32,
+    //how many point lights closest to the camera get a shadow cube each frame
+    pub max_shadowed_point_lights: u32,
+    //world space radius of the soft filtering disk for point light shadows
+    pub point_filter_radius: f32,
+    pub point_depth_bias: f32,
+}
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            map_size: 2048,
+            cascade_count: MAX_CASCADES as u32,
+            cascade_split_lambda: 0.75,
+            shadow_distance: 60.0,
+            caster_distance: 50.0,
+            pcf_radius: 1,
+            depth_bias_constant: 2,
+            depth_bias_slope_scale: 2.0,
+            depth_bias_clamp: 0.0,
+            normal_bias: 0.02,
+            point_map_size: 512,
+            max_shadowed_point_lights: 4,
+            point_filter_radius: 0.05,
+            point_depth_bias: 0.005,
+        }
+    }
+}
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    cascade_view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    spot_view_proj: [[[f32; 4]; 4]; MAX_SHADOWED_SPOT_LIGHTS],
+    //view space distance where each cascade ends
+    cascade_splits: [f32; MAX_CASCADES],
+    cascade_count: u32,
+    pcf_radius: u32,
+    normal_bias: f32,
+    texel_size: f32,
+    point_filter_radius: f32,
+    point_depth_bias: f32,
+    _padding: [u32; 2],
+}
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowViewUniform {
+    view_proj: [[f32; 4]; 4],
+    //xyz is the light position and w its range, only used by point light shadows
+    light_position: [f32; 4],
+}
+pub struct ShadowMaps {
+    settings: ShadowSettings,
+    texture: Texture,
+    layer_views: Vec<wgpu::TextureView>,
+    point_texture: CubeTexture,
+    point_face_views: Vec<wgpu::TextureView>,
+    uniform: ShadowUniform,
+    uniform_buffer: wgpu::Buffer,
+    view_buffer: wgpu::Buffer,
+    view_layout: wgpu::BindGroupLayout,
+    view_bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::RenderPipeline,
+    point_pipeline: wgpu::RenderPipeline,
+    //which spot shadow slot each spot light was given this frame
+    spot_slots: Vec<Option<u32>>,
+    //which shadow cube each point light was given this frame
+    point_slots: Vec<Option<u32>>,
+    point_views: Vec<ShadowViewUniform>,
+}
+impl ShadowMaps {
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings) -> Self {
+        let (texture, layer_views) = Self::create_texture(device, settings.map_size);
+        let (point_texture, point_face_views) = Self::create_point_texture(device, &settings);
+        let uniform = ShadowUniform {
+            cascade_view_proj: [Matrix4::identity().into(); MAX_CASCADES],
+            spot_view_proj: [Matrix4::identity().into(); MAX_SHADOWED_SPOT_LIGHTS],
+            cascade_splits: [0.0; MAX_CASCADES],
+            cascade_count: 0,
+            pcf_radius: settings.pcf_radius,
+            normal_bias: settings.normal_bias,
+            texel_size: 1.0 / settings.map_size as f32,
+            point_filter_radius: settings.point_filter_radius,
+            point_depth_bias: settings.point_depth_bias,
+            _padding: [0; 2],
+        };
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let view_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow View Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let (view_buffer, view_bind_groups) = Self::create_views(device, &view_layout, &settings);
+        let pipeline = Self::create_pipeline(device, &view_layout, &settings);
+        let point_pipeline = Self::create_point_pipeline(device, &view_layout);
+        Self {
+            settings,
+            texture,
+            layer_views,
+            point_texture,
+            point_face_views,
+            uniform,
+            uniform_buffer,
+            view_buffer,
+            view_layout,
+            view_bind_groups,
+            pipeline,
+            point_pipeline,
+            spot_slots: vec![],
+            point_slots: vec![],
+            point_views: vec![],
+        }
+    }
+    //one view for every 2d shadow layer followed by six for every shadow cube
+    fn create_views(
+        device: &wgpu::Device,
+        view_layout: &wgpu::BindGroupLayout,
+        settings: &ShadowSettings,
+    ) -> (wgpu::Buffer, Vec<wgpu::BindGroup>) {
+        let count = SHADOW_LAYERS + 6 * Self::point_cube_count(settings) as usize;
+        let view_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow View Buffer"),
+            size: VIEW_UNIFORM_STRIDE * count as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let view_bind_groups = (0..count)
+            .map(|i| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Shadow View Bind Group"),
+                    layout: view_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &view_buffer,
+                            offset: i as wgpu::BufferAddress * VIEW_UNIFORM_STRIDE,
+                            size: wgpu::BufferSize::new(
+                                std::mem::size_of::<ShadowViewUniform>() as wgpu::BufferAddress
+                            ),
+                        }),
+                    }],
+                })
+            })
+            .collect();
+        (view_buffer, view_bind_groups)
+    }
+    //the cube array keeps one cube even when no point light casts shadows
+    fn point_cube_count(settings: &ShadowSettings) -> u32 {
+        settings.max_shadowed_point_lights.max(1)
+    }
+    fn create_point_texture(
+        device: &wgpu::Device,
+        settings: &ShadowSettings,
+    ) -> (CubeTexture, Vec<wgpu::TextureView>) {
+        let texture = CubeTexture::create_depth_array(
+            device,
+            settings.point_map_size,
+            Self::point_cube_count(settings),
+            Some("Point Shadow Maps"),
+        );
+        let face_views = (0..texture.size().depth_or_array_layers)
+            .map(|layer| texture.create_face_view(layer))
+            .collect();
+        (texture, face_views)
+    }
+    fn create_texture(device: &wgpu::Device, map_size: u32) -> (Texture, Vec<wgpu::TextureView>) {
+        let mut texture = Texture::create_depth_texture_with_size(
+            device,
+            wgpu::Extent3d {
+                width: map_size,
+                height: map_size,
+                depth_or_array_layers: SHADOW_LAYERS as u32,
+            },
+            "Shadow Maps",
+        );
+        texture.view = texture.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Maps View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let layer_views = (0..SHADOW_LAYERS as u32)
+            .map(|layer| {
+                texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Map Layer View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        (texture, layer_views)
+    }
+    fn create_pipeline(
+        device: &wgpu::Device,
+        view_layout: &wgpu::BindGroupLayout,
+        settings: &ShadowSettings,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[view_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(shaders::descriptor("shadow.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            //depth only, nothing to shade
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: settings.depth_bias_constant,
+                    slope_scale: settings.depth_bias_slope_scale,
+                    clamp: settings.depth_bias_clamp,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+    fn create_point_pipeline(
+        device: &wgpu::Device,
+        view_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Point Shadow Pipeline Layout"),
+            bind_group_layouts: &[view_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(shaders::descriptor("shadow.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Point Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_point",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            //writes the linear distance to the light instead of the projected depth
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_point",
+                targets: &[],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                //the mirrored face projection flips the winding
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+    //recreates the shadow maps, the light bind group has to be rebuilt afterwards
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        let settings = ShadowSettings {
+            cascade_count: settings.cascade_count.min(MAX_CASCADES as u32),
+            ..settings
+        };
+        if settings.map_size != self.settings.map_size {
+            (self.texture, self.layer_views) = Self::create_texture(device, settings.map_size);
+        }
+        if settings.point_map_size != self.settings.point_map_size
+            || settings.max_shadowed_point_lights != self.settings.max_shadowed_point_lights
+        {
+            (self.point_texture, self.point_face_views) =
+                Self::create_point_texture(device, &settings);
+            (self.view_buffer, self.view_bind_groups) =
+                Self::create_views(device, &self.view_layout, &settings);
+        }
+        self.pipeline = Self::create_pipeline(device, &self.view_layout, &settings);
+        self.settings = settings;
+    }
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+    pub fn point_texture(&self) -> &CubeTexture {
+        &self.point_texture
+    }
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+    pub fn spot_shadow_index(&self, spot_light: usize) -> Option<u32> {
+        self.spot_slots.get(spot_light).copied().flatten()
+    }
+    pub fn point_shadow_index(&self, point_light: usize) -> Option<u32> {
+        self.point_slots.get(point_light).copied().flatten()
+    }
+    fn cascade_count(&self, lights: &Lights) -> usize {
+        match &lights.directional {
+            Some(light) if light.cast_shadows => self.settings.cascade_count as usize,
+            _ => 0,
+        }
+    }
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        projection: &Projection,
+        lights: &Lights,
+    ) {
+        let cascade_count = self.cascade_count(lights);
+        let near = projection.znear();
+        let far = projection.zfar().min(self.settings.shadow_distance);
+        let splits = cascade_splits(near, far, cascade_count, self.settings.cascade_split_lambda);
+        if let Some(light) = &lights.directional {
+            let inv_view = camera.calc_matrix().invert().unwrap();
+            let mut slice_near = near;
+            for (i, split) in splits.iter().take(cascade_count).enumerate() {
+                self.uniform.cascade_view_proj[i] = cascade_view_proj(
+                    inv_view,
+                    projection,
+                    slice_near,
+                    *split,
+                    light.direction,
+                    self.settings.map_size,
+                    self.settings.caster_distance,
+                )
+                .into();
+                slice_near = *split;
+            }
+        }
+        self.spot_slots.clear();
+        let mut next_slot = 0;
+        for light in &lights.spot_lights {
+            if !light.cast_shadows || next_slot >= MAX_SHADOWED_SPOT_LIGHTS {
+                self.spot_slots.push(None);
+                continue;
+            }
+            let direction = light.direction.normalize();
+            let view = Matrix4::look_to_rh(
+                Point3::from_vec(light.position),
+                direction,
+                up_for(direction),
+            );
+            let fovy = cgmath::Rad((light.outer_angle.0 * 2.0).min(3.0));
+            let proj = OPENGL_TO_WGPU_MATRIX * cgmath::perspective(fovy, 1.0, 0.1, light.range);
+            self.uniform.spot_view_proj[next_slot] = (proj * view).into();
+            self.spot_slots.push(Some(next_slot as u32));
+            next_slot += 1;
+        }
+        self.assign_point_shadows(camera, lights);
+        self.uniform.cascade_splits = splits;
+        self.uniform.cascade_count = cascade_count as u32;
+        self.uniform.pcf_radius = self.settings.pcf_radius;
+        self.uniform.normal_bias = self.settings.normal_bias;
+        self.uniform.texel_size = 1.0 / self.settings.map_size as f32;
+        self.uniform.point_filter_radius = self.settings.point_filter_radius;
+        self.uniform.point_depth_bias = self.settings.point_depth_bias;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+        let views = self
+            .uniform
+            .cascade_view_proj
+            .iter()
+            .chain(self.uniform.spot_view_proj.iter())
+            .map(|view_proj| ShadowViewUniform {
+                view_proj: *view_proj,
+                light_position: [0.0; 4],
+            })
+            .chain(self.point_views.iter().copied());
+        for (i, view) in views.enumerate() {
+            queue.write_buffer(
+                &self.view_buffer,
+                i as wgpu::BufferAddress * VIEW_UNIFORM_STRIDE,
+                bytemuck::cast_slice(&[view]),
+            );
+        }
+    }
+    //hands the shadow cube budget to the shadow casting point lights nearest the camera
+    fn assign_point_shadows(&mut self, camera: &Camera, lights: &Lights) {
+        let camera_position = camera.position.to_vec();
+        let mut candidates = lights
+            .point_lights
+            .iter()
+            .enumerate()
+            .filter(|(_, light)| light.cast_shadows)
+            .map(|(i, light)| (i, (light.position - camera_position).magnitude2()))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+        candidates.truncate(self.settings.max_shadowed_point_lights as usize);
+        self.point_slots = vec![None; lights.point_lights.len()];
+        self.point_views.clear();
+        for (slot, (index, _)) in candidates.into_iter().enumerate() {
+            self.point_slots[index] = Some(slot as u32);
+            let light = &lights.point_lights[index];
+            let proj = cgmath::Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0)
+                * OPENGL_TO_WGPU_MATRIX
+                * cgmath::perspective(cgmath::Deg(90.0), 1.0, POINT_SHADOW_NEAR, light.range);
+            for (forward, up) in CUBE_FACES {
+                let view = Matrix4::look_to_rh(
+                    Point3::from_vec(light.position),
+                    forward.into(),
+                    up.into(),
+                );
+                self.point_views.push(ShadowViewUniform {
+                    view_proj: (proj * view).into(),
+                    light_position: light.position.extend(light.range).into(),
+                });
+            }
+        }
+    }
+    //layers that need to be rendered this frame
+    fn active_layers(&self) -> impl Iterator<Item = usize> + '_ {
+        let spot_count = self.spot_slots.iter().flatten().count();
+        (0..self.uniform.cascade_count as usize).chain(MAX_CASCADES..MAX_CASCADES + spot_count)
+    }
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        instance_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+    ) {
+        for layer in self.active_layers() {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[layer],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            pass.draw_shadow_model_instanced(
+                model,
+                instances.clone(),
+                &self.view_bind_groups[layer],
+            );
+        }
+        for face in 0..self.point_views.len() {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Point Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.point_face_views[face],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.point_pipeline);
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            pass.draw_shadow_model_instanced(
+                model,
+                instances.clone(),
+                &self.view_bind_groups[SHADOW_LAYERS + face],
+            );
+        }
+    }
+}
+fn up_for(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    }
+}
+//mixes logarithmic and uniform splits, see "Parallel-Split Shadow Maps"
+fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> [f32; MAX_CASCADES] {
+    let mut splits = [far; MAX_CASCADES];
+    for (i, split) in splits.iter_mut().take(count).enumerate() {
+        let p = (i + 1) as f32 / count as f32;
+        let log = near * (far / near).powf(p);
+        let uniform = near + (far - near) * p;
+        *split = lambda * log + (1.0 - lambda) * uniform;
+    }
+    splits
+}
+fn cascade_view_proj(
+    inv_view: Matrix4<f32>,
+    projection: &Projection,
+    near: f32,
+    far: f32,
+    light_direction: Vector3<f32>,
+    map_size: u32,
+    caster_distance: f32,
+) -> Matrix4<f32> {
+    let tan_half_fovy = (projection.fovy().0 / 2.0).tan();
+    let corners = [near, far]
+        .into_iter()
+        .flat_map(|z| {
+            let y = z * tan_half_fovy;
+            let x = y * projection.aspect();
+            [(-x, -y), (x, -y), (-x, y), (x, y)]
+                .map(|(x, y)| inv_view.transform_point(Point3::new(x, y, -z)))
+        })
+        .collect::<Vec<_>>();
+    let center = Point3::centroid(&corners);
+    //a bounding sphere keeps the cascade size constant while the camera rotates
+    let radius = corners
+        .iter()
+        .map(|c| (c - center).magnitude())
+        .fold(0.0f32, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+    let direction = light_direction.normalize();
+    let view = Matrix4::look_at_rh(
+        center - direction * (radius + caster_distance),
+        center,
+        up_for(direction),
+    );
+    let proj = OPENGL_TO_WGPU_MATRIX
+        * cgmath::ortho(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            0.0,
+            radius * 2.0 + caster_distance,
+        );
+    let mut view_proj = proj * view;
+    //snap the cascade to whole texels so the shadow edges don't shimmer when moving
+    let half_size = map_size as f32 / 2.0;
+    let origin = view_proj * cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let texel_x = origin.x * half_size;
+    let texel_y = origin.y * half_size;
+    view_proj.w.x += (texel_x.round() - texel_x) / half_size;
+    view_proj.w.y += (texel_y.round() - texel_y) / half_size;
+    view_proj
+}
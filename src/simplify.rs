@@ -0,0 +1,234 @@
+//quadric error mesh simplification (Garland and Heckbert 1997) for the lower levels of detail.
+//only uses std, build.rs includes it to simplify the models while packing the assets
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+//the symmetric 4x4 error matrix of a set of planes, as its upper triangle
+type Quadric = [f64; 10];
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+//twice the triangle's area along its normal
+fn triangle_normal(a: [f64; 3], b: [f64; 3], c: [f64; 3]) -> [f64; 3] {
+    cross(sub(b, a), sub(c, a))
+}
+//the triangle's plane, weighted by its area so slivers count for little
+fn plane_quadric(a: [f64; 3], b: [f64; 3], c: [f64; 3]) -> Quadric {
+    let normal = triangle_normal(a, b, c);
+    let length = dot(normal, normal).sqrt();
+    if length <= f64::EPSILON {
+        return [0.0; 10];
+    }
+    let area = length * 0.5;
+    let [x, y, z] = normal.map(|n| n / length);
+    let w = -dot([x, y, z], a);
+    [
+        x * x,
+        x * y,
+        x * z,
+        x * w,
+        y * y,
+        y * z,
+        y * w,
+        z * z,
+        z * w,
+        w * w,
+    ]
+    .map(|value| value * area)
+}
+fn add(a: &mut Quadric, b: &Quadric) {
+    for (a, b) in a.iter_mut().zip(b) {
+        *a += b;
+    }
+}
+fn error(q: &Quadric, [x, y, z]: [f64; 3]) -> f64 {
+    q[0] * x * x
+        + 2.0 * q[1] * x * y
+        + 2.0 * q[2] * x * z
+        + 2.0 * q[3] * x
+        + q[4] * y * y
+        + 2.0 * q[5] * y * z
+        + 2.0 * q[6] * y
+        + q[7] * z * z
+        + 2.0 * q[8] * z
+        + q[9]
+}
+//moving vertex from onto vertex to, ordered cheapest first in the heap
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    //the vertices' versions when the cost was worked out, stale collapses are skipped
+    versions: (u32, u32),
+}
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+//collapses edges onto their existing vertices until the triangles are down to the target, so the
+//returned indices still index the given vertices. vertices on the mesh's border, and those sharing
+//a position with another vertex (uv and normal seams), are never moved so the mesh doesn't crack
+pub fn simplify(positions: &[[f32; 3]], indices: &[u32], target_triangles: usize) -> Vec<u32> {
+    let positions = positions
+        .iter()
+        .map(|p| p.map(f64::from))
+        .collect::<Vec<_>>();
+    let mut triangles = indices
+        .chunks_exact(3)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect::<Vec<_>>();
+    let mut live = vec![true; triangles.len()];
+    let mut live_count = triangles.len();
+    if live_count <= target_triangles {
+        return indices.to_vec();
+    }
+
+    let mut quadrics = vec![[0.0; 10]; positions.len()];
+    let mut vertex_triangles = vec![vec![]; positions.len()];
+    let mut edges = std::collections::HashMap::<(u32, u32), u32>::new();
+    for (t, triangle) in triangles.iter().enumerate() {
+        let [a, b, c] = triangle.map(|i| positions[i as usize]);
+        let quadric = plane_quadric(a, b, c);
+        for (i, &vertex) in triangle.iter().enumerate() {
+            add(&mut quadrics[vertex as usize], &quadric);
+            vertex_triangles[vertex as usize].push(t);
+            let next = triangle[(i + 1) % 3];
+            *edges
+                .entry((vertex.min(next), vertex.max(next)))
+                .or_default() += 1;
+        }
+    }
+    let mut locked = vec![false; positions.len()];
+    for (&(a, b), &count) in &edges {
+        if count == 1 {
+            locked[a as usize] = true;
+            locked[b as usize] = true;
+        }
+    }
+    let mut by_position = (0..positions.len() as u32).collect::<Vec<_>>();
+    by_position.sort_by(|&a, &b| {
+        let (a, b) = (positions[a as usize], positions[b as usize]);
+        a[0].total_cmp(&b[0])
+            .then(a[1].total_cmp(&b[1]))
+            .then(a[2].total_cmp(&b[2]))
+    });
+    for pair in by_position.windows(2) {
+        if positions[pair[0] as usize] == positions[pair[1] as usize] {
+            locked[pair[0] as usize] = true;
+            locked[pair[1] as usize] = true;
+        }
+    }
+
+    let mut versions = vec![0u32; positions.len()];
+    let mut heap = BinaryHeap::new();
+    let push = |heap: &mut BinaryHeap<Collapse>,
+                quadrics: &[Quadric],
+                versions: &[u32],
+                from: u32,
+                to: u32| {
+        if locked[from as usize] {
+            return;
+        }
+        let mut quadric = quadrics[from as usize];
+        add(&mut quadric, &quadrics[to as usize]);
+        heap.push(Collapse {
+            cost: error(&quadric, positions[to as usize]),
+            from,
+            to,
+            versions: (versions[from as usize], versions[to as usize]),
+        });
+    };
+    for &(a, b) in edges.keys() {
+        push(&mut heap, &quadrics, &versions, a, b);
+        push(&mut heap, &quadrics, &versions, b, a);
+    }
+
+    while live_count > target_triangles {
+        let Some(collapse) = heap.pop() else {
+            break;
+        };
+        let (from, to) = (collapse.from as usize, collapse.to as usize);
+        if collapse.versions != (versions[from], versions[to]) {
+            continue;
+        }
+        //rejects collapses that would flip a triangle over
+        let flips = vertex_triangles[from].iter().any(|&t| {
+            let triangle = triangles[t];
+            if !live[t] || triangle.contains(&collapse.to) {
+                return false;
+            }
+            let [a, b, c] = triangle.map(|i| positions[i as usize]);
+            let [na, nb, nc] =
+                triangle.map(|i| positions[if i == collapse.from { to } else { i as usize }]);
+            let (before, after) = (triangle_normal(a, b, c), triangle_normal(na, nb, nc));
+            //turning the face by more than 60 degrees counts too, small turns add up to flips
+            dot(before, after) <= 0.5 * (dot(before, before) * dot(after, after)).sqrt()
+        });
+        if flips {
+            continue;
+        }
+        for t in std::mem::take(&mut vertex_triangles[from]) {
+            if !live[t] {
+                continue;
+            }
+            if triangles[t].contains(&collapse.to) {
+                live[t] = false;
+                live_count -= 1;
+            } else {
+                for vertex in &mut triangles[t] {
+                    if *vertex == collapse.from {
+                        *vertex = collapse.to;
+                    }
+                }
+                vertex_triangles[to].push(t);
+            }
+        }
+        let quadric = quadrics[from];
+        add(&mut quadrics[to], &quadric);
+        //the collapsed vertex's entries are stale for good, and the edges around the kept one
+        //are costed again
+        versions[from] += 1;
+        versions[to] += 1;
+        vertex_triangles[to].retain(|&t| live[t]);
+        let mut neighbours = vertex_triangles[to]
+            .iter()
+            .flat_map(|&t| triangles[t])
+            .filter(|&vertex| vertex != collapse.to)
+            .collect::<Vec<_>>();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for neighbour in neighbours {
+            push(&mut heap, &quadrics, &versions, collapse.to, neighbour);
+            push(&mut heap, &quadrics, &versions, neighbour, collapse.to);
+        }
+    }
+    triangles
+        .iter()
+        .zip(live)
+        .filter(|(_, live)| *live)
+        .flat_map(|(triangle, _)| *triangle)
+        .collect()
+}
@@ -0,0 +1,456 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    antialiasing::halton,
+    model::{self, DrawModel, LodInstances, MaterialKind, Model, Vertex},
+    renderer::InstanceRaw,
+    shaders,
+    textures::Texture,
+};
+
+const KERNEL_SIZE: usize = 32;
+const AO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+//view space normals encoded into 0 to 1, which is also what the post effects read
+pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    //the radius of the sampled hemisphere, in world units
+    pub radius: f32,
+    //how far a surface has to be in front of a sample to occlude it, avoids self shadowing
+    pub bias: f32,
+    //the occlusion is raised to this power
+    pub intensity: f32,
+    //up to 32 samples per pixel
+    pub samples: u32,
+}
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.5,
+            samples: 16,
+        }
+    }
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    proj: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    sample_count: u32,
+}
+//renders view space normals and depth in a prepass, then the occlusion the main shader multiplies the ambient light by
+pub struct Ssao {
+    settings: SsaoSettings,
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    uniform_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    prepass_pipeline: wgpu::RenderPipeline,
+    ssao_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+    normals: Texture,
+    //the occlusion, blurred into the scratch texture and back
+    ambient_occlusion: Texture,
+    scratch: Texture,
+    //the ssao pass, then the blur of the occlusion and of the scratch texture
+    bind_groups: [wgpu::BindGroup; 3],
+}
+impl Ssao {
+    pub fn new(
+        device: &wgpu::Device,
+        material_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let settings = SsaoSettings::default();
+        let kernel = create_kernel();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSAO Buffer"),
+            contents: bytemuck::cast_slice(&[uniform(settings, kernel, Matrix4::identity())]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                texture_entry(2),
+                texture_entry(3),
+            ],
+        });
+        //the prepass only uses the material and camera groups of the main shader
+        let prepass_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Normal Prepass Pipeline Layout"),
+                bind_group_layouts: &[material_layout, camera_layout],
+                push_constant_ranges: &[],
+            });
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Normal Prepass Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::source("shader.wgsl")),
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Normal Prepass Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_normals",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: NORMAL_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(shaders::descriptor("ssao.wgsl"));
+        let create_pipeline = |entry_point: &str, label: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: AO_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let ssao_pipeline = create_pipeline("fs_ssao", "SSAO Pipeline");
+        let blur_horizontal_pipeline =
+            create_pipeline("fs_blur_horizontal", "SSAO Horizontal Blur Pipeline");
+        let blur_vertical_pipeline =
+            create_pipeline("fs_blur_vertical", "SSAO Vertical Blur Pipeline");
+        let (normals, ambient_occlusion, scratch, bind_groups) =
+            Self::create_targets(device, &layout, &uniform_buffer, depth_view, width, height);
+        Self {
+            settings,
+            kernel,
+            uniform_buffer,
+            layout,
+            prepass_pipeline,
+            ssao_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            normals,
+            ambient_occlusion,
+            scratch,
+            bind_groups,
+        }
+    }
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> (Texture, Texture, Texture, [wgpu::BindGroup; 3]) {
+        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        let create_texture = |label, format| {
+            Texture::create_2d_texture(
+                device,
+                Some(label),
+                width,
+                height,
+                format,
+                usage,
+                wgpu::FilterMode::Nearest,
+            )
+        };
+        let normals = create_texture("Prepass Normal Texture", NORMAL_FORMAT);
+        let ambient_occlusion = create_texture("Ambient Occlusion Texture", AO_FORMAT);
+        let scratch = create_texture("Ambient Occlusion Blur Texture", AO_FORMAT);
+        //the ssao pass doesn't read its source, it gets the scratch texture to fill the slot
+        let bind_groups = [&scratch, &ambient_occlusion, &scratch].map(|source| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("SSAO Bind Group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(depth_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&normals.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                ],
+            })
+        });
+        (normals, ambient_occlusion, scratch, bind_groups)
+    }
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        (
+            self.normals,
+            self.ambient_occlusion,
+            self.scratch,
+            self.bind_groups,
+        ) = Self::create_targets(
+            device,
+            &self.layout,
+            &self.uniform_buffer,
+            depth_view,
+            width,
+            height,
+        );
+    }
+    //the blurred occlusion, white while ssao is off
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.ambient_occlusion.view
+    }
+    //None while ssao is off, as the prepass doesn't run
+    pub fn normals_view(&self) -> Option<&wgpu::TextureView> {
+        self.settings.enabled.then_some(&self.normals.view)
+    }
+    pub fn settings(&self) -> SsaoSettings {
+        self.settings
+    }
+    pub fn set_settings(&mut self, settings: SsaoSettings) {
+        self.settings = settings;
+    }
+    //takes the projection the frame is drawn with, jitter included
+    pub fn update(&self, queue: &wgpu::Queue, proj: Matrix4<f32>) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform(self.settings, self.kernel, proj)]),
+        );
+    }
+    //draws the opaque standard materials' normals and depth, custom materials have their own vertex shaders and are skipped
+    pub fn render_prepass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        model: &Model,
+        batches: &[LodInstances],
+        camera_bind_group: &wgpu::BindGroup,
+        light_bind_group: &wgpu::BindGroup,
+    ) {
+        if !self.settings.enabled {
+            return;
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Normal Prepass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.normals.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.5,
+                        g: 0.5,
+                        b: 1.0,
+                        a: 1.0,
+                    }),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.prepass_pipeline);
+        for batch in batches {
+            pass.set_vertex_buffer(1, batch.instance_buffer);
+            for (index, mesh) in model.meshes.iter().enumerate() {
+                let material = &model.materials[mesh.material];
+                if material.is_transparent() || matches!(material.kind, MaterialKind::Custom(_)) {
+                    continue;
+                }
+                pass.draw_mesh_instances(
+                    index,
+                    mesh,
+                    batch.level,
+                    material,
+                    &batch.instances,
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+        }
+    }
+    //computes and blurs the occlusion, or clears it to white when ssao is off
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.settings.enabled {
+            self.draw(
+                encoder,
+                None,
+                &self.ambient_occlusion.view,
+                "SSAO Clear Pass",
+            );
+            return;
+        }
+        let passes = [
+            (
+                &self.ssao_pipeline,
+                &self.ambient_occlusion.view,
+                "SSAO Pass",
+            ),
+            (
+                &self.blur_horizontal_pipeline,
+                &self.scratch.view,
+                "SSAO Horizontal Blur Pass",
+            ),
+            (
+                &self.blur_vertical_pipeline,
+                &self.ambient_occlusion.view,
+                "SSAO Vertical Blur Pass",
+            ),
+        ];
+        for ((pipeline, target, label), bind_group) in passes.into_iter().zip(&self.bind_groups) {
+            self.draw(encoder, Some((pipeline, bind_group)), target, label);
+        }
+    }
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: Option<(&wgpu::RenderPipeline, &wgpu::BindGroup)>,
+        target: &wgpu::TextureView,
+        label: &str,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        if let Some((pipeline, bind_group)) = pipeline {
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
+//points in the +z hemisphere from a halton sequence, more of them close to the centre
+fn create_kernel() -> [[f32; 4]; KERNEL_SIZE] {
+    let mut kernel = [[0.0; 4]; KERNEL_SIZE];
+    for (i, sample) in kernel.iter_mut().enumerate() {
+        let index = i as u32 + 1;
+        let direction = Vector3::new(
+            halton(index, 2) * 2.0 - 1.0,
+            halton(index, 3) * 2.0 - 1.0,
+            halton(index, 5).max(0.05),
+        )
+        .normalize();
+        let t = i as f32 / KERNEL_SIZE as f32;
+        let scale = 0.1 + 0.9 * t * t;
+        *sample = (direction * scale).extend(0.0).into();
+    }
+    kernel
+}
+fn uniform(
+    settings: SsaoSettings,
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    proj: Matrix4<f32>,
+) -> SsaoUniform {
+    SsaoUniform {
+        proj: proj.into(),
+        inv_proj: proj.invert().unwrap_or(Matrix4::identity()).into(),
+        kernel,
+        radius: settings.radius.max(1e-3),
+        bias: settings.bias,
+        intensity: settings.intensity.max(0.0),
+        sample_count: settings.samples.clamp(1, KERNEL_SIZE as u32),
+    }
+}
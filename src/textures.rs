@@ -1,97 +1,19 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
 use image::GenericImageView;
+
+use crate::{compressed::CompressedImage, shaders};
 #[derive(Debug)]
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    //material textures share their samplers through the TextureLoader
+    pub sampler: Rc<wgpu::Sampler>,
     pub size: wgpu::Extent3d,
 }
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
-    pub fn from_bytes(
-        device: &wgpu::Device,
-        bytes: &[u8],
-        label: &str,
-        queue: &wgpu::Queue,
-        is_normal_map: bool,
-    ) -> Result<Self, image::ImageError> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, &img, Some(label), queue, is_normal_map)
-    }
-    pub fn from_location(
-        device: &wgpu::Device,
-        location: &str,
-        label: &str,
-        queue: &wgpu::Queue,
-        is_normal_map: bool,
-    ) -> Result<Self, image::ImageError> {
-        let img = image::open(location)?;
-        Self::from_image(device, &img, Some(label), queue, is_normal_map)
-    }
-    pub fn from_image(
-        device: &wgpu::Device,
-        img: &image::DynamicImage,
-        label: Option<&str>,
-        queue: &wgpu::Queue,
-        is_normal_map: bool,
-    ) -> Result<Self, image::ImageError> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
-        let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-        let format = if is_normal_map {
-            wgpu::TextureFormat::Rgba8Unorm
-        } else {
-            wgpu::TextureFormat::Rgba8UnormSrgb
-        };
-
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label,
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("Diffuse Texture Sampler"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-        Ok(Self {
-            texture,
-            view,
-            sampler,
-            size,
-        })
-    }
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
@@ -102,6 +24,14 @@ impl Texture {
             height: config.height,
             depth_or_array_layers: 1,
         };
+        Self::create_depth_texture_with_size(device, size, label)
+    }
+    //a size with more than one layer creates a depth array, which is what the shadow maps use
+    pub fn create_depth_texture_with_size(
+        device: &wgpu::Device,
+        size: wgpu::Extent3d,
+        label: &str,
+    ) -> Self {
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
@@ -129,7 +59,7 @@ impl Texture {
         Self {
             texture,
             view,
-            sampler,
+            sampler: Rc::new(sampler),
             size,
         }
     }
@@ -165,7 +95,40 @@ impl Texture {
         Self {
             texture,
             view,
-            sampler,
+            sampler: Rc::new(sampler),
+            size,
+        }
+    }
+    //a render target with several samples per pixel, resolved into a single sampled texture
+    pub fn create_multisampled(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        Self {
+            texture,
+            view,
+            sampler: Rc::new(sampler),
             size,
         }
     }
@@ -250,6 +213,181 @@ impl CubeTexture {
             },
         }
     }
+    //a cube map from a KTX2 or DDS file, decoded on the CPU when the adapter lacks its block format
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        if !image.is_cube || image.layers != 6 {
+            anyhow::bail!(
+                "Expected a single cube map but found {} layers",
+                image.layers
+            );
+        }
+        let decompressed;
+        let image = if image.format.is_compressed() && !image.supported_by(device) {
+            decompressed = image.decompress()?;
+            &decompressed
+        } else {
+            image
+        };
+        let cube = Self::create_2d(
+            device,
+            image.width,
+            image.height,
+            image.format,
+            image.levels.len() as u32,
+            wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            wgpu::FilterMode::Nearest,
+            label,
+        );
+        image.upload(queue, &cube.texture);
+        Ok(cube)
+    }
+    //copies every face and mip back from the gpu, the texture needs COPY_SRC usage
+    pub fn read_back(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<CompressedImage> {
+        let format = self.texture.format();
+        if !self.texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            anyhow::bail!("Cube texture was created without COPY_SRC usage");
+        }
+        let Some(block_size) = format.block_copy_size(None) else {
+            anyhow::bail!("{:?} can't be copied to a buffer", format);
+        };
+        let mut image = CompressedImage {
+            format,
+            width: self.size.width,
+            height: self.size.height,
+            layers: self.size.depth_or_array_layers,
+            is_cube: true,
+            levels: Vec::new(),
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Cube Read Back Encoder"),
+        });
+        let mut buffers = Vec::new();
+        for level in 0..self.texture.mip_level_count() {
+            let (blocks_x, blocks_y) = image.level_blocks(level);
+            let row = blocks_x * block_size;
+            let padded_row = row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Cube Read Back Buffer"),
+                size: (padded_row * blocks_y * image.layers) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: level,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_row),
+                        rows_per_image: Some(blocks_y),
+                    },
+                },
+                image.level_size(level).physical_size(format),
+            );
+            buffers.push((buffer, row as usize, padded_row as usize));
+        }
+        queue.submit([encoder.finish()]);
+        for (buffer, row, padded_row) in buffers {
+            let slice = buffer.slice(..);
+            let (sender, receiver) = std::sync::mpsc::channel();
+            slice.map_async(wgpu::MapMode::Read, move |result| {
+                sender.send(result).ok();
+            });
+            device.poll(wgpu::Maintain::Wait);
+            receiver.recv()??;
+            //drop the row padding the copy needed
+            let level = slice
+                .get_mapped_range()
+                .chunks_exact(padded_row)
+                .flat_map(|padded| &padded[..row])
+                .copied()
+                .collect();
+            image.levels.push(level);
+            buffer.unmap();
+        }
+        Ok(image)
+    }
+    //bakes a generated cube, like the equirectangular conversion, to a KTX2 file that loads without it
+    pub fn save_ktx2(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<()> {
+        let bytes = self.read_back(device, queue)?.to_ktx2()?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+    //an array of depth cubes sampled with a comparison sampler, used for point light shadows
+    pub fn create_depth_array(
+        device: &wgpu::Device,
+        size: u32,
+        cube_count: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6 * cube_count.max(1),
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        Self {
+            texture,
+            sampler,
+            view,
+            size,
+        }
+    }
+    //a 2d view of a single face, layer is cube_index * 6 + face
+    pub fn create_face_view(&self, layer: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Cube Face View"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
     pub fn texture(&self) -> &wgpu::Texture {
         &self.texture
     }
@@ -263,3 +401,559 @@ impl CubeTexture {
         &self.size
     }
 }
+//how a material texture is sampled, set from the MTL map options
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+    pub address_mode: wgpu::AddressMode,
+    pub filter: wgpu::FilterMode,
+    //1 turns anisotropic filtering off, it only applies with linear filtering
+    pub anisotropy: u16,
+}
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self {
+            address_mode: wgpu::AddressMode::Repeat,
+            filter: wgpu::FilterMode::Linear,
+            anisotropy: 1,
+        }
+    }
+}
+//uploads material textures with a full mip chain and hands out shared samplers
+pub struct TextureLoader {
+    samplers: RefCell<HashMap<SamplerOptions, Rc<wgpu::Sampler>>>,
+    mip_layout: wgpu::BindGroupLayout,
+    mip_shader: wgpu::ShaderModule,
+    mip_sampler: wgpu::Sampler,
+    mip_pipelines: RefCell<HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>>,
+}
+impl TextureLoader {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let mip_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Mipmap Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let mip_shader = device.create_shader_module(shaders::descriptor("blit.wgsl"));
+        let mip_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            samplers: RefCell::new(HashMap::new()),
+            mip_layout,
+            mip_shader,
+            mip_sampler,
+            mip_pipelines: RefCell::new(HashMap::new()),
+        }
+    }
+    pub fn sampler(&self, device: &wgpu::Device, options: SamplerOptions) -> Rc<wgpu::Sampler> {
+        self.samplers
+            .borrow_mut()
+            .entry(options)
+            .or_insert_with(|| {
+                let anisotropy = match options.filter {
+                    wgpu::FilterMode::Linear => options.anisotropy.clamp(1, 16),
+                    wgpu::FilterMode::Nearest => 1,
+                };
+                Rc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+                    label: Some("Material Sampler"),
+                    address_mode_u: options.address_mode,
+                    address_mode_v: options.address_mode,
+                    address_mode_w: options.address_mode,
+                    mag_filter: options.filter,
+                    min_filter: options.filter,
+                    mipmap_filter: options.filter,
+                    anisotropy_clamp: anisotropy,
+                    ..Default::default()
+                }))
+            })
+            .clone()
+    }
+    pub fn from_image(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        is_normal_map: bool,
+        options: SamplerOptions,
+    ) -> Texture {
+        let rgba = img.to_rgba8();
+        let (width, height) = img.dimensions();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let format = if is_normal_map {
+            wgpu::TextureFormat::Rgba8Unorm
+        } else {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: size.max_mips(wgpu::TextureDimension::D2),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        self.generate_mipmaps(device, queue, &texture);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Texture {
+            texture,
+            view,
+            sampler: self.sampler(device, options),
+            size,
+        }
+    }
+    //a 1x1 texture used in place of a material map that wasn't provided
+    pub fn from_color(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        is_normal_map: bool,
+    ) -> Texture {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        self.from_image(
+            device,
+            queue,
+            &img,
+            Some(label),
+            is_normal_map,
+            SamplerOptions::default(),
+        )
+    }
+    //uploads a KTX2 or DDS image with its own mips, decoding on the CPU when the adapter lacks the format
+    pub fn from_compressed(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: Option<&str>,
+        options: SamplerOptions,
+    ) -> anyhow::Result<Texture> {
+        let decompressed;
+        let image = if image.format.is_compressed() && !image.supported_by(device) {
+            decompressed = image.decompress()?;
+            &decompressed
+        } else {
+            image
+        };
+        let size = image.level_size(0);
+        let format_features = image.format.guaranteed_format_features(device.features());
+        //single level files get their chain generated when the format can be rendered to
+        let generate_mips = image.levels.len() == 1
+            && format_features
+                .allowed_usages
+                .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            && format_features
+                .flags
+                .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE);
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        if generate_mips {
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: if generate_mips {
+                size.max_mips(wgpu::TextureDimension::D2)
+            } else {
+                image.levels.len() as u32
+            },
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: image.format,
+            usage,
+            view_formats: &[],
+        });
+        image.upload(queue, &texture);
+        if generate_mips {
+            self.generate_mipmaps(device, queue, &texture);
+        }
+        let dimension = if image.is_cube && image.layers == 6 {
+            wgpu::TextureViewDimension::Cube
+        } else if image.layers > 1 {
+            wgpu::TextureViewDimension::D2Array
+        } else {
+            wgpu::TextureViewDimension::D2
+        };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(dimension),
+            ..Default::default()
+        });
+        Ok(Texture {
+            texture,
+            view,
+            sampler: self.sampler(device, options),
+            size,
+        })
+    }
+    //fills every mip after the first by downsampling the level above, layer by layer
+    pub fn generate_mipmaps(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) {
+        if texture.mip_level_count() < 2 {
+            return;
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        let mip_view = |layer, mip| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Mip View"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        };
+        for layer in 0..texture.depth_or_array_layers() {
+            for mip in 1..texture.mip_level_count() {
+                let src = mip_view(layer, mip - 1);
+                let dst = mip_view(layer, mip);
+                self.blit(
+                    device,
+                    &mut encoder,
+                    &dst,
+                    texture.format(),
+                    &[(&src, None)],
+                );
+            }
+        }
+        queue.submit([encoder.finish()]);
+    }
+    //draws each source stretched over its viewport, or the whole target, in one pass
+    fn blit(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        format: wgpu::TextureFormat,
+        sources: &[(&wgpu::TextureView, Option<[f32; 4]>)],
+    ) {
+        let mut pipelines = self.mip_pipelines.borrow_mut();
+        let pipeline = pipelines.entry(format).or_insert_with(|| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Mipmap Pipeline Layout"),
+                bind_group_layouts: &[&self.mip_layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Mipmap Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &self.mip_shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.mip_shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        });
+        let bind_groups = sources
+            .iter()
+            .map(|(src, _)| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Mipmap Bind Group"),
+                    layout: &self.mip_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(src),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.mip_sampler),
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mipmap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        for ((_, viewport), bind_group) in sources.iter().zip(&bind_groups) {
+            if let Some([x, y, width, height]) = *viewport {
+                pass.set_viewport(x, y, width, height, 0.0, 1.0);
+            }
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
+//where a packed texture ended up, instances carry it to pick their layer and tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureRegion {
+    pub layer: u32,
+    pub uv_offset: [f32; 2],
+    pub uv_scale: [f32; 2],
+}
+impl Default for TextureRegion {
+    fn default() -> Self {
+        Self {
+            layer: 0,
+            uv_offset: [0.0; 2],
+            uv_scale: [1.0; 2],
+        }
+    }
+}
+//packs textures into the layers of one array texture so meshes using any of them share a bind group,
+//sources of another size are resampled to the layer size
+pub struct TextureArrayBuilder<'a> {
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    sources: Vec<&'a wgpu::Texture>,
+}
+impl<'a> TextureArrayBuilder<'a> {
+    pub fn new(width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            sources: Vec::new(),
+        }
+    }
+    pub fn push(&mut self, texture: &'a wgpu::Texture) -> TextureRegion {
+        self.sources.push(texture);
+        TextureRegion {
+            layer: self.sources.len() as u32 - 1,
+            ..Default::default()
+        }
+    }
+    pub fn build(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        loader: &TextureLoader,
+        label: Option<&str>,
+        options: SamplerOptions,
+    ) -> Texture {
+        let size = wgpu::Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: self.sources.len().max(1) as u32,
+        };
+        let mips = size.max_mips(wgpu::TextureDimension::D2);
+        let texture = create_packed_texture(device, label, size, self.format, mips);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Array Encoder"),
+        });
+        for (layer, source) in self.sources.iter().enumerate() {
+            let src = source_view(source);
+            let dst = layer_view(&texture, layer as u32);
+            loader.blit(device, &mut encoder, &dst, self.format, &[(&src, None)]);
+        }
+        queue.submit([encoder.finish()]);
+        loader.generate_mipmaps(device, queue, &texture);
+        packed_texture(texture, loader.sampler(device, options), size)
+    }
+}
+//packs square power of two tiles into a grid on each page of an array texture, so small textures
+//share layers as well as a bind group. tiles don't repeat, their uvs are remapped into the tile
+pub struct TextureAtlasBuilder<'a> {
+    page_size: u32,
+    tile_size: u32,
+    format: wgpu::TextureFormat,
+    sources: Vec<&'a wgpu::Texture>,
+}
+impl<'a> TextureAtlasBuilder<'a> {
+    pub fn new(page_size: u32, tile_size: u32, format: wgpu::TextureFormat) -> Self {
+        let tile_size = tile_size.next_power_of_two().min(page_size);
+        Self {
+            page_size: page_size.next_power_of_two(),
+            tile_size,
+            format,
+            sources: Vec::new(),
+        }
+    }
+    fn tiles_per_row(&self) -> u32 {
+        self.page_size / self.tile_size
+    }
+    pub fn push(&mut self, texture: &'a wgpu::Texture) -> TextureRegion {
+        let index = self.sources.len() as u32;
+        self.sources.push(texture);
+        let per_row = self.tiles_per_row();
+        let (layer, tile) = (index / (per_row * per_row), index % (per_row * per_row));
+        let scale = self.tile_size as f32 / self.page_size as f32;
+        TextureRegion {
+            layer,
+            uv_offset: [
+                (tile % per_row) as f32 * scale,
+                (tile / per_row) as f32 * scale,
+            ],
+            uv_scale: [scale; 2],
+        }
+    }
+    pub fn build(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        loader: &TextureLoader,
+        label: Option<&str>,
+        options: SamplerOptions,
+    ) -> Texture {
+        let per_row = self.tiles_per_row();
+        let per_page = (per_row * per_row) as usize;
+        let size = wgpu::Extent3d {
+            width: self.page_size,
+            height: self.page_size,
+            depth_or_array_layers: self.sources.len().div_ceil(per_page).max(1) as u32,
+        };
+        //stopping the chain at one texel per tile keeps the mips from bleeding between tiles
+        let page_mips = size.max_mips(wgpu::TextureDimension::D2);
+        let mips = page_mips - (page_mips - 1).min(per_row.trailing_zeros());
+        let texture = create_packed_texture(device, label, size, self.format, mips);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Atlas Encoder"),
+        });
+        for (page, sources) in self.sources.chunks(per_page).enumerate() {
+            let views = sources
+                .iter()
+                .map(|source| source_view(source))
+                .collect::<Vec<_>>();
+            let tiles = views
+                .iter()
+                .enumerate()
+                .map(|(tile, view)| {
+                    let tile = tile as u32;
+                    let viewport = [
+                        ((tile % per_row) * self.tile_size) as f32,
+                        ((tile / per_row) * self.tile_size) as f32,
+                        self.tile_size as f32,
+                        self.tile_size as f32,
+                    ];
+                    (view, Some(viewport))
+                })
+                .collect::<Vec<_>>();
+            let dst = layer_view(&texture, page as u32);
+            loader.blit(device, &mut encoder, &dst, self.format, &tiles);
+        }
+        queue.submit([encoder.finish()]);
+        loader.generate_mipmaps(device, queue, &texture);
+        packed_texture(texture, loader.sampler(device, options), size)
+    }
+}
+fn create_packed_texture(
+    device: &wgpu::Device,
+    label: Option<&str>,
+    size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+    mip_level_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label,
+        size,
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    })
+}
+//the first layer of a source, whatever view the texture was created with
+fn source_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        array_layer_count: Some(1),
+        ..Default::default()
+    })
+}
+fn layer_view(texture: &wgpu::Texture, layer: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        mip_level_count: Some(1),
+        base_array_layer: layer,
+        array_layer_count: Some(1),
+        ..Default::default()
+    })
+}
+fn packed_texture(
+    texture: wgpu::Texture,
+    sampler: Rc<wgpu::Sampler>,
+    size: wgpu::Extent3d,
+) -> Texture {
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+    Texture {
+        texture,
+        view,
+        sampler,
+        size,
+    }
+}
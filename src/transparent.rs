@@ -0,0 +1,507 @@
+use std::ops::Range;
+
+use cgmath::{Matrix4, Vector4};
+
+use crate::{
+    material::MaterialLibrary,
+    model::{self, DrawModel, MaterialKind, Model, Vertex},
+    renderer::InstanceRaw,
+    shaders,
+    textures::{self, Texture},
+};
+
+const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransparencyMode {
+    //draws sorted back to front by view depth
+    Sorted,
+    //weighted blended order independent transparency, custom materials are still sorted
+    WeightedBlended,
+}
+impl TransparencyMode {
+    pub const ALL: [TransparencyMode; 2] =
+        [TransparencyMode::Sorted, TransparencyMode::WeightedBlended];
+    pub fn name(self) -> &'static str {
+        match self {
+            TransparencyMode::Sorted => "sorted",
+            TransparencyMode::WeightedBlended => "weighted blended",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+struct TransparentDraw {
+    mesh: usize,
+    instances: Range<u32>,
+}
+//the pass for blended materials, drawn after the opaque geometry with depth writes off
+pub struct TransparentPass {
+    mode: TransparencyMode,
+    sorted_pipeline: wgpu::RenderPipeline,
+    oit_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    composite_layout: wgpu::BindGroupLayout,
+    composite_bind_group: wgpu::BindGroup,
+    sample_count: u32,
+    //the single sampled targets the composite reads, multisampled ones resolve into them
+    accum: Texture,
+    revealage: Texture,
+    multisampled: Option<(Texture, Texture)>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    draws: Vec<TransparentDraw>,
+}
+impl TransparentPass {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Transparent Shader"),
+            source: wgpu::ShaderSource::Wgsl(shaders::source("shader.wgsl")),
+        });
+        let sorted_pipeline = Self::create_pipeline(
+            device,
+            layout,
+            &shader,
+            "fs_transparent",
+            &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            sample_count,
+            "Sorted Transparent Pipeline",
+        );
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        //revealage is the product of (1 - alpha) over every surface
+        let revealage = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let oit_pipeline = Self::create_pipeline(
+            device,
+            layout,
+            &shader,
+            "fs_oit",
+            &[
+                Some(wgpu::ColorTargetState {
+                    format: ACCUM_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: additive,
+                        alpha: additive,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    format: REVEALAGE_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: revealage,
+                        alpha: revealage,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+            ],
+            sample_count,
+            "OIT Accumulation Pipeline",
+        );
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let composite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("OIT Composite Bind Group Layout"),
+            entries: &[texture_entry(0), texture_entry(1)],
+        });
+        let composite_pipeline = {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("OIT Composite Pipeline Layout"),
+                bind_group_layouts: &[&composite_layout],
+                push_constant_ranges: &[],
+            });
+            let shader = device.create_shader_module(shaders::descriptor("oit_composite.wgsl"));
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("OIT Composite Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+        };
+        let (accum, revealage, multisampled) =
+            Self::create_targets(device, sample_count, width, height);
+        let composite_bind_group =
+            Self::create_composite_bind_group(device, &composite_layout, &accum, &revealage);
+        let instance_capacity = 64;
+        let instance_buffer = Self::create_instance_buffer(device, instance_capacity);
+        Self {
+            mode: TransparencyMode::Sorted,
+            sorted_pipeline,
+            oit_pipeline,
+            composite_pipeline,
+            composite_layout,
+            composite_bind_group,
+            sample_count,
+            accum,
+            revealage,
+            multisampled,
+            instance_buffer,
+            instance_capacity,
+            draws: vec![],
+        }
+    }
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        fragment_entry: &str,
+        targets: &[Option<wgpu::ColorTargetState>],
+        sample_count: u32,
+        label: &str,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: fragment_entry,
+                targets,
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+    fn create_targets(
+        device: &wgpu::Device,
+        sample_count: u32,
+        width: u32,
+        height: u32,
+    ) -> (Texture, Texture, Option<(Texture, Texture)>) {
+        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        let accum = textures::Texture::create_2d_texture(
+            device,
+            Some("OIT Accumulation Texture"),
+            width,
+            height,
+            ACCUM_FORMAT,
+            usage,
+            wgpu::FilterMode::Nearest,
+        );
+        let revealage = textures::Texture::create_2d_texture(
+            device,
+            Some("OIT Revealage Texture"),
+            width,
+            height,
+            REVEALAGE_FORMAT,
+            usage,
+            wgpu::FilterMode::Nearest,
+        );
+        let multisampled = (sample_count > 1).then(|| {
+            (
+                Texture::create_multisampled(
+                    device,
+                    "Multisampled OIT Accumulation Texture",
+                    width,
+                    height,
+                    ACCUM_FORMAT,
+                    sample_count,
+                ),
+                Texture::create_multisampled(
+                    device,
+                    "Multisampled OIT Revealage Texture",
+                    width,
+                    height,
+                    REVEALAGE_FORMAT,
+                    sample_count,
+                ),
+            )
+        });
+        (accum, revealage, multisampled)
+    }
+    fn create_composite_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        accum: &Texture,
+        revealage: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("OIT Composite Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&revealage.view),
+                },
+            ],
+        })
+    }
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Transparent Instance Buffer"),
+            size: (std::mem::size_of::<InstanceRaw>() * capacity) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.accum, self.revealage, self.multisampled) =
+            Self::create_targets(device, self.sample_count, width, height);
+        self.composite_bind_group = Self::create_composite_bind_group(
+            device,
+            &self.composite_layout,
+            &self.accum,
+            &self.revealage,
+        );
+    }
+    pub fn mode(&self) -> TransparencyMode {
+        self.mode
+    }
+    pub fn set_mode(&mut self, mode: TransparencyMode) {
+        self.mode = mode;
+    }
+    //sorts every transparent mesh instance back to front and uploads the instances in that order
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: Matrix4<f32>,
+        model: &Model,
+        instances: &[InstanceRaw],
+    ) {
+        let mut sorted = model
+            .meshes
+            .iter()
+            .enumerate()
+            .filter(|(_, mesh)| model.materials[mesh.material].is_transparent())
+            .flat_map(|(mesh, _)| {
+                instances.iter().map(move |instance| {
+                    let position = view * instance.position().extend(1.0);
+                    (mesh, instance, position)
+                })
+            })
+            .collect::<Vec<(usize, &InstanceRaw, Vector4<f32>)>>();
+        //view space looks down -z so the furthest surface has the smallest z
+        sorted.sort_by(|a, b| a.2.z.total_cmp(&b.2.z));
+        self.draws.clear();
+        for (i, (mesh, _, _)) in sorted.iter().enumerate() {
+            let i = i as u32;
+            match self.draws.last_mut() {
+                Some(draw) if draw.mesh == *mesh => draw.instances.end = i + 1,
+                _ => self.draws.push(TransparentDraw {
+                    mesh: *mesh,
+                    instances: i..i + 1,
+                }),
+            }
+        }
+        if sorted.is_empty() {
+            return;
+        }
+        if sorted.len() > self.instance_capacity {
+            self.instance_capacity = sorted.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+        let data = sorted.iter().map(|(_, i, _)| **i).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&data));
+    }
+    //whether a draw goes through the accumulation pass instead of being sorted
+    fn is_order_independent(&self, model: &Model, draw: &TransparentDraw) -> bool {
+        let material = &model.materials[model.meshes[draw.mesh].material];
+        self.mode == TransparencyMode::WeightedBlended
+            && matches!(material.kind, MaterialKind::Standard(_))
+    }
+    //accumulates the order independent draws and composites them over the target
+    pub fn render_order_independent(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        model: &Model,
+        camera_bind_group: &wgpu::BindGroup,
+        light_bind_group: &wgpu::BindGroup,
+        environment_bind_group: &wgpu::BindGroup,
+    ) {
+        if !self
+            .draws
+            .iter()
+            .any(|draw| self.is_order_independent(model, draw))
+        {
+            return;
+        }
+        let (accum, accum_resolve, revealage, revealage_resolve) = match &self.multisampled {
+            Some((accum, revealage)) => (
+                &accum.view,
+                Some(&self.accum.view),
+                &revealage.view,
+                Some(&self.revealage.view),
+            ),
+            None => (&self.accum.view, None, &self.revealage.view, None),
+        };
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("OIT Accumulation Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: accum,
+                        resolve_target: accum_resolve,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: revealage,
+                        resolve_target: revealage_resolve,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.oit_pipeline);
+            pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            pass.set_bind_group(3, environment_bind_group, &[]);
+            for draw in &self.draws {
+                if !self.is_order_independent(model, draw) {
+                    continue;
+                }
+                let mesh = &model.meshes[draw.mesh];
+                pass.draw_mesh_instanced(
+                    mesh,
+                    &model.materials[mesh.material],
+                    draw.instances.clone(),
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.composite_pipeline);
+        pass.set_bind_group(0, &self.composite_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+    //draws the sorted transparent meshes into a pass that already has group 3 set
+    pub fn render_sorted<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        materials: &'a MaterialLibrary,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let vertex_layouts = [model::ModelVertex::desc(), InstanceRaw::desc()];
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for draw in &self.draws {
+            if self.is_order_independent(model, draw) {
+                continue;
+            }
+            let mesh = &model.meshes[draw.mesh];
+            let material = &model.materials[mesh.material];
+            let pipeline = match &material.kind {
+                MaterialKind::Custom(custom) => materials
+                    .pipeline(custom, &vertex_layouts)
+                    .unwrap_or(&self.sorted_pipeline),
+                MaterialKind::Standard(_) => &self.sorted_pipeline,
+            };
+            render_pass.set_pipeline(pipeline);
+            render_pass.draw_mesh_instanced(
+                mesh,
+                material,
+                draw.instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+}
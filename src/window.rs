@@ -1,6 +1,13 @@
 use std::{rc::Rc, sync::Arc};
 
-use crate::{renderer::Renderer, Plugin};
+use crate::{
+    exposure::AutoExposureSettings,
+    hdr::ToneMappingSettings,
+    renderer::{RenderPath, Renderer},
+    scripting::Scripting,
+    ssao::SsaoSettings,
+    Plugin,
+};
 use winit::{
     event::{DeviceEvent, ElementState, Event, WindowEvent},
     event_loop::EventLoop,
@@ -16,9 +23,14 @@ pub struct Engine<'a> {
     event_loop: EventLoop<()>,
     plugins: Vec<Box<dyn Plugin>>,
     window: Arc<winit::window::Window>,
+    scripting: Scripting,
 }
 impl<'a> Engine<'a> {
     pub async fn new() -> Engine<'a> {
+        Self::with_render_path(RenderPath::default()).await
+    }
+    //the render path can't be changed once the renderer exists
+    pub async fn with_render_path(render_path: RenderPath) -> Engine<'a> {
         let event_loop = EventLoop::new().unwrap();
         let window = WindowBuilder::new()
             .with_title("Game")
@@ -34,16 +46,21 @@ impl<'a> Engine<'a> {
         #[cfg(not(target_os = "macos"))]
         window.set_cursor_grab(CursorGrabMode::Confined).unwrap();
         let window = Arc::new(window);
-        let renderer = Renderer::new(window.clone()).await;
+        let renderer = Renderer::new(window.clone(), render_path).await;
         let plugins = vec![];
+        let scripting = Scripting::new().expect("Failed to create the lua state");
         Engine {
             renderer,
             event_loop,
             plugins,
             window: window,
+            scripting,
         }
         //depending on the platform, use vulkan,opengl or metal
     }
+    pub fn bake_sky(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        self.renderer.bake_sky(path)
+    }
     //TODO: plugins
     pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) {
         self.plugins.push(plugin);
@@ -51,8 +68,19 @@ impl<'a> Engine<'a> {
     pub fn run(mut self) {
         for plugin in self.plugins.iter() {
             plugin.init();
+            for effect in plugin.post_effects() {
+                if let Err(e) = self.renderer.add_post_effect(effect) {
+                    eprintln!("{e}");
+                }
+            }
+        }
+        if let Err(e) = self.scripting.load_main() {
+            eprintln!("{e}");
         }
 
+        //errors repeat every frame until fixed, so only print them when they change
+        let mut update_error = None;
+        let mut script_error = None;
         self.event_loop
             .run(move |event, elwt| {
                 let time_now = std::time::Instant::now();
@@ -111,6 +139,62 @@ impl<'a> Engine<'a> {
                                                 crate::camera::CameraMovement::Crouch,
                                             );
                                         }
+                                        Key::Named(winit::keyboard::NamedKey::F3) => {
+                                            self.renderer.toggle_debug_overlay();
+                                        }
+                                        //tone mapping keys only work while the overlay shows them
+                                        Key::Character("t") if self.renderer.debug_overlay() => {
+                                            let tone_mapping = self.renderer.tone_mapping();
+                                            self.renderer.set_tone_mapping(ToneMappingSettings {
+                                                operator: tone_mapping.operator.next(),
+                                                ..tone_mapping
+                                            });
+                                        }
+                                        Key::Character("e") if self.renderer.debug_overlay() => {
+                                            let auto_exposure = self.renderer.auto_exposure();
+                                            self.renderer.set_auto_exposure(AutoExposureSettings {
+                                                enabled: !auto_exposure.enabled,
+                                                ..auto_exposure
+                                            });
+                                        }
+                                        Key::Character("o") if self.renderer.debug_overlay() => {
+                                            let ssao = self.renderer.ssao();
+                                            self.renderer.set_ssao(SsaoSettings {
+                                                enabled: !ssao.enabled,
+                                                ..ssao
+                                            });
+                                        }
+                                        Key::Character("c") if self.renderer.debug_overlay() => {
+                                            self.renderer.set_culling_mode(
+                                                self.renderer.culling_mode().next(),
+                                            );
+                                        }
+                                        Key::Character("b") if self.renderer.debug_overlay() => {
+                                            self.renderer.set_transparency_mode(
+                                                self.renderer.transparency_mode().next(),
+                                            );
+                                        }
+                                        Key::Character("v") if self.renderer.debug_overlay() => {
+                                            self.renderer
+                                                .set_debug_view(self.renderer.debug_view().next());
+                                        }
+                                        //skips the msaa counts the adapter doesn't support
+                                        Key::Character("m") if self.renderer.debug_overlay() => {
+                                            let mut mode = self.renderer.anti_aliasing().next();
+                                            while self.renderer.set_anti_aliasing(mode).is_err() {
+                                                mode = mode.next();
+                                            }
+                                        }
+                                        Key::Character(key @ ("-" | "="))
+                                            if self.renderer.debug_overlay() =>
+                                        {
+                                            let tone_mapping = self.renderer.tone_mapping();
+                                            let step = if key == "-" { -0.25 } else { 0.25 };
+                                            self.renderer.set_tone_mapping(ToneMappingSettings {
+                                                exposure: tone_mapping.exposure + step,
+                                                ..tone_mapping
+                                            });
+                                        }
                                         _ => {}
                                     }
                                 }
@@ -137,7 +221,12 @@ impl<'a> Engine<'a> {
                     _ => {}
                 }
                 //spawn code below in a different thread
-                self.renderer.update(time_now.elapsed()).unwrap();
+                report_changed(&mut update_error, self.renderer.update(time_now.elapsed()));
+                report_changed(
+                    &mut script_error,
+                    self.scripting
+                        .update(&mut self.renderer, time_now.elapsed()),
+                );
                 self.renderer.render();
 
                 let avg_fps: f64 = 1.0 / (time_now.elapsed().as_secs_f64());
@@ -146,3 +235,14 @@ impl<'a> Engine<'a> {
         //cleanup
     }
 }
+//prints the error if it differs from the last one and forgets it once the call succeeds
+fn report_changed<E: std::fmt::Display>(last: &mut Option<String>, result: Result<(), E>) {
+    let message = result.err().map(|e| e.to_string());
+    if let Some(message) = message
+        .as_ref()
+        .filter(|&message| Some(message) != last.as_ref())
+    {
+        eprintln!("{message}");
+    }
+    *last = message;
+}